@@ -1,67 +1,276 @@
-use crate::{
-  list::Category,
-  sandbox::{
-    sandbox::{DeviceAccess, NetworkMode},
-    user_mapping::UserMapping,
-    wine::{SyncMode, UpscaleMode},
-  },
+use crate::{list::Category, profile::Profile};
+use clap::{ArgAction, Args, Parser, Subcommand};
+use raptor_cage::sandbox::{
+  dbus_proxy::{DbusMode, DbusRule},
+  explain::OutputFormat,
+  gamescope::Gamescope,
+  gpu::GpuSelection,
+  hidraw::HidrawSelection,
+  mount::{MountConfig, MountMapping},
+  nested_x11::X11Mode,
+  runtime_dir::RuntimeSocket,
+  sandbox::{AudioMode, DeviceAccess, DisplayServer, NetworkMode},
+  seccomp::SeccompLevel,
+  user_mapping::UserMapping,
+  wait::WaitMode,
+  wine::{SyncMode, UpscaleMode},
+  xauth::X11Cookie,
 };
-use clap::{ArgAction, Parser};
 use std::path::PathBuf;
 
+/// Options shared by every command that configures a sandbox. Parameters that are not passed keep
+/// their value unset, so they can be merged on top of a profile.
+#[derive(Debug, Args)]
+pub struct RunArgs {
+  /// Environment variable overrides.
+  #[arg(short = 'e', long = "setenv", value_name="KEY=VALUE", action = ArgAction::Append)]
+  environment: Vec<String>,
+  /// Additional mount points.
+  #[arg(short = 'v', long = "volume", value_name="PATH", action = ArgAction::Append, value_parser)]
+  volumes: Vec<MountMapping>,
+  /// Disable namespace isolation.
+  #[arg(long, default_value = "false")]
+  no_namespace_isolation: bool,
+  /// Use specific user and group id [default: random].
+  #[arg(long, value_name = "UID:GID", value_parser)]
+  user_mapping: Option<UserMapping>,
+  /// Configure network access [default: no_access].
+  #[arg(long, value_name = "MODE", value_parser)]
+  network_mode: Option<NetworkMode>,
+  /// Sandbox device access, either all or a comma separated list of device classes (input,
+  /// uinput, gpu, hidraw, sound, video, ntsync), presets (minimal, none) and /dev paths
+  /// [default: minimal].
+  #[arg(long, alias = "device", value_name = "ACCESS", value_parser)]
+  device_access: Option<DeviceAccess>,
+  /// One of none, auto, integrated, dedicated or a PCI address e.g., 0000:01:00.0, see
+  /// `rcage list --category gpus` [default: none].
+  #[arg(long, value_name = "GPU", value_parser)]
+  gpu: Option<GpuSelection>,
+  /// Comma separated list of USB IDs (VID:PID) of the hidraw devices to expose, or
+  /// known-controllers e.g., for DualSense or Switch Pro controllers [default: none].
+  #[arg(long, value_name = "IDS", value_parser)]
+  hidraw: Option<HidrawSelection>,
+  /// One of none, pipewire, pulse or all [default: all].
+  #[arg(long, value_name = "MODE", value_parser)]
+  audio: Option<AudioMode>,
+  /// One of x11, wayland or auto [default: auto].
+  #[arg(long, value_name = "SERVER", value_parser)]
+  display_server: Option<DisplayServer>,
+  /// One of host or nested[:WxH], nested runs a private X server [default: host].
+  #[arg(long, value_name = "MODE", value_parser)]
+  x11: Option<X11Mode>,
+  /// One of session or untrusted, the cookie used with the host X server [default: session].
+  #[arg(long, value_name = "COOKIE", value_parser)]
+  x11_cookie: Option<X11Cookie>,
+  /// One of none, filtered or full, for both the session and system buses [default: full].
+  #[arg(long, value_name = "MODE", value_parser)]
+  dbus: Option<DbusMode>,
+  /// Allow a bus name with --dbus=filtered, looks like [system:]see|talk|own:NAME e.g.,
+  /// talk:org.freedesktop.Notifications.
+  #[arg(long, value_name = "RULE", action = ArgAction::Append, value_parser)]
+  dbus_allow: Vec<DbusRule>,
+  /// Expose an additional entry of XDG_RUNTIME_DIR e.g., gamemode or discord-ipc-0.
+  #[arg(long = "runtime-socket", value_name = "NAME", action = ArgAction::Append, value_parser)]
+  runtime_sockets: Vec<RuntimeSocket>,
+  /// One of off, default or strict, the syscalls denied inside the sandbox [default: default].
+  #[arg(long, value_name = "LEVEL", value_parser)]
+  seccomp: Option<SeccompLevel>,
+  /// Allow creating nested user namespaces inside the sandbox, only for launchers that need them.
+  #[arg(long, default_value = "false")]
+  allow_userns: bool,
+  /// Print additional troubleshooting information.
+  #[arg(long, default_value = "false")]
+  verbose: bool,
+  /// One of none, dlss, fsr:mode:stre or gamescope:filter:sharp [default: none].
+  #[arg(long, value_name = "MODE", value_parser)]
+  upscale_mode: Option<UpscaleMode>,
+  /// Configure Wine sync mode, one of none, auto, ntsync, fsync or esync [default: none].
+  #[arg(long, value_name = "MODE", value_parser)]
+  sync_mode: Option<SyncMode>,
+  /// Keep the sandbox alive after the app exits, one of app, wineserver or process:NAME:TIMEOUT
+  /// [default: app].
+  #[arg(long, value_name = "MODE", value_parser)]
+  wait: Option<WaitMode>,
+  /// Run the app under gamescope inside the sandbox, optionally with gamescope options e.g.,
+  /// --gamescope="-w 1280 -h 720 -W 2560 -H 1440". Requires the gpu device class.
+  #[arg(
+    long,
+    value_name = "ARGS",
+    num_args = 0..=1,
+    require_equals = true,
+    default_missing_value = "",
+    value_parser
+  )]
+  gamescope: Option<Gamescope>,
+  /// Path of the Wine runner.
+  #[arg(short, long = "runner", value_name = "PATH")]
+  runner_path: Option<PathBuf>,
+  /// Path of the Wine prefix.
+  #[arg(short, long = "prefix", value_name = "PATH")]
+  prefix_path: Option<PathBuf>,
+  /// Path that contains the application files.
+  #[arg(short = 'd', long = "appdir", value_name = "PATH", value_parser)]
+  app_dir: Option<MountConfig>,
+  /// Path of the executable file relative to appdir.
+  #[arg(short = 'b', long = "appbin", value_name = "BIN")]
+  app_bin: Option<String>,
+  /// Optional game arguments, need to be placed after a double dash.
+  app_args: Option<Vec<String>>,
+}
+
+impl From<RunArgs> for Profile {
+  /// Flags can only enable options, so boolean flags that were not passed are left unset instead of
+  /// overriding the value of a profile with `false`.
+  fn from(args: RunArgs) -> Self {
+    let environment = args
+      .environment
+      .iter()
+      .map(|item| {
+        let (key, val) = item.split_once('=').unwrap_or((item, ""));
+        (key.to_string(), val.to_string())
+      })
+      .collect();
+    Profile {
+      environment,
+      volumes: args.volumes,
+      namespace_isolation: args.no_namespace_isolation.then_some(false),
+      user_mapping: args.user_mapping,
+      network_mode: args.network_mode,
+      device_access: args.device_access,
+      gpu: args.gpu,
+      hidraw: args.hidraw,
+      audio: args.audio,
+      display_server: args.display_server,
+      x11: args.x11,
+      x11_cookie: args.x11_cookie,
+      dbus: args.dbus,
+      dbus_allow: args.dbus_allow,
+      runtime_sockets: args.runtime_sockets,
+      seccomp: args.seccomp,
+      allow_userns: args.allow_userns.then_some(true),
+      verbose: args.verbose.then_some(true),
+      upscale_mode: args.upscale_mode,
+      sync_mode: args.sync_mode,
+      wait: args.wait,
+      gamescope: args.gamescope,
+      runner: args.runner_path,
+      prefix: args.prefix_path,
+      app_dir: args.app_dir,
+      app_bin: args.app_bin,
+      app_args: args.app_args,
+      bottles_data_root: None,
+    }
+  }
+}
+
 #[derive(Debug, Parser)]
 #[command(version = env!("CARGO_PKG_VERSION"))]
 pub enum Commands {
   /// Run application sandboxed.
   #[command(arg_required_else_help = true)]
   Run {
-    /// Environment variable overrides.
-    #[arg(short = 'e', long = "setenv", value_name="KEY=VALUE", action = ArgAction::Append)]
-    environment: Vec<String>,
-    /// Additional mount points.
-    #[arg(short = 'v', long = "volume", value_name="PATH", action = ArgAction::Append)]
-    volumes: Vec<String>,
-    /// Disable namespace isolation.
-    #[arg(long, default_value = "false")]
-    no_namespace_isolation: bool,
-    /// Use specific user and group id.
-    #[arg(long, value_name = "UID:GID", default_value = "random", value_parser)]
-    user_mapping: UserMapping,
-    /// Configure network access.
-    #[arg(long, value_name = "MODE", default_value = "no_access", value_parser)]
-    network_mode: NetworkMode,
-    /// Sandbox device access.
-    #[arg(long, value_name = "ACCESS", default_value = "minimal", value_parser)]
-    device_access: DeviceAccess,
-    /// Print additional troubleshooting information.
+    /// Load options from a profile, parameters passed on the command line take precedence.
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+    /// Print the bwrap command line instead of running it.
     #[arg(long, default_value = "false")]
-    verbose: bool,
-    /// One of none, dlss, fsr:mode:stre.
-    #[arg(long, value_name = "MODE", default_value = "none", value_parser)]
-    upscale_mode: UpscaleMode,
-    /// Configure Wine sync mode.
-    #[arg(long, value_name = "MODE", default_value = "none", value_parser)]
-    sync_mode: SyncMode,
-    /// Path of the Wine runner.
-    #[arg(short, long = "runner", value_name = "PATH")]
-    runner_path: Option<PathBuf>,
-    /// Path of the Wine prefix.
-    #[arg(short, long = "prefix", value_name = "PATH")]
-    prefix_path: Option<PathBuf>,
-    /// Path that contains the application files.
-    #[arg(short = 'd', long = "appdir", value_name = "PATH")]
-    app_dir: Option<String>,
-    /// Path of the executable file relative to appdir.
-    #[arg(short = 'b', long = "appbin", value_name = "BIN")]
-    app_bin: Option<String>,
-    /// Optional game arguments, need to be placed after a double dash.
-    app_args: Option<Vec<String>>,
+    dry_run: bool,
+    /// Output format used by --dry-run, one of text or shell.
+    #[arg(
+      long,
+      value_name = "FORMAT",
+      default_value = "text",
+      requires = "dry_run",
+      value_parser
+    )]
+    format: OutputFormat,
+    #[command(flatten)]
+    options: Box<RunArgs>,
   },
-  /// List installed runners and prefixes.
+  /// Print the bwrap command line that would be used by run, grouped by purpose.
+  Explain {
+    /// Load options from a profile, parameters passed on the command line take precedence.
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+    /// One of text or shell, the latter prints a script that can be executed.
+    #[arg(long, value_name = "FORMAT", default_value = "text", value_parser)]
+    format: OutputFormat,
+    #[command(flatten)]
+    options: Box<RunArgs>,
+  },
+  /// List installed runners and prefixes, and the detected GPUs.
   List {
+    /// One of all, prefixes, runners or gpus.
     #[arg(long, value_name = "CATEGORY", default_value = "all", value_parser)]
     category: Category,
   },
+  /// List running sandboxes.
+  Ps {
+    /// Print the instances as JSON.
+    #[arg(long, default_value = "false")]
+    json: bool,
+  },
+  /// Stop a running sandbox and every process in it.
+  Kill {
+    /// ID or name of the sandbox, see `rcage ps`.
+    instance: String,
+    /// Seconds to wait for the sandbox to exit before using a more forceful method.
+    #[arg(long, value_name = "SECONDS", default_value = "5")]
+    timeout: u64,
+  },
+  /// Run a command inside a running sandbox e.g., winecfg or a trainer.
+  Exec {
+    /// ID or name of the sandbox, see `rcage ps`.
+    instance: String,
+    /// Command and its arguments, need to be placed after a double dash.
+    #[arg(last = true, required = true)]
+    command: Vec<String>,
+  },
+  /// Manage profiles.
+  #[command(subcommand)]
+  Profile(ProfileCommands),
+  /// Inspect the global configuration.
+  #[command(subcommand)]
+  Config(ConfigCommands),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommands {
+  /// Print the global config file, or the options that would be used by `rcage run`.
+  Show {
+    /// Print the merged options, and the layer each value comes from.
+    #[arg(long, default_value = "false")]
+    resolved: bool,
+    /// Include a profile in the resolved options.
+    #[arg(long, value_name = "NAME", requires = "resolved")]
+    profile: Option<String>,
+    #[command(flatten)]
+    options: Box<RunArgs>,
+  },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ProfileCommands {
+  /// Save the given run parameters as a new profile.
+  New {
+    name: String,
+    /// Replace the profile if it already exists.
+    #[arg(long, default_value = "false")]
+    force: bool,
+    #[command(flatten)]
+    options: Box<RunArgs>,
+  },
+  /// Print the contents of a profile.
+  Show { name: String },
+  /// List saved profiles.
+  List,
+  /// Open a profile with $EDITOR, changes are only saved if the profile is still valid.
+  Edit { name: String },
+  /// Delete a profile.
+  #[command(alias = "remove")]
+  Rm { name: String },
+  /// Copy an existing profile under a new name.
+  Clone { source: String, target: String },
 }
 
 #[derive(Debug, Parser)]
@@ -0,0 +1,314 @@
+use crate::profile::{self, Profile};
+use anyhow::Context;
+use raptor_cage::sandbox::{
+  bottles,
+  sandbox::SandboxConfig,
+  wait::WaitMode,
+  wine::{SyncMode, UpscaleMode},
+};
+use std::{
+  env, fmt, fs,
+  path::{Path, PathBuf},
+};
+
+/// Sources that contribute to the options used by `rcage run`, sorted from lowest to highest
+/// precedence i.e., a value set on the command line replaces the one set by a profile, which in
+/// turn replaces the one from the global config, which replaces the built-in default.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layer {
+  /// Built-in defaults, see `SandboxConfig::default()`.
+  Default,
+  /// Global config file, see `get_config_path`.
+  Global,
+  /// Profile selected with `--profile`.
+  Profile,
+  /// Parameters passed on the command line.
+  CommandLine,
+}
+
+impl fmt::Display for Layer {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let layer_str = match self {
+      Layer::Default => "default",
+      Layer::Global => "global config",
+      Layer::Profile => "profile",
+      Layer::CommandLine => "command line",
+    };
+    write!(f, "{}", layer_str)
+  }
+}
+
+/// Get the directory where raptor-cage stores its configuration, honoring `$XDG_CONFIG_HOME`.
+pub fn get_config_dir() -> anyhow::Result<PathBuf> {
+  let config_home = match env::var("XDG_CONFIG_HOME") {
+    Ok(value) if !value.is_empty() => PathBuf::from(value),
+    _ => {
+      let home_dir = env::var("HOME").context("Failed to retrieve $HOME variable")?;
+      Path::new(&home_dir).join(".config")
+    }
+  };
+  Ok(config_home.join(env!("CARGO_PKG_NAME")))
+}
+
+/// Get the path of the global config file, it uses the same format as profiles.
+pub fn get_config_path() -> anyhow::Result<PathBuf> {
+  Ok(get_config_dir()?.join("config.toml"))
+}
+
+/// Load the global config, a missing file is the same as an empty config.
+pub fn load_global() -> anyhow::Result<Profile> {
+  let path = get_config_path()?;
+  if !path.exists() {
+    return Ok(Profile::default());
+  }
+  Profile::from_file(&path)
+}
+
+/// Get the Bottles data root, taking into account the one set in the global config (if any).
+pub fn get_data_root() -> anyhow::Result<PathBuf> {
+  match load_global()?.bottles_data_root {
+    Some(data_root) => Ok(data_root),
+    None => bottles::get_data_root(),
+  }
+}
+
+/// Built-in defaults expressed as options, so they can be displayed along with the other layers.
+fn get_defaults() -> Profile {
+  let defaults = SandboxConfig::default();
+  Profile {
+    namespace_isolation: Some(defaults.namespace_isolation),
+    user_mapping: Some(defaults.user_mapping),
+    network_mode: Some(defaults.network_mode),
+    device_access: Some(defaults.device_access),
+    gpu: Some(defaults.gpu),
+    hidraw: Some(defaults.hidraw),
+    audio: Some(defaults.audio),
+    dbus: Some(defaults.dbus),
+    display_server: Some(defaults.display_server),
+    x11: Some(defaults.x11),
+    x11_cookie: Some(defaults.x11_cookie),
+    seccomp: Some(defaults.seccomp),
+    allow_userns: Some(defaults.allow_userns),
+    verbose: Some(defaults.verbose),
+    upscale_mode: Some(UpscaleMode::None),
+    sync_mode: Some(SyncMode::None),
+    wait: Some(WaitMode::App),
+    ..Default::default()
+  }
+}
+
+/// Load every layer in order of precedence (lowest first).
+pub fn get_layers(
+  profile_name: Option<&str>,
+  cli_options: Profile,
+) -> anyhow::Result<Vec<(Layer, Profile)>> {
+  let mut layers = vec![
+    (Layer::Default, get_defaults()),
+    (Layer::Global, load_global()?),
+  ];
+  if let Some(name) = profile_name {
+    layers.push((Layer::Profile, profile::load(name)?));
+  }
+  layers.push((Layer::CommandLine, cli_options));
+  Ok(layers)
+}
+
+/// Merge all the layers into the final options.
+pub fn resolve(profile_name: Option<&str>, cli_options: Profile) -> anyhow::Result<Profile> {
+  let layers = get_layers(profile_name, cli_options)?;
+  Ok(
+    layers
+      .into_iter()
+      .fold(Profile::default(), |merged, (_, options)| merged.merge(options)),
+  )
+}
+
+fn format_key(key: &str) -> String {
+  if !key.is_empty()
+    && key
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+  {
+    key.to_string()
+  } else {
+    toml::Value::String(key.to_string()).to_string()
+  }
+}
+
+/// Replace the value of `key`, or add it if it was not set by a previous layer.
+fn set_value(
+  values: &mut Vec<(String, toml::Value, Layer)>,
+  key: String,
+  value: toml::Value,
+  layer: Layer,
+) {
+  match values.iter_mut().find(|(k, _, _)| *k == key) {
+    Some(entry) => *entry = (key, value, layer),
+    None => values.push((key, value, layer)),
+  }
+}
+
+/// Format the merged options as TOML, annotating each value with the layer where it comes from.
+fn format_resolved(layers: &[(Layer, Profile)]) -> anyhow::Result<String> {
+  let mut values: Vec<(String, toml::Value, Layer)> = Vec::new();
+  let mut environment: Vec<(String, toml::Value, Layer)> = Vec::new();
+  // Items of list options are kept from every layer, see `Profile::merge`.
+  let mut lists: Vec<(&str, Vec<(toml::Value, Layer)>)> = vec![
+    ("dbus_allow", Vec::new()),
+    ("runtime_sockets", Vec::new()),
+    ("volumes", Vec::new()),
+  ];
+  for (layer, options) in layers {
+    let table = toml::Table::try_from(options).context("Failed to serialize options")?;
+    for (key, value) in table {
+      match (key.as_str(), value) {
+        ("environment", toml::Value::Table(variables)) => {
+          for (name, value) in variables {
+            set_value(&mut environment, name, value, *layer);
+          }
+        }
+        (_, value) => match (lists.iter_mut().find(|(name, _)| *name == key), value) {
+          (Some((_, list)), toml::Value::Array(items)) => {
+            list.extend(items.into_iter().map(|item| (item, *layer)));
+          }
+          (_, value) => set_value(&mut values, key, value, *layer),
+        },
+      }
+    }
+  }
+  values.sort_by(|a, b| a.0.cmp(&b.0));
+  let mut lines: Vec<String> = values
+    .iter()
+    .map(|(key, value, layer)| format!("{} = {} # {}", format_key(key), value, layer))
+    .collect();
+  lines.extend(environment.iter().map(|(name, value, layer)| {
+    format!("environment.{} = {} # {}", format_key(name), value, layer)
+  }));
+  for (name, items) in lists.iter().filter(|(_, items)| !items.is_empty()) {
+    lines.push(format!("{} = [", name));
+    lines.extend(
+      items
+        .iter()
+        .map(|(item, layer)| format!("  {}, # {}", item, layer)),
+    );
+    lines.push("]".into());
+  }
+  Ok(lines.join("\n"))
+}
+
+/// Print the global config file, or the options that `rcage run` would use when `resolved` is set.
+pub fn show(
+  resolved: bool,
+  profile_name: Option<&str>,
+  cli_options: Profile,
+) -> anyhow::Result<()> {
+  if resolved {
+    let layers = get_layers(profile_name, cli_options)?;
+    println!("{}", format_resolved(&layers)?);
+    return Ok(());
+  }
+  let path = get_config_path()?;
+  if !path.exists() {
+    println!("No global config found at {}", path.to_string_lossy());
+    return Ok(());
+  }
+  let content = fs::read_to_string(&path)
+    .with_context(|| format!("Failed to read config: {}", path.to_string_lossy()))?;
+  print!("{}", content);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_layer_precedence() {
+    let layers = vec![
+      (Layer::Default, get_defaults()),
+      (Layer::Global, toml::from_str(r#"sync_mode = "fsync""#).unwrap()),
+      (Layer::Profile, toml::from_str(r#"network_mode = "full""#).unwrap()),
+      (Layer::CommandLine, toml::from_str(r#"network_mode = "restricted""#).unwrap()),
+    ];
+    let merged = layers
+      .into_iter()
+      .fold(Profile::default(), |merged, (_, options)| merged.merge(options));
+    assert_eq!(merged.sync_mode, Some(SyncMode::Fsync));
+    assert_eq!(merged.namespace_isolation, Some(true));
+    assert_eq!(merged.network_mode.unwrap().to_string(), "restricted_access");
+  }
+
+  #[test]
+  fn test_format_resolved() {
+    let layers = vec![
+      (Layer::Default, get_defaults()),
+      (
+        Layer::Global,
+        toml::from_str(
+          r#"
+          sync_mode = "fsync"
+          volumes = ["/data:/data"]
+          runtime_sockets = ["gamemode"]
+          environment = { A = "1", B = "1" }
+          "#,
+        )
+        .unwrap(),
+      ),
+      (
+        Layer::Profile,
+        toml::from_str(
+          r#"
+          network_mode = "full"
+          environment = { B = "2" }
+          "#,
+        )
+        .unwrap(),
+      ),
+      (
+        Layer::CommandLine,
+        toml::from_str(
+          r#"
+          network_mode = "restricted"
+          volumes = ["/other:/other:rw"]
+          runtime_sockets = ["discord-ipc-0"]
+          "#,
+        )
+        .unwrap(),
+      ),
+    ];
+    let expected = r#"allow_userns = false # default
+audio = "all" # default
+dbus = "full" # default
+device_access = "minimal" # default
+display_server = "auto" # default
+gpu = "none" # default
+hidraw = "none" # default
+namespace_isolation = true # default
+network_mode = "restricted_access" # command line
+seccomp = "default" # default
+sync_mode = "fsync" # global config
+upscale_mode = "none" # default
+user_mapping = "random" # default
+verbose = false # default
+wait = "app" # default
+x11 = "host" # default
+x11_cookie = "session" # default
+environment.A = "1" # global config
+environment.B = "2" # profile
+runtime_sockets = [
+  "gamemode", # global config
+  "discord-ipc-0", # command line
+]
+volumes = [
+  "/data:/data", # global config
+  "/other:/other:rw", # command line
+]"#;
+    assert_eq!(format_resolved(&layers).unwrap(), expected);
+  }
+
+  #[test]
+  fn test_format_key() {
+    assert_eq!(format_key("MANGOHUD"), "MANGOHUD");
+    assert_eq!(format_key("A.B"), "\"A.B\"");
+  }
+}
@@ -0,0 +1,199 @@
+use raptor_cage::sandbox::{
+  control,
+  registry::{Instance, Registry},
+};
+use std::os::unix::process::ExitStatusExt;
+use std::{
+  path::Path,
+  process::ExitStatus,
+  thread,
+  time::{Duration, Instant},
+};
+
+/// Format a duration in seconds as e.g. `1h05m` or `42s`.
+fn format_uptime(seconds: u64) -> String {
+  let (hours, minutes) = (seconds / 3600, seconds % 3600 / 60);
+  match (hours, minutes) {
+    (0, 0) => format!("{}s", seconds),
+    (0, _) => format!("{}m{:02}s", minutes, seconds % 60),
+    _ => format!("{}h{:02}m", hours, minutes),
+  }
+}
+
+/// Runners and prefixes are usually under the Bottles data root, so their name is enough.
+fn format_path(path: Option<&Path>) -> String {
+  match path {
+    Some(path) => path
+      .file_name()
+      .unwrap_or(path.as_os_str())
+      .to_string_lossy()
+      .to_string(),
+    None => "-".into(),
+  }
+}
+
+fn format_table(instances: &[Instance], now: u64) -> String {
+  let mut rows: Vec<[String; 7]> = vec![[
+    "ID".into(),
+    "NAME".into(),
+    "PID".into(),
+    "UPTIME".into(),
+    "NETWORK".into(),
+    "RUNNER".into(),
+    "PREFIX".into(),
+  ]];
+  rows.extend(instances.iter().map(|instance| {
+    [
+      instance.id.clone(),
+      instance.name.clone(),
+      instance.child_pid.to_string(),
+      format_uptime(now.saturating_sub(instance.started_at)),
+      instance.network_mode.to_string(),
+      format_path(instance.runner.as_deref()),
+      format_path(instance.prefix.as_deref()),
+    ]
+  }));
+  let mut widths = [0; 7];
+  for row in &rows {
+    for (width, cell) in widths.iter_mut().zip(row) {
+      *width = (*width).max(cell.chars().count());
+    }
+  }
+  rows
+    .iter()
+    .map(|row| {
+      let cells: Vec<String> = row
+        .iter()
+        .zip(widths)
+        .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+        .collect();
+      cells.join("  ").trim_end().to_string()
+    })
+    .collect::<Vec<String>>()
+    .join("\n")
+}
+
+/// Print the running sandboxes, stale registry entries are removed along the way.
+pub fn ps(json: bool) -> anyhow::Result<()> {
+  let instances = Registry::from_env()?.list()?;
+  if json {
+    println!("{}", serde_json::to_string_pretty(&instances)?);
+  } else {
+    println!("{}", format_table(&instances, Instance::now()));
+  }
+  Ok(())
+}
+
+/// Wait until bwrap exits, returns `false` if it is still running after `timeout`.
+fn wait_for_exit(instance: &Instance, timeout: Duration) -> bool {
+  let deadline = Instant::now() + timeout;
+  while instance.is_running() {
+    if Instant::now() >= deadline {
+      return false;
+    }
+    thread::sleep(Duration::from_millis(100));
+  }
+  true
+}
+
+/// Stop a sandbox and every process in it, escalating from the most graceful method: first ask
+/// wineserver to stop the Wine processes, then send SIGTERM and finally SIGKILL, giving the sandbox
+/// `timeout` seconds to exit after each step.
+pub fn kill(target: &str, timeout: u64) -> anyhow::Result<()> {
+  let instance = Registry::from_env()?.find(target)?;
+  let timeout = Duration::from_secs(timeout);
+  if instance.runner.is_some() {
+    println!("Stopping Wine processes in {} ({})", instance.name, instance.id);
+    let command = ["wineserver".to_string(), "-k".to_string()];
+    match control::command_in(&instance, &command)?.status() {
+      Ok(status) if status.success() => (),
+      Ok(status) => println!("wineserver -k failed: {}", status),
+      Err(e) => println!("wineserver -k failed: {}", e),
+    }
+    if wait_for_exit(&instance, timeout) {
+      return Ok(());
+    }
+  }
+  println!("Sending SIGTERM to {} ({})", instance.name, instance.id);
+  control::signal(&instance, libc::SIGTERM)?;
+  if wait_for_exit(&instance, timeout) {
+    return Ok(());
+  }
+  println!("Sending SIGKILL to {} ({})", instance.name, instance.id);
+  control::signal(&instance, libc::SIGKILL)?;
+  // Killing bwrap also kills the sandbox (see --die-with-parent), even if a process was missed.
+  // SAFETY: kill has no memory safety requirements.
+  unsafe { libc::kill(instance.pid as libc::pid_t, libc::SIGKILL) };
+  if !wait_for_exit(&instance, timeout) {
+    anyhow::bail!("Sandbox {} ({}) is still running", instance.name, instance.id);
+  }
+  Ok(())
+}
+
+/// Exit code of a command, or 128 plus the signal that killed it, as shells report it.
+fn exit_code(status: ExitStatus) -> i32 {
+  status
+    .code()
+    .or_else(|| status.signal().map(|signal| 128 + signal))
+    .unwrap_or(1)
+}
+
+/// Run a command inside a running sandbox, sharing its namespaces (and therefore its wineserver),
+/// environment, working directory and seccomp filter. Returns the exit code of the command.
+pub fn exec(target: &str, command: &[String]) -> anyhow::Result<i32> {
+  let instance = Registry::from_env()?.find(target)?;
+  let status = control::command_in(&instance, command)?
+    .status()
+    .map_err(|e| anyhow::anyhow!("Could not spawn nsenter: {}", e))?;
+  Ok(exit_code(status))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use raptor_cage::sandbox::sandbox::NetworkMode;
+  use raptor_cage::sandbox::seccomp::SeccompLevel;
+
+  #[test]
+  fn test_format_uptime() {
+    let test_cases = vec![
+      (0, "0s"),
+      (42, "42s"),
+      (65, "1m05s"),
+      (3600, "1h00m"),
+      (7530, "2h05m"),
+    ];
+    for (seconds, expected) in test_cases {
+      assert_eq!(format_uptime(seconds), expected);
+    }
+  }
+
+  #[test]
+  fn test_exit_code() {
+    assert_eq!(exit_code(ExitStatus::from_raw(0)), 0);
+    assert_eq!(exit_code(ExitStatus::from_raw(3 << 8)), 3);
+    assert_eq!(exit_code(ExitStatus::from_raw(libc::SIGKILL)), 137);
+  }
+
+  #[test]
+  fn test_format_table() {
+    let instances = vec![Instance {
+      id: "0000abcd".into(),
+      name: "some_game".into(),
+      pid: 100,
+      child_pid: 101,
+      started_at: 1_000,
+      network_mode: NetworkMode::NoAccess,
+      seccomp: SeccompLevel::Default,
+      deny_userns: false,
+      runner: Some("/data/runners/soda-9.0-1".into()),
+      prefix: None,
+      environment: Default::default(),
+      working_dir: "/".into(),
+    }];
+    let expected = "\
+ID        NAME       PID  UPTIME  NETWORK    RUNNER      PREFIX
+0000abcd  some_game  101  1m40s   no_access  soda-9.0-1  -";
+    assert_eq!(format_table(&instances, 1_100), expected);
+  }
+}
@@ -1,85 +1,103 @@
-use crate::{
-  inhibitor,
-  sandbox::{
-    bwrap,
-    mount::{MountConfig, MountMapping},
-    sandbox::{DeviceAccess, LaunchConfig, LaunchParams, NetworkMode, RuntimeEnv, SandboxConfig},
-    user_mapping::UserMapping,
-    wine::{SyncMode, UpscaleMode},
-  },
+use crate::{config, inhibitor, profile::Profile};
+use raptor_cage::sandbox::{
+  Sandbox,
+  explain::OutputFormat,
+  sandbox::{LaunchConfig, LaunchParams, RuntimeEnv, SandboxConfig},
 };
-use std::{collections::HashMap, path::PathBuf, str::FromStr};
+use std::collections::HashMap;
 
-// TODO: deny "/" and other important dirs.
-fn parse_mappings(volumes: &[String]) -> anyhow::Result<Vec<MountMapping>> {
-  let mut mappings: Vec<MountMapping> = Vec::with_capacity(volumes.len());
-  for volume in volumes {
-    let mapping =
-      MountMapping::from_str(volume).map_err(|e| anyhow::anyhow!("Volume error: {}", e))?;
-    mappings.push(mapping);
-  }
-  Ok(mappings)
-}
-
-pub async fn run(
-  environment: &[String],
-  volumes: &[String],
-  no_namespace_isolation: bool,
-  user_mapping: UserMapping,
-  network_mode: NetworkMode,
-  device_access: DeviceAccess,
-  verbose: bool,
-  upscale_mode: UpscaleMode,
-  sync_mode: SyncMode,
-  runner_path: Option<PathBuf>,
-  prefix_path: Option<PathBuf>,
-  app_dir: Option<String>,
-  app_bin: Option<String>,
-  app_args: Option<Vec<String>>,
-) -> anyhow::Result<()> {
-  if runner_path.as_ref().xor(prefix_path.as_ref()).is_some() {
+/// Options from the command line are merged on top of the given profile (if any), see
+/// `config::Layer` for the full order of precedence.
+fn setup(profile_name: Option<&str>, cli_options: Profile) -> anyhow::Result<Sandbox> {
+  let options = config::resolve(profile_name, cli_options)?;
+  if options
+    .runner
+    .as_ref()
+    .xor(options.prefix.as_ref())
+    .is_some()
+  {
     anyhow::bail!("Either both runner and prefix paths are required, or neither");
   }
+  let defaults = SandboxConfig::default();
   let sandbox_config = SandboxConfig {
-    namespace_isolation: !no_namespace_isolation,
-    user_mapping,
-    network_mode,
-    device_access,
-    verbose,
+    namespace_isolation: options
+      .namespace_isolation
+      .unwrap_or(defaults.namespace_isolation),
+    user_mapping: options.user_mapping.unwrap_or(defaults.user_mapping),
+    network_mode: options.network_mode.unwrap_or(defaults.network_mode),
+    device_access: options.device_access.unwrap_or(defaults.device_access),
+    gpu: options.gpu.unwrap_or(defaults.gpu),
+    hidraw: options.hidraw.unwrap_or(defaults.hidraw),
+    audio: options.audio.unwrap_or(defaults.audio),
+    display_server: options.display_server.unwrap_or(defaults.display_server),
+    x11: options.x11.unwrap_or(defaults.x11),
+    x11_cookie: options.x11_cookie.unwrap_or(defaults.x11_cookie),
+    dbus: options.dbus.unwrap_or(defaults.dbus),
+    dbus_allow: options.dbus_allow,
+    runtime_sockets: options.runtime_sockets,
+    seccomp: options.seccomp.unwrap_or(defaults.seccomp),
+    allow_userns: options.allow_userns.unwrap_or(defaults.allow_userns),
+    verbose: options.verbose.unwrap_or(defaults.verbose),
   };
-  let launch_params = if let Some(app_dir) = app_dir {
-    let mount_config = MountConfig::from_str(&app_dir).map_err(|e| anyhow::anyhow!("{}", e))?;
+  let launch_params = if let Some(mount_config) = options.app_dir {
     Some(LaunchParams::configured(
       !mount_config.writable,
       mount_config.path.to_string_lossy().to_string(),
-      app_bin,
-      app_args,
+      options.app_bin,
+      options.app_args,
     ))
   } else {
     Some(LaunchParams::Unconfigured)
   };
-  let launch_config = LaunchConfig::new(
-    runner_path,
-    prefix_path,
+  let mut launch_config = LaunchConfig::new(
+    options.runner,
+    options.prefix,
     launch_params,
-    Some(upscale_mode),
-    Some(sync_mode),
+    options.upscale_mode,
+    options.sync_mode,
+    options.bottles_data_root,
   )?;
-  let env_overrides: HashMap<String, String> = environment
-    .into_iter()
-    .map(|item| {
-      let (key, val) = item.split_once('=').unwrap_or((&item, ""));
-      (key.to_string(), val.to_string())
-    })
-    .collect();
+  if let Some(wait_mode) = options.wait {
+    launch_config.wait_mode = wait_mode;
+  }
+  launch_config.gamescope = options.gamescope;
+  let env_overrides: HashMap<String, String> = options.environment.into_iter().collect();
   let mut runtime_env = RuntimeEnv::from_env()?;
   runtime_env.overrides = Some(env_overrides);
-  let mount_mappings = parse_mappings(volumes)?;
+  let mut builder = Sandbox::builder();
+  if let Some(name) = profile_name {
+    builder = builder.name(name);
+  }
+  builder
+    .sandbox_config(sandbox_config)
+    .launch_config(launch_config)
+    .runtime_env(runtime_env)
+    .mounts(options.volumes)
+    .build()
+}
+
+pub async fn run(profile_name: Option<&str>, cli_options: Profile) -> anyhow::Result<()> {
+  let sandbox = setup(profile_name, cli_options)?;
   // Inhibit the system so screen does not dim while running a game, inhibition will be
   // automatically released when inhibit_handle is dropped.
   let inhibit_handle = inhibitor::inhibit_idle().await;
   if let Err(inhibit_error) = &inhibit_handle {
-    println!("Inhibition failed: {}", inhibit_error.to_string());
+    println!("Inhibition failed: {}", inhibit_error);
+  }
+  let status = sandbox.spawn()?.wait()?;
+  if !status.success() {
+    anyhow::bail!("The bwrap command exited with non-zero exit code");
   }
-  bwrap::run(&sandbox_config, &launch_config, &runtime_env, &mount_mappings)
+  Ok(())
+}
+
+/// Print the bwrap command line that `run` would execute.
+pub fn explain(
+  profile_name: Option<&str>,
+  cli_options: Profile,
+  format: OutputFormat,
+) -> anyhow::Result<()> {
+  let sandbox = setup(profile_name, cli_options)?;
+  println!("{}", sandbox.explain(format)?);
+  Ok(())
 }
@@ -0,0 +1,14 @@
+//! Run games (or any other program) in a Bubblewrap sandbox, optionally using a Wine runner and
+//! prefix managed by Bottles.
+//!
+//! ```no_run
+//! use raptor_cage::sandbox::{Sandbox, sandbox::SandboxConfig};
+//!
+//! let mut child = Sandbox::builder()
+//!   .sandbox_config(SandboxConfig::default())
+//!   .spawn()?;
+//! let status = child.wait()?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+pub mod sandbox;
@@ -1,4 +1,5 @@
-use crate::sandbox::bottles;
+use crate::config;
+use raptor_cage::sandbox::{bottles, gpu};
 use std::str::FromStr;
 
 #[derive(Debug, Clone)]
@@ -6,6 +7,7 @@ pub enum Category {
   All,
   PrefixesOnly,
   RunnersOnly,
+  GpusOnly,
 }
 
 impl FromStr for Category {
@@ -15,15 +17,17 @@ impl FromStr for Category {
       "all" | "a" => Ok(Category::All),
       "prefixes" | "p" => Ok(Category::PrefixesOnly),
       "runners" | "r" => Ok(Category::RunnersOnly),
+      "gpus" | "g" => Ok(Category::GpusOnly),
       _ => Err(format!("Invalid category: {}", s)),
     }
   }
 }
 
 pub fn list(category: Category) -> anyhow::Result<()> {
-  let data_root = bottles::get_data_root()?;
+  // GPUs do not need the data root, so a broken configuration does not prevent listing them.
   match category {
     Category::All => {
+      let data_root = config::get_data_root()?;
       let prefixes = bottles::list_prefixes(&data_root)?;
       println!(
         "Prefixes:\n{}",
@@ -42,15 +46,32 @@ pub fn list(category: Category) -> anyhow::Result<()> {
           .collect::<Vec<String>>()
           .join("\n")
       );
+      let gpus = gpu::detect_host_gpus()?;
+      println!(
+        "GPUs:\n{}",
+        gpus
+          .iter()
+          .map(|gpu| format!("  {}", gpu))
+          .collect::<Vec<String>>()
+          .join("\n")
+      );
     }
     Category::PrefixesOnly => {
+      let data_root = config::get_data_root()?;
       let prefixes = bottles::list_prefixes(&data_root)?;
       println!("{}", prefixes.join("\n"));
     }
     Category::RunnersOnly => {
+      let data_root = config::get_data_root()?;
       let runners = bottles::list_runners(&data_root)?;
       println!("{}", runners.join("\n"));
     }
+    Category::GpusOnly => {
+      let gpus = gpu::detect_host_gpus()?;
+      for gpu in gpus {
+        println!("{}", gpu);
+      }
+    }
   }
   Ok(())
 }
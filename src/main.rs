@@ -1,50 +1,60 @@
 mod cli;
+mod config;
 mod inhibitor;
+mod instances;
 mod invoker;
 mod list;
-mod sandbox;
+mod profile;
 
 use clap::Parser;
-use cli::{Cli, Commands};
+use cli::{Cli, Commands, ConfigCommands, ProfileCommands};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
   let args = Cli::parse();
   match args.command {
     Commands::Run {
-      environment,
-      volumes,
-      no_namespace_isolation,
-      user_mapping,
-      network_mode,
-      device_access,
-      verbose,
-      upscale_mode,
-      sync_mode,
-      runner_path,
-      prefix_path,
-      app_dir,
-      app_bin,
-      app_args,
+      profile,
+      dry_run,
+      format,
+      options,
     } => {
-      invoker::run(
-        &environment,
-        &volumes,
-        no_namespace_isolation,
-        user_mapping,
-        network_mode,
-        device_access,
-        verbose,
-        upscale_mode,
-        sync_mode,
-        runner_path,
-        prefix_path,
-        app_dir,
-        app_bin,
-        app_args,
-      )
-      .await
+      if dry_run {
+        invoker::explain(profile.as_deref(), (*options).into(), format)
+      } else {
+        invoker::run(profile.as_deref(), (*options).into()).await
+      }
     }
+    Commands::Explain {
+      profile,
+      format,
+      options,
+    } => invoker::explain(profile.as_deref(), (*options).into(), format),
     Commands::List { category } => list::list(category),
+    Commands::Ps { json } => instances::ps(json),
+    Commands::Kill { instance, timeout } => instances::kill(&instance, timeout),
+    Commands::Exec { instance, command } => {
+      // The exit code of the command is forwarded, as if it was run directly.
+      std::process::exit(instances::exec(&instance, &command)?)
+    }
+    Commands::Profile(command) => match command {
+      ProfileCommands::New {
+        name,
+        force,
+        options,
+      } => profile::create(&name, &(*options).into(), force),
+      ProfileCommands::Show { name } => profile::show(&name),
+      ProfileCommands::List => profile::list(),
+      ProfileCommands::Edit { name } => profile::edit(&name),
+      ProfileCommands::Rm { name } => profile::remove(&name),
+      ProfileCommands::Clone { source, target } => profile::clone(&source, &target),
+    },
+    Commands::Config(command) => match command {
+      ConfigCommands::Show {
+        resolved,
+        profile,
+        options,
+      } => config::show(resolved, profile.as_deref(), (*options).into()),
+    },
   }
 }
@@ -0,0 +1,411 @@
+use crate::config::get_config_dir;
+use anyhow::Context;
+use raptor_cage::sandbox::{
+  dbus_proxy::{DbusMode, DbusRule},
+  gamescope::Gamescope,
+  gpu::GpuSelection,
+  hidraw::HidrawSelection,
+  mount::{MountConfig, MountMapping},
+  nested_x11::X11Mode,
+  runtime_dir::RuntimeSocket,
+  sandbox::{AudioMode, DeviceAccess, DisplayServer, NetworkMode},
+  seccomp::SeccompLevel,
+  user_mapping::UserMapping,
+  wait::WaitMode,
+  wine::{SyncMode, UpscaleMode},
+  xauth::X11Cookie,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::BTreeMap,
+  env, fs,
+  path::{Path, PathBuf},
+  process::Command,
+};
+
+/// Contains every option accepted by `rcage run`. All fields are optional so multiple sources
+/// (e.g. the global config, a profile file and the command line) can be merged, any value left
+/// unset will fall back to the defaults defined by `SandboxConfig` and `LaunchConfig`.
+///
+/// Profiles are stored as TOML files, values use the same syntax as the command line parameters:
+/// ```toml
+/// network_mode = "restricted"
+/// sync_mode = "fsync"
+/// runner = "soda-9.0-1"
+/// prefix = "my_prefix"
+/// app_dir = "/home/user/games/some_game:rw"
+/// app_bin = "game.exe"
+/// volumes = ["/data/installers:/installers"]
+///
+/// [environment]
+/// MANGOHUD = "1"
+/// ```
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  pub environment: BTreeMap<String, String>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub volumes: Vec<MountMapping>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub namespace_isolation: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub user_mapping: Option<UserMapping>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub network_mode: Option<NetworkMode>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub device_access: Option<DeviceAccess>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub gpu: Option<GpuSelection>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub hidraw: Option<HidrawSelection>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub audio: Option<AudioMode>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub display_server: Option<DisplayServer>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub x11: Option<X11Mode>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub x11_cookie: Option<X11Cookie>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub dbus: Option<DbusMode>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub dbus_allow: Vec<DbusRule>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub runtime_sockets: Vec<RuntimeSocket>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub seccomp: Option<SeccompLevel>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub allow_userns: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub verbose: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub upscale_mode: Option<UpscaleMode>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub sync_mode: Option<SyncMode>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub wait: Option<WaitMode>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub gamescope: Option<Gamescope>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub runner: Option<PathBuf>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub prefix: Option<PathBuf>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub app_dir: Option<MountConfig>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub app_bin: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub app_args: Option<Vec<String>>,
+  /// Where Bottles stores runners and prefixes, relative runner and prefix paths are resolved
+  /// against this directory.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub bottles_data_root: Option<PathBuf>,
+}
+
+impl Profile {
+  /// Merge two sets of options, values set in `other` take precedence. Environment variables with
+  /// the same name are replaced, while list options (e.g. volumes) from both sides are kept.
+  pub fn merge(mut self, other: Profile) -> Profile {
+    self.environment.extend(other.environment);
+    self.volumes.extend(other.volumes);
+    self.dbus_allow.extend(other.dbus_allow);
+    self.runtime_sockets.extend(other.runtime_sockets);
+    Profile {
+      environment: self.environment,
+      volumes: self.volumes,
+      namespace_isolation: other.namespace_isolation.or(self.namespace_isolation),
+      user_mapping: other.user_mapping.or(self.user_mapping),
+      network_mode: other.network_mode.or(self.network_mode),
+      device_access: other.device_access.or(self.device_access),
+      gpu: other.gpu.or(self.gpu),
+      hidraw: other.hidraw.or(self.hidraw),
+      audio: other.audio.or(self.audio),
+      display_server: other.display_server.or(self.display_server),
+      x11: other.x11.or(self.x11),
+      x11_cookie: other.x11_cookie.or(self.x11_cookie),
+      dbus: other.dbus.or(self.dbus),
+      dbus_allow: self.dbus_allow,
+      runtime_sockets: self.runtime_sockets,
+      seccomp: other.seccomp.or(self.seccomp),
+      allow_userns: other.allow_userns.or(self.allow_userns),
+      verbose: other.verbose.or(self.verbose),
+      upscale_mode: other.upscale_mode.or(self.upscale_mode),
+      sync_mode: other.sync_mode.or(self.sync_mode),
+      wait: other.wait.or(self.wait),
+      gamescope: other.gamescope.or(self.gamescope),
+      runner: other.runner.or(self.runner),
+      prefix: other.prefix.or(self.prefix),
+      app_dir: other.app_dir.or(self.app_dir),
+      app_bin: other.app_bin.or(self.app_bin),
+      app_args: other.app_args.or(self.app_args),
+      bottles_data_root: other.bottles_data_root.or(self.bottles_data_root),
+    }
+  }
+
+  pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+    let content = fs::read_to_string(path)
+      .with_context(|| format!("Failed to read profile: {}", path.to_string_lossy()))?;
+    toml::from_str(&content).with_context(|| format!("Invalid profile: {}", path.to_string_lossy()))
+  }
+
+  pub fn to_toml(&self) -> anyhow::Result<String> {
+    toml::to_string(self).context("Failed to serialize profile")
+  }
+}
+
+pub fn get_profiles_dir() -> anyhow::Result<PathBuf> {
+  Ok(get_config_dir()?.join("profiles"))
+}
+
+/// Get the file path of a profile, names are used as file names so they must not contain path
+/// separators.
+pub fn get_profile_path(name: &str) -> anyhow::Result<PathBuf> {
+  if name.is_empty() || name.starts_with('.') || name.contains('/') {
+    anyhow::bail!("Invalid profile name: {}", name);
+  }
+  Ok(get_profiles_dir()?.join(format!("{}.toml", name)))
+}
+
+/// Same as `get_profile_path`, but fails if the profile does not exist.
+fn get_existing_profile_path(name: &str) -> anyhow::Result<PathBuf> {
+  let path = get_profile_path(name)?;
+  if !path.is_file() {
+    anyhow::bail!("Profile not found: {}", name);
+  }
+  Ok(path)
+}
+
+/// Write raw profile contents, existing profiles are only replaced when `overwrite` is set.
+fn write_profile(name: &str, content: &str, overwrite: bool) -> anyhow::Result<PathBuf> {
+  let path = get_profile_path(name)?;
+  if !overwrite && path.exists() {
+    anyhow::bail!("Profile already exists: {}", name);
+  }
+  let profiles_dir = get_profiles_dir()?;
+  fs::create_dir_all(&profiles_dir)
+    .with_context(|| format!("Failed to create directory: {}", profiles_dir.to_string_lossy()))?;
+  fs::write(&path, content)
+    .with_context(|| format!("Failed to write profile: {}", path.to_string_lossy()))?;
+  Ok(path)
+}
+
+pub fn load(name: &str) -> anyhow::Result<Profile> {
+  Profile::from_file(&get_existing_profile_path(name)?)
+}
+
+pub fn list_profiles() -> anyhow::Result<Vec<String>> {
+  let profiles_dir = get_profiles_dir()?;
+  if !profiles_dir.is_dir() {
+    return Ok(vec![]);
+  }
+  let entries = fs::read_dir(&profiles_dir)
+    .with_context(|| format!("Failed to read directory: {}", profiles_dir.to_string_lossy()))?;
+  let mut names: Vec<String> = entries
+    .flatten()
+    .map(|entry| entry.path())
+    .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "toml"))
+    .filter_map(|path| {
+      path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+    })
+    .collect();
+  names.sort();
+  Ok(names)
+}
+
+/// Save the given options as a new profile.
+pub fn create(name: &str, profile: &Profile, overwrite: bool) -> anyhow::Result<()> {
+  let path = write_profile(name, &profile.to_toml()?, overwrite)?;
+  println!("Profile saved: {}", path.to_string_lossy());
+  Ok(())
+}
+
+pub fn show(name: &str) -> anyhow::Result<()> {
+  let path = get_existing_profile_path(name)?;
+  let content = fs::read_to_string(&path)
+    .with_context(|| format!("Failed to read profile: {}", path.to_string_lossy()))?;
+  print!("{}", content);
+  Ok(())
+}
+
+pub fn list() -> anyhow::Result<()> {
+  println!("{}", list_profiles()?.join("\n"));
+  Ok(())
+}
+
+/// Open a profile with `$VISUAL` or `$EDITOR`. The changes are made on a temporary copy, which is
+/// only saved when it is still a valid profile, so a typo cannot leave a broken profile behind.
+pub fn edit(name: &str) -> anyhow::Result<()> {
+  let path = get_existing_profile_path(name)?;
+  let editor = env::var("VISUAL")
+    .or_else(|_| env::var("EDITOR"))
+    .unwrap_or("vi".into());
+  // Editors like "code --wait" include arguments.
+  let mut editor_parts = editor.split_whitespace();
+  let editor_bin = editor_parts.next().context("Editor must not be empty")?;
+  let temp_file = tempfile::Builder::new()
+    .prefix(&format!("{}-", name))
+    .suffix(".toml")
+    .tempfile()?;
+  fs::copy(&path, temp_file.path())
+    .with_context(|| format!("Failed to read profile: {}", path.to_string_lossy()))?;
+  let status = Command::new(editor_bin)
+    .args(editor_parts)
+    .arg(temp_file.path())
+    .status()
+    .with_context(|| format!("Could not start editor: {}", editor))?;
+  if !status.success() {
+    anyhow::bail!("The editor exited with non-zero exit code, profile was not modified");
+  }
+  Profile::from_file(temp_file.path()).context("Profile was not modified")?;
+  let content = fs::read_to_string(temp_file.path())?;
+  write_profile(name, &content, true)?;
+  println!("Profile saved: {}", path.to_string_lossy());
+  Ok(())
+}
+
+pub fn remove(name: &str) -> anyhow::Result<()> {
+  let path = get_existing_profile_path(name)?;
+  fs::remove_file(&path)
+    .with_context(|| format!("Failed to remove profile: {}", path.to_string_lossy()))
+}
+
+/// Copy a profile as-is (comments included), the source profile must be valid.
+pub fn clone(source: &str, target: &str) -> anyhow::Result<()> {
+  let source_path = get_existing_profile_path(source)?;
+  Profile::from_file(&source_path)?;
+  let content = fs::read_to_string(&source_path)
+    .with_context(|| format!("Failed to read profile: {}", source_path.to_string_lossy()))?;
+  let path = write_profile(target, &content, false)?;
+  println!("Profile saved: {}", path.to_string_lossy());
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_profile_parsing() {
+    let profile: Profile = toml::from_str(
+      r#"
+      network_mode = "restricted"
+      sync_mode = "fsync"
+      upscale_mode = "fsr:balanced:1"
+      user_mapping = "500000:600000"
+      runner = "soda-9.0-1"
+      prefix = "my_prefix"
+      app_dir = "/games/some_game:rw"
+      app_bin = "game.exe"
+      volumes = ["/data/installers:/installers"]
+
+      [environment]
+      MANGOHUD = "1"
+      "#,
+    )
+    .unwrap();
+    assert!(matches!(profile.network_mode, Some(NetworkMode::RestrictedAccess)));
+    assert_eq!(profile.sync_mode, Some(SyncMode::Fsync));
+    assert!(matches!(profile.upscale_mode, Some(UpscaleMode::Fsr { strength: 1, .. })));
+    assert!(matches!(profile.user_mapping, Some(UserMapping::Custom(500_000, 600_000))));
+    assert_eq!(profile.runner, Some(PathBuf::from("soda-9.0-1")));
+    assert_eq!(
+      profile.app_dir,
+      Some(MountConfig {
+        path: PathBuf::from("/games/some_game"),
+        writable: true,
+      })
+    );
+    assert_eq!(profile.volumes.len(), 1);
+    assert_eq!(profile.environment.get("MANGOHUD"), Some(&"1".to_string()));
+  }
+
+  #[test]
+  fn test_profile_parsing_errors() {
+    let test_cases = vec![
+      r#"network_mode = "everything""#,
+      r#"volumes = ["/:/test"]"#,
+      r#"unknown_field = true"#,
+    ];
+    for input in test_cases {
+      assert!(toml::from_str::<Profile>(input).is_err(), "{}", input);
+    }
+  }
+
+  #[test]
+  fn test_profile_merge() {
+    let profile: Profile = toml::from_str(
+      r#"
+      network_mode = "full"
+      sync_mode = "esync"
+      app_bin = "game.exe"
+      volumes = ["/data:/data"]
+      environment = { A = "1", B = "2" }
+      "#,
+    )
+    .unwrap();
+    let overrides: Profile = toml::from_str(
+      r#"
+      network_mode = "no"
+      volumes = ["/other:/other"]
+      environment = { B = "3" }
+      "#,
+    )
+    .unwrap();
+    let merged = profile.merge(overrides);
+    assert!(matches!(merged.network_mode, Some(NetworkMode::NoAccess)));
+    assert_eq!(merged.sync_mode, Some(SyncMode::Esync));
+    assert_eq!(merged.app_bin, Some("game.exe".to_string()));
+    assert_eq!(merged.volumes.len(), 2);
+    assert_eq!(merged.environment.get("A"), Some(&"1".to_string()));
+    assert_eq!(merged.environment.get("B"), Some(&"3".to_string()));
+  }
+
+  #[test]
+  fn test_profile_serialization() {
+    let input = r#"
+      user_mapping = "500000:600000"
+      network_mode = "r"
+      device_access = "all"
+      upscale_mode = "fsr:u:5"
+      sync_mode = "fsync"
+      gamescope = " -w 1280  -h 720"
+      runner = "soda-9.0-1"
+      app_dir = "/games/some_game:rw"
+      volumes = ["/data:/data", "/other:/other:rw"]
+      environment = { A = "1" }
+      "#;
+    let profile: Profile = toml::from_str(input).unwrap();
+    let serialized = profile.to_toml().unwrap();
+    let expected = r#"volumes = ["/data:/data", "/other:/other:rw"]
+user_mapping = "500000:600000"
+network_mode = "restricted_access"
+device_access = "all"
+upscale_mode = "fsr:ultra:5"
+sync_mode = "fsync"
+gamescope = "-w 1280 -h 720"
+runner = "soda-9.0-1"
+app_dir = "/games/some_game:rw"
+
+[environment]
+A = "1"
+"#;
+    assert_eq!(serialized, expected);
+    let roundtrip: Profile = toml::from_str(&serialized).unwrap();
+    assert_eq!(roundtrip.to_toml().unwrap(), expected);
+  }
+
+  #[test]
+  fn test_empty_profile_serialization() {
+    assert_eq!(Profile::default().to_toml().unwrap(), "");
+  }
+
+  #[test]
+  fn test_invalid_profile_names() {
+    for name in ["", ".hidden", "../escape", "a/b"] {
+      assert!(get_profile_path(name).is_err(), "{}", name);
+    }
+  }
+}
@@ -1,56 +1,331 @@
+use super::bwrap_args::{BwrapArgs, BwrapBuilder, BwrapOp, Namespace};
+use super::dbus_proxy::{self, DbusMode, DbusProxy};
 use super::display::Display;
+use super::explain::{
+  self, EMPTY_FILE_PLACEHOLDER, NESTED_XAUTHORITY_PLACEHOLDER, OutputFormat,
+  SECCOMP_FD_PLACEHOLDER, SESSION_BUS_PROXY_PLACEHOLDER, SYSTEM_BUS_PROXY_PLACEHOLDER,
+  UNTRUSTED_XAUTHORITY_PLACEHOLDER,
+};
+use super::gamescope::Gamescope;
+use super::gpu::{self, Gpu, GpuSelection, VULKAN_ICD_DIR};
+use super::host::{self, BwrapVersion, HostProbe, KernelVersion, SystemHost};
+use super::launcher::SandboxChild;
 use super::mount::MountMapping;
+use super::nested_x11::{self, NestedServer, X11Mode};
+use super::registry::{Instance, Registry};
+use super::runtime_dir::RuntimeSocket;
 use super::sandbox::{
-  DeviceAccess, LaunchConfig, LaunchParams, NetworkMode, RuntimeEnv, SandboxConfig,
-};
-use super::sandbox_config::{
-  INNER_APP_DIR, INNER_WINE_PREFIX, INNER_WINE_ROOT, current_timestamp_hex, find_nvidia_devices,
+  AudioMode, DeviceAccess, DeviceClass, DisplayServer, LaunchConfig, LaunchParams, NetworkMode,
+  RuntimeEnv, SandboxConfig,
 };
-use super::wine::{SyncMode, UpscaleMode};
+use super::sandbox_config::{INNER_APP_DIR, INNER_WINE_PREFIX, INNER_WINE_ROOT};
+use super::seccomp::{self, SeccompLevel};
+use super::wait::WaitMode;
+use super::wine::{NTSYNC_DEVICE, SyncMode, UpscaleMode};
+use super::xauth::{self, X11Cookie};
 use anyhow::Context;
-use std::env;
-use std::path::PathBuf;
+use serde::Deserialize;
+use std::io::{self, BufRead, BufReader};
+use std::os::fd::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use tempfile::NamedTempFile;
 
-/// Gets the corresponding bwrap parameters for the selected DeviceAccess option.
-pub fn get_device_args(device_access: &DeviceAccess) -> anyhow::Result<Vec<String>> {
-  match device_access {
+/// Gets the corresponding bwrap options for the selected DeviceAccess option, `sync_mode` must be
+/// already resolved, see `resolve_sync_mode`. With a selected GPU, the gpu class only exposes the
+/// nodes of that GPU.
+pub fn get_device_ops(
+  device_access: &DeviceAccess,
+  sync_mode: SyncMode,
+  gpu: Option<&Gpu>,
+  host: &dyn HostProbe,
+) -> anyhow::Result<Vec<BwrapOp>> {
+  let dev_bind = |device: String| BwrapOp::DevBind {
+    src: device.clone(),
+    dst: device,
+  };
+  let (classes, paths) = match device_access {
     DeviceAccess::All => {
       // NOTE: "bwrap --dev /dev ..." does not work as expected, so using "--dev-bind" instead.
-      let args = vec!["--dev-bind", "/dev", "/dev"];
-      Ok(args.into_iter().map(String::from).collect())
+      return Ok(vec![dev_bind("/dev".into())]);
     }
-    DeviceAccess::Minimal => {
-      let nvidia_devices = find_nvidia_devices()?;
-      // TODO: check if /dev/snd/seq is needed.
-      let mut devices: Vec<String> = vec!["/dev/input", "/dev/uinput", "/dev/dri"]
-        .into_iter()
-        .map(String::from)
-        .collect();
-      devices.extend(nvidia_devices);
-      let args: Vec<String> = devices
+    DeviceAccess::Selected { classes, paths } => (classes, paths),
+  };
+  let char_devices = host.char_devices()?;
+  let char_devices_starting_with = |prefix: &str| -> Vec<String> {
+    char_devices
+      .iter()
+      .filter(|device| device.starts_with(prefix))
+      .cloned()
+      .collect()
+  };
+  let mut devices: Vec<String> = Vec::new();
+  for class in classes {
+    match class {
+      DeviceClass::Input => devices.push("/dev/input".into()),
+      DeviceClass::Uinput => devices.push("/dev/uinput".into()),
+      DeviceClass::Gpu => {
+        match gpu {
+          Some(gpu) => devices.extend(
+            [Some(gpu.card.clone()), gpu.render_node.clone()]
+              .into_iter()
+              .flatten(),
+          ),
+          None => devices.push("/dev/dri".into()),
+        }
+        // Other GPUs are hidden, including their /dev/nvidiaN device. The NVIDIA control devices
+        // (e.g. /dev/nvidiactl) are only needed when the selected GPU uses the NVIDIA driver.
+        let keep_nvidia_device = |device: &str| {
+          let is_gpu_device = device
+            .strip_prefix("/dev/nvidia")
+            .is_some_and(|minor| minor.parse::<u32>().is_ok());
+          match gpu {
+            None => true,
+            Some(gpu) if gpu.is_nvidia_proprietary() => {
+              !is_gpu_device || gpu.nvidia_device().as_deref() == Some(device)
+            }
+            Some(_) => false,
+          }
+        };
+        let nvidia_devices = char_devices_starting_with("/dev/nvidia");
+        devices.extend(
+          nvidia_devices
+            .into_iter()
+            .filter(|device| keep_nvidia_device(device)),
+        );
+      }
+      DeviceClass::Hidraw => devices.extend(char_devices_starting_with("/dev/hidraw")),
+      DeviceClass::Sound => {
+        if host.path_exists(Path::new("/dev/snd")) {
+          devices.push("/dev/snd".into());
+        }
+      }
+      DeviceClass::Video => {
+        devices.extend(char_devices_starting_with("/dev/video"));
+        devices.extend(char_devices_starting_with("/dev/media"));
+      }
+      DeviceClass::Ntsync => {
+        if host.path_exists(Path::new(NTSYNC_DEVICE)) {
+          devices.push(NTSYNC_DEVICE.into());
+        }
+      }
+    }
+  }
+  if sync_mode == SyncMode::Ntsync && !classes.contains(&DeviceClass::Ntsync) {
+    devices.push(NTSYNC_DEVICE.into());
+  }
+  devices.extend(paths.iter().cloned());
+  Ok(devices.into_iter().map(dev_bind).collect())
+}
+
+/// Gets the bwrap options for the selected AudioMode option. The audio sockets live under
+/// XDG_RUNTIME_DIR, which starts empty inside the sandbox, so only the allowed ones are bound.
+fn get_audio_ops(audio: AudioMode, runtime_env: &RuntimeEnv, host: &dyn HostProbe) -> Vec<BwrapOp> {
+  let runtime_dir = Path::new(&runtime_env.xdg_runtime_dir);
+  let to_string = |path: &Path| path.to_string_lossy().to_string();
+  let pulse_socket = runtime_dir.join("pulse/native");
+  // The manager socket (pipewire-0-manager) grants privileged access e.g., to other clients, so it
+  // is never bound.
+  let pipewire_socket = runtime_dir.join("pipewire-0");
+  // Only needed by PulseAudio itself, pipewire-pulse does not use cookies.
+  let pulse_cookie = Path::new(&runtime_env.home_dir).join(".config/pulse/cookie");
+  let mut ops = Vec::new();
+  let bind_same = |path: &Path| BwrapOp::Bind {
+    src: to_string(path),
+    dst: to_string(path),
+    ro: true,
+  };
+  if matches!(audio, AudioMode::Pulse | AudioMode::All) && host.path_exists(&pulse_socket) {
+    ops.push(bind_same(&pulse_socket));
+    if host.path_exists(&pulse_cookie) {
+      ops.push(bind_same(&pulse_cookie));
+      ops.push(BwrapOp::SetEnv {
+        key: "PULSE_COOKIE".into(),
+        value: to_string(&pulse_cookie),
+      });
+    }
+  }
+  if matches!(audio, AudioMode::Pipewire | AudioMode::All) && host.path_exists(&pipewire_socket) {
+    ops.push(bind_same(&pipewire_socket));
+  }
+  ops
+}
+
+/// Gets the bwrap options that expose the entries of XDG_RUNTIME_DIR requested by
+/// `--runtime-socket`, which must exist.
+fn get_runtime_socket_ops(
+  runtime_sockets: &[RuntimeSocket],
+  runtime_env: &RuntimeEnv,
+  host: &dyn HostProbe,
+) -> anyhow::Result<Vec<BwrapOp>> {
+  let mut ops = Vec::new();
+  for socket in runtime_sockets {
+    let path = Path::new(&runtime_env.xdg_runtime_dir).join(&socket.name);
+    if !host.path_exists(&path) {
+      anyhow::bail!("Runtime socket not found: {}", path.to_string_lossy());
+    }
+    let path = path.to_string_lossy().to_string();
+    ops.push(BwrapOp::Bind {
+      src: path.clone(),
+      dst: path,
+      ro: true,
+    });
+  }
+  Ok(ops)
+}
+
+/// Runner files that provide the Wine Wayland driver, the location depends on how it was built.
+const WINE_WAYLAND_DRIVERS: [&str; 2] = [
+  "lib/wine/x86_64-unix/winewayland.so",
+  "lib64/wine/x86_64-unix/winewayland.so",
+];
+
+/// Runner binary whose strings tell which sync modes the runner supports.
+const RUNNER_WINESERVER: &str = "bin/wineserver";
+
+/// Checks that the host supports the requested sync mode, and picks one for `SyncMode::Auto`. The
+/// kernel version is given, rather than probed, so every version can be tested.
+fn resolve_sync_mode(
+  sync_mode: SyncMode,
+  kernel_version: Option<KernelVersion>,
+  runner_path: Option<&Path>,
+  host: &dyn HostProbe,
+) -> anyhow::Result<SyncMode> {
+  let host_supports = |mode: SyncMode| {
+    let new_enough = mode
+      .min_kernel()
+      .is_none_or(|min| kernel_version.is_some_and(|version| version >= min));
+    new_enough && (mode != SyncMode::Ntsync || host.path_exists(Path::new(NTSYNC_DEVICE)))
+  };
+  match sync_mode {
+    SyncMode::Auto => {
+      // Without a runner there is no way to tell what is supported.
+      let Some(runner_path) = runner_path else {
+        return Ok(SyncMode::None);
+      };
+      let wineserver = runner_path.join(RUNNER_WINESERVER);
+      let runner_supports = |mode: SyncMode| {
+        mode
+          .runner_marker()
+          .is_some_and(|marker| host.file_contains(&wineserver, marker.as_bytes()))
+      };
+      let best_mode = [SyncMode::Ntsync, SyncMode::Fsync, SyncMode::Esync]
         .into_iter()
-        .flat_map(|d| vec!["--dev-bind".to_string(), d.to_owned(), d.to_owned()])
-        .collect();
-      Ok(args)
+        .find(|mode| host_supports(*mode) && runner_supports(*mode));
+      Ok(best_mode.unwrap_or(SyncMode::None))
     }
+    SyncMode::Ntsync if !host_supports(SyncMode::Ntsync) => anyhow::bail!(
+      "--sync-mode=ntsync requires Linux 6.14 or newer and {}, check that the ntsync module is \
+       loaded",
+      NTSYNC_DEVICE
+    ),
+    mode => Ok(mode),
   }
 }
 
-fn get_mount_args(mount_mappings: &[MountMapping]) -> Vec<String> {
-  let mut args: Vec<String> = Vec::with_capacity(mount_mappings.len() * 3);
-  for mapping in mount_mappings {
-    let bind_param = if mapping.target_config.writable {
-      "--bind"
+/// Display servers exposed to the sandbox, resolved from `--display-server` and the session.
+struct Displays<'a> {
+  /// X11 display address.
+  x11: Option<&'a str>,
+  /// Path of the Wayland socket.
+  wayland: Option<String>,
+}
+
+fn get_displays(
+  display_server: DisplayServer,
+  runtime_env: &RuntimeEnv,
+) -> anyhow::Result<Displays<'_>> {
+  let x11 = runtime_env.display_address.as_deref();
+  // WAYLAND_DISPLAY is usually a socket name relative to XDG_RUNTIME_DIR.
+  let wayland = runtime_env.wayland_display.as_ref().map(|display| {
+    if display.starts_with('/') {
+      display.clone()
     } else {
-      "--ro-bind"
-    };
-    let source = mapping.source_path.to_string_lossy();
-    let target = mapping.target_config.path.to_string_lossy();
-    args.extend(vec![bind_param.into(), source.into(), target.into()]);
+      format!("{}/{}", runtime_env.xdg_runtime_dir, display)
+    }
+  });
+  match display_server {
+    DisplayServer::X11 => Ok(Displays {
+      x11: Some(x11.context("DISPLAY is not set, X11 is not available")?),
+      wayland: None,
+    }),
+    DisplayServer::Wayland => Ok(Displays {
+      x11: None,
+      wayland: Some(wayland.context("WAYLAND_DISPLAY is not set, Wayland is not available")?),
+    }),
+    DisplayServer::Auto if x11.is_none() && wayland.is_none() => {
+      anyhow::bail!("Neither DISPLAY nor WAYLAND_DISPLAY is set, no display server is available")
+    }
+    DisplayServer::Auto => Ok(Displays { x11, wayland }),
   }
-  args
+}
+
+/// Whether the sandbox gets an untrusted cookie for the X server of the session.
+fn uses_untrusted_cookie(sandbox_config: &SandboxConfig, runtime_env: &RuntimeEnv) -> bool {
+  sandbox_config.x11 == X11Mode::Host
+    && sandbox_config.x11_cookie == X11Cookie::Untrusted
+    && sandbox_config.display_server != DisplayServer::Wayland
+    && runtime_env.display_address.is_some()
+}
+
+/// First bwrap version with `--disable-userns`.
+const DISABLE_USERNS_VERSION: BwrapVersion = (0, 8, 0);
+
+/// How the sandbox is prevented from creating nested user namespaces, which expose a much larger
+/// attack surface of the kernel (e.g. mounting filesystems, or netfilter).
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum UsernsBlocking {
+  /// Not prevented because of `--allow-userns`, e.g. for launchers that run their own sandbox.
+  Allowed,
+  /// By bwrap itself.
+  Bwrap,
+  /// By the seccomp filter, for bwrap versions without `--disable-userns` or when the sandbox does
+  /// not get its own user namespace.
+  Seccomp,
+}
+
+impl UsernsBlocking {
+  fn resolve(sandbox_config: &SandboxConfig, bwrap_version: Option<BwrapVersion>) -> Self {
+    if sandbox_config.allow_userns {
+      UsernsBlocking::Allowed
+    } else if sandbox_config.namespace_isolation
+      && bwrap_version.is_some_and(|version| version >= DISABLE_USERNS_VERSION)
+    {
+      UsernsBlocking::Bwrap
+    } else {
+      UsernsBlocking::Seccomp
+    }
+  }
+
+  /// Explain why nested user namespaces are allowed or how they are denied, for `--verbose`.
+  fn describe(self, sandbox_config: &SandboxConfig, bwrap_version: Option<BwrapVersion>) -> String {
+    match self {
+      UsernsBlocking::Allowed => "allowed by --allow-userns".into(),
+      UsernsBlocking::Bwrap => "denied by bwrap (--disable-userns)".into(),
+      UsernsBlocking::Seccomp => {
+        let reason = match bwrap_version {
+          _ if !sandbox_config.namespace_isolation => "--no-namespace-isolation is set".into(),
+          Some((major, minor, patch)) => {
+            format!("bwrap {}.{}.{} does not support --disable-userns", major, minor, patch)
+          }
+          None => "the bwrap version is unknown".into(),
+        };
+        format!("denied by the seccomp filter, {}", reason)
+      }
+    }
+  }
+}
+
+fn get_mount_ops(mount_mappings: &[MountMapping]) -> Vec<BwrapOp> {
+  mount_mappings
+    .iter()
+    .map(|mapping| BwrapOp::Bind {
+      src: mapping.source_path.to_string_lossy().into(),
+      dst: mapping.target_config.path.to_string_lossy().into(),
+      ro: !mapping.target_config.writable,
+    })
+    .collect()
 }
 
 fn build_args(
@@ -58,113 +333,175 @@ fn build_args(
   launch_config: &LaunchConfig,
   runtime_env: &RuntimeEnv,
   mount_mappings: &[MountMapping],
-  empty_file_path: &str,
-) -> anyhow::Result<Vec<String>> {
-  let mut args = vec![
-    // Kill processes in sandbox when bwrap dies.
-    "--die-with-parent",
-  ];
+  host: &dyn HostProbe,
+) -> anyhow::Result<BwrapArgs> {
+  let empty_file_path = host.empty_file_path();
+  let displays = get_displays(sandbox_config.display_server, runtime_env)?;
+  if sandbox_config.x11 != X11Mode::Host && displays.x11.is_none() {
+    anyhow::bail!("--x11={} requires X11, check --display-server", sandbox_config.x11);
+  }
+  let requested_sync_mode = launch_config.sync_mode.unwrap_or(SyncMode::None);
+  let sync_mode = resolve_sync_mode(
+    requested_sync_mode,
+    host.kernel_version(),
+    launch_config.runner_path.as_deref(),
+    host,
+  )?;
+  // GPUs are only probed when one is selected, so a missing sysfs does not break other setups.
+  let gpus = match sandbox_config.gpu {
+    GpuSelection::None => Vec::new(),
+    _ => host.gpus()?,
+  };
+  let selected_gpu = sandbox_config.gpu.resolve(&gpus)?;
+  let mut builder = BwrapBuilder::new();
+  let namespaces_reason = if sandbox_config.namespace_isolation {
+    format!("--user-mapping={}", sandbox_config.user_mapping)
+  } else {
+    "--no-namespace-isolation".into()
+  };
+  builder.group("namespaces", namespaces_reason);
+  // Kill processes in sandbox when bwrap dies.
+  builder.op(BwrapOp::DieWithParent);
   // With user isolation the uid and gid will change inside the container, outside the container
   // they will still be the same as the invoking user.
-  let uid: String;
-  let gid: String;
   if sandbox_config.namespace_isolation {
-    // Need to keep IPC namespace (i.e. no --unshare-ipc) because it breaks some GUI applications
-    // i.e. when quickly moving the mouse cursor over the WinRAR menu bar, the application will
-    // crash with a "X Error of failed request:  BadValue (integer parameter out of range for
-    // operation)" error.
-    // TODO: consider bringing back the --unshare-ipc parameter, it seems limited to X11, see also
-    // flatpak docs about the IPC issue.
-    args.extend(["--unshare-pid", "--unshare-cgroup"]);
-    (uid, gid) = sandbox_config.user_mapping.get_uid_gid_string();
-    args.extend(["--unshare-user", "--uid", &uid, "--gid", &gid]);
+    // Need to keep IPC namespace (i.e. no --unshare-ipc) with X11 because it breaks some GUI
+    // applications i.e. when quickly moving the mouse cursor over the WinRAR menu bar, the
+    // application will crash with a "X Error of failed request:  BadValue (integer parameter out
+    // of range for operation)" error. This comes from the MIT-SHM extension, so Wayland is fine.
+    if displays.x11.is_none() {
+      builder.unshare(Namespace::Ipc);
+    }
+    builder.unshare(Namespace::Pid).unshare(Namespace::Cgroup);
+    let (uid, gid) = sandbox_config.user_mapping.get_uid_gid();
+    builder
+      .unshare(Namespace::User)
+      .op(BwrapOp::Uid(uid))
+      .op(BwrapOp::Gid(gid));
   }
   // Use a new UTS space and a hostname based on the current timestamp.
-  let timestamp = current_timestamp_hex();
-  args.extend(["--unshare-uts", "--hostname", &timestamp]);
+  builder
+    .unshare(Namespace::Uts)
+    .op(BwrapOp::Hostname(host.hostname()));
+  let userns = UsernsBlocking::resolve(sandbox_config, host.bwrap_version());
+  let userns_reason = match userns {
+    UsernsBlocking::Allowed => "--allow-userns",
+    UsernsBlocking::Bwrap => "without --allow-userns",
+    UsernsBlocking::Seccomp => "without --allow-userns, denied by the seccomp filter",
+  };
+  builder.group("user namespaces", userns_reason);
+  if userns == UsernsBlocking::Bwrap {
+    builder
+      .op(BwrapOp::DisableUserns)
+      .op(BwrapOp::AssertUsernsDisabled);
+  }
+  // Applied by bwrap right before executing the command, so its own setup is not affected.
+  let deny_userns = userns == UsernsBlocking::Seccomp;
+  if sandbox_config.seccomp != SeccompLevel::Off || deny_userns {
+    let seccomp_reason = match (sandbox_config.seccomp, deny_userns) {
+      (SeccompLevel::Off, _) => "nested user namespaces".into(),
+      (level, false) => format!("--seccomp={}", level),
+      (level, true) => format!("--seccomp={}, nested user namespaces", level),
+    };
+    builder
+      .group("seccomp", seccomp_reason)
+      .op(BwrapOp::Seccomp(host.seccomp_fd().into()));
+  }
   // Share devices, if NVIDIA devices are missing, weird/misleading gstreamer errors may appear when
   // playing games, like telling you that a gst plugin is missing.
-  let device_args = get_device_args(&sandbox_config.device_access)?;
-  args.extend(device_args.iter().map(|a| a.as_str()));
+  // Only the gpu class is narrowed down by --gpu.
+  let isolates_gpu = matches!(
+    &sandbox_config.device_access,
+    DeviceAccess::Selected { classes, .. } if classes.contains(&DeviceClass::Gpu)
+  );
+  let mut devices_reason = match selected_gpu {
+    Some(_) if isolates_gpu => {
+      format!("--device-access={}, --gpu={}", sandbox_config.device_access, sandbox_config.gpu)
+    }
+    _ => format!("--device-access={}", sandbox_config.device_access),
+  };
+  // Only the matching hidraw nodes, unless every node is already exposed.
+  let exposes_hidraw = matches!(
+    &sandbox_config.device_access,
+    DeviceAccess::Selected { classes, .. } if !classes.contains(&DeviceClass::Hidraw)
+  ) && !sandbox_config.hidraw.is_empty();
+  if exposes_hidraw {
+    devices_reason.push_str(&format!(", --hidraw={}", sandbox_config.hidraw));
+  }
+  builder.group("devices", devices_reason);
+  for op in get_device_ops(&sandbox_config.device_access, sync_mode, selected_gpu, host)? {
+    builder.op(op);
+  }
+  if exposes_hidraw {
+    // Nodes are resolved when the sandbox starts, controllers plugged later are not exposed.
+    for device in host.hidraw_devices() {
+      if sandbox_config.hidraw.matches(device.id) {
+        builder.op(BwrapOp::DevBind {
+          src: device.path.clone(),
+          dst: device.path,
+        });
+      }
+    }
+  }
   // System binaries and libraries.
-  args.extend([
-    "--ro-bind",
-    "/bin",
-    "/bin",
-    "--ro-bind",
-    "/lib64",
-    "/lib64",
-    "--ro-bind",
-    "/sbin",
-    "/sbin",
-    "--ro-bind",
-    "/usr",
-    "/usr",
-    "--symlink",
-    "/usr/lib",
-    "/lib",
-  ]);
-  // Need to bind /run because it allows D-Bus to work, also some apps that directly or indirectly
-  // rely on libudev may fail to access devices like gamepads if /run/udev/data is not accessible.
-  // Binding /run works but it exposes more than we need, so only bind D-Bus related paths,
-  // i.e. sandboxed apps shouldn't be able to run "DOCKER_HOST=unix:///run/docker.sock docker ps",
-  // the aforementioned command works even if --ro-bind was used.
+  builder
+    .group("system binds", "always")
+    .ro_bind_same("/bin")
+    .ro_bind_same("/lib64")
+    .ro_bind_same("/sbin")
+    .ro_bind_same("/usr")
+    .symlink("/usr/lib", "/lib");
+  // Some apps that directly or indirectly rely on libudev may fail to access devices like gamepads
+  // if /run/udev/data is not accessible. Binding /run works but it exposes more than we need, so
+  // only bind the needed paths (D-Bus is configured later), i.e. sandboxed apps shouldn't be able to
+  // run "DOCKER_HOST=unix:///run/docker.sock docker ps", the aforementioned command works even if
+  // --ro-bind was used.
   // Access to /sys is needed for apps to be able to retrieve kernel and hardware information.
-  args.extend([
-    "--ro-bind",
-    "/run/dbus",
-    "/run/dbus",
-    "--ro-bind",
+  builder
     // TODO: investigate "0090:err:hid:udev_bus_init UDEV monitor creation failed" errors. Happens
     // with wine-ge-proton8-26.
-    "/run/udev/data",
-    "/run/udev/data",
-    "--ro-bind",
-    "/run/user",
-    "/run/user",
-    "--ro-bind",
-    "/sys",
-    "/sys",
-  ]);
+    .ro_bind_same("/run/udev/data")
+    .ro_bind_same("/sys");
   // There are just so many things that could be needed under /etc to the point
   // that is not reliable to selectively mount directories under /etc
   // (e.g. DOOM 2016 will fail if no /etc/vulkan is present), so mount all /etc.
-  args.extend([
-    "--bind",
-    "/etc",
-    "/etc",
-    "--ro-bind",
-    empty_file_path,
-    "/etc/hostname",
+  builder
+    .bind("/etc", "/etc")
+    .ro_bind(empty_file_path, "/etc/hostname")
     // Application shared data e.g., "/usr/share/vulkan/icd.d".
-    "--ro-bind",
-    "/usr/share",
-    "/usr/share",
-  ]);
+    .ro_bind_same("/usr/share");
+  // Only expose the Vulkan drivers of the selected GPU, so games cannot enumerate (or render on)
+  // the other ones, even with --device-access=all.
+  if let Some(gpu) = selected_gpu
+    && let Some(icd_prefixes) = gpu.vulkan_icd_prefixes()
+  {
+    builder
+      .group("vulkan", format!("--gpu={} ({})", sandbox_config.gpu, gpu.vendor))
+      .tmpfs(VULKAN_ICD_DIR);
+    for icd_file in host.dir_entries(Path::new(VULKAN_ICD_DIR)) {
+      if icd_prefixes
+        .iter()
+        .any(|prefix| icd_file.starts_with(prefix))
+      {
+        builder.ro_bind_same(format!("{}/{}", VULKAN_ICD_DIR, icd_file));
+      }
+    }
+  }
   // Setup networking, the bwrap default is enabled, our default will be to have it disabled.
+  builder.group("network", format!("--network-mode={}", sandbox_config.network_mode));
   match sandbox_config.network_mode {
     NetworkMode::FullAccess => (), // No extra arguments required
     NetworkMode::RestrictedAccess => {
-      args.extend([
-        "--tmpfs",
-        "/etc/ca-certificates",
-        "--tmpfs",
-        "/etc/ssl",
-        "--tmpfs",
-        "/etc/NetworkManager",
-        "--ro-bind",
-        empty_file_path,
-        "/etc/resolv.conf",
-        "--ro-bind",
-        empty_file_path,
-        "/etc/nsswitch.conf",
-        "--ro-bind",
-        empty_file_path,
-        "/etc/hosts",
-      ]);
+      builder
+        .tmpfs("/etc/ca-certificates")
+        .tmpfs("/etc/ssl")
+        .tmpfs("/etc/NetworkManager")
+        .ro_bind(empty_file_path, "/etc/resolv.conf")
+        .ro_bind(empty_file_path, "/etc/nsswitch.conf")
+        .ro_bind(empty_file_path, "/etc/hosts");
     }
     NetworkMode::NoAccess => {
-      args.push("--unshare-net");
+      builder.unshare(Namespace::Net);
     }
   }
   // While --dir itself doesn't inherently leak data from the host, it provides less protection
@@ -172,185 +509,256 @@ fn build_args(
   // contained within the sandbox), in other words, it has greater attack surface in case a
   // vulnerability in Bubblewrap is found. In contrast, --tmpfs ensures a clean and isolated
   // environment with no chance of interaction with the host filesystem.
-  args.extend([
-    "--tmpfs",
-    "/var",
-    "--proc",
-    "proc",
-    "--tmpfs",
-    &runtime_env.home_dir,
-  ]);
+  builder
+    .group("temporary filesystems", "always")
+    .tmpfs("/var")
+    .op(BwrapOp::Proc("proc".into()))
+    .tmpfs(&runtime_env.home_dir)
+    .tmpfs("/tmp")
+    .tmpfs("/dev/shm");
+  // XDG_RUNTIME_DIR holds the sockets of every service of the session (e.g. ssh-agent, gpg-agent,
+  // keyring, portals or rootless Docker), so it starts empty and each permission binds the ones it
+  // needs on top of it. Being a tmpfs, programs can also create their own sockets e.g., gamescope.
+  builder
+    .group("runtime dir", "always")
+    .tmpfs(&runtime_env.xdg_runtime_dir);
+  if !sandbox_config.runtime_sockets.is_empty() {
+    let names: Vec<String> = sandbox_config
+      .runtime_sockets
+      .iter()
+      .map(|socket| socket.to_string())
+      .collect();
+    builder.group("runtime sockets", format!("--runtime-socket={}", names.join(",")));
+    for op in get_runtime_socket_ops(&sandbox_config.runtime_sockets, runtime_env, host)? {
+      builder.op(op);
+    }
+  }
+  // D-Bus gives access to services of the system (e.g. systemd or NetworkManager) and of the
+  // session (e.g. the secret service), with a filtering proxy the runtime env already points to
+  // the proxied sockets.
+  builder.group("dbus", format!("--dbus={}", sandbox_config.dbus));
+  let session_bus_path = dbus_proxy::bus_socket_path(&runtime_env.dbus_session_bus_address);
+  let session_bus_address = match sandbox_config.dbus {
+    DbusMode::None => None,
+    DbusMode::Full => {
+      builder.ro_bind_same("/run/dbus");
+      if let Some(bus_path) = session_bus_path
+        && host.path_exists(Path::new(bus_path))
+      {
+        builder.ro_bind_same(bus_path);
+      }
+      Some(runtime_env.dbus_session_bus_address.clone())
+    }
+    DbusMode::Filtered => {
+      let bus_path = session_bus_path.context("The D-Bus proxy socket is missing")?;
+      let inner_bus_path = format!("{}/bus", runtime_env.xdg_runtime_dir);
+      builder.ro_bind(bus_path, &inner_bus_path);
+      if let Some(system_bus_path) = &runtime_env.dbus_system_bus_socket {
+        builder.ro_bind(system_bus_path, dbus_proxy::SYSTEM_BUS_SOCKET);
+      }
+      Some(format!("unix:path={}", inner_bus_path))
+    }
+  };
   // Mount the directory that contains the Wine binaries and libraries (a.k.a. runner), the Wine
   // version to be mounted must be statically compiled in order to not rely on any host library
   // i.e. the runners downloaded by Bottles are statically compiled.
+  builder.group("wine", "--runner, --prefix");
   if let Some(runner_path) = &launch_config.runner_path {
-    args.extend([
-      "--tmpfs",
-      "/opt",
-      "--ro-bind",
-      runner_path.to_str().context("bad runner path")?,
-      INNER_WINE_ROOT,
-    ]);
+    builder
+      .tmpfs("/opt")
+      .ro_bind(runner_path.to_str().context("bad runner path")?, INNER_WINE_ROOT);
+    // Without X11, Wine falls back to its Wayland driver, which older runners do not ship.
+    let has_wayland_driver = WINE_WAYLAND_DRIVERS
+      .iter()
+      .any(|driver| host.path_exists(&runner_path.join(driver)));
+    if displays.x11.is_none() && !has_wayland_driver {
+      anyhow::bail!(
+        "The runner does not include the Wine Wayland driver, use --display-server=x11 instead"
+      );
+    }
   }
   // Prefix needs to be read-write because some dependencies may be installed or system files change
   // while wine is running, even changing the registry requires write access.
   if let Some(prefix_path) = &launch_config.prefix_path {
-    args.extend([
-      "--bind",
-      prefix_path.to_str().context("bad prefix path")?,
-      INNER_WINE_PREFIX,
-    ]);
+    builder.bind(prefix_path.to_str().context("bad prefix path")?, INNER_WINE_PREFIX);
+  }
+  // Gamescope is started by --gamescope, and by gamescope upscale modes.
+  let upscale_mode = launch_config
+    .upscale_mode
+    .as_ref()
+    .unwrap_or(&UpscaleMode::None);
+  let gamescope_upscale = match upscale_mode {
+    UpscaleMode::Gamescope { filter, sharpness } => Some((*filter, *sharpness)),
+    _ => None,
+  };
+  let gamescope = match (&launch_config.gamescope, gamescope_upscale) {
+    (Some(gamescope), _) => Some(gamescope.clone()),
+    (None, Some(_)) => Some(Gamescope::default()),
+    (None, None) => None,
+  };
+  // Gamescope renders through the GPU, without it gamescope fails once the sandbox is started.
+  if let (Some(_), DeviceAccess::Selected { classes, paths }) =
+    (&gamescope, &sandbox_config.device_access)
+    && !classes.contains(&DeviceClass::Gpu)
+    && !paths.iter().any(|path| path.starts_with("/dev/dri"))
+  {
+    anyhow::bail!(
+      "Gamescope needs the GPU, add gpu to --device-access={}",
+      sandbox_config.device_access
+    );
   }
   // Mount X11 socket to allow running GUI apps. Using the same X11 display number as the host
   // because using a different number will not work despite being the first recommendation in the
   // ArchWiki: https://wiki.archlinux.org/title/Bubblewrap#Using_X11.
-  let display = Display::from_str(&runtime_env.display_address)?;
-  let x11_socket = display.get_socket_path();
-  args.extend([
-    "--tmpfs",
-    "/tmp",
-    "--tmpfs",
-    "/dev/shm",
-    "--bind",
-    &x11_socket,
-    &x11_socket,
-    "--ro-bind",
-    &runtime_env.xauthority_file,
-    &runtime_env.xauthority_file,
-  ]);
+  // With a nested X server, the display and Xauthority of the runtime env are the nested ones.
+  if let Some(display_address) = displays.x11 {
+    let x11_socket = Display::from_str(display_address)?.get_socket_path();
+    let x11_reason = match sandbox_config.x11 {
+      X11Mode::Host => format!("DISPLAY={}", display_address),
+      x11 => format!("--x11={}, DISPLAY={}", x11, display_address),
+    };
+    builder
+      .group("x11", x11_reason)
+      .bind(&x11_socket, &x11_socket);
+    if let Some(xauthority_file) = &runtime_env.xauthority_file {
+      builder.ro_bind_same(xauthority_file);
+    }
+  }
+  // Only the Wayland socket is needed, the compositor shares buffers through file descriptors.
+  if let Some(wayland_socket) = &displays.wayland {
+    builder
+      .group("wayland", format!("WAYLAND_DISPLAY={}", wayland_socket))
+      .bind(wayland_socket, wayland_socket);
+  }
   // Clear env and set minimal required variables, we need to make sure that all needed variables
   // are being passed otherwise games may crash or have no sound.
-  args.extend([
-    "--clearenv",
-    "--setenv",
-    "HOME",
-    &runtime_env.home_dir,
-    "--setenv",
-    "XAUTHORITY",
-    &runtime_env.xauthority_file,
-    "--setenv",
-    "DBUS_SESSION_BUS_ADDRESS",
-    &runtime_env.dbus_session_bus_address,
-    "--setenv",
-    "XDG_RUNTIME_DIR",
-    &runtime_env.xdg_runtime_dir,
-    "--setenv",
-    "DISPLAY",
-    &runtime_env.display_address,
-    "--setenv",
-    "WINEPREFIX",
-    INNER_WINE_PREFIX,
-    "--setenv",
-    "WINEDLLOVERRIDES",
-    "winemenubuilder=''",
-    "--setenv",
-    "WINE_LARGE_ADDRESS_AWARE",
-    "1",
-  ]);
+  builder
+    .group("env", "always")
+    .op(BwrapOp::ClearEnv)
+    .setenv("HOME", &runtime_env.home_dir);
+  if let (Some(_), Some(xauthority_file)) = (displays.x11, &runtime_env.xauthority_file) {
+    builder.setenv("XAUTHORITY", xauthority_file);
+  }
+  if let Some(session_bus_address) = &session_bus_address {
+    builder.setenv("DBUS_SESSION_BUS_ADDRESS", session_bus_address);
+  }
+  builder.setenv("XDG_RUNTIME_DIR", &runtime_env.xdg_runtime_dir);
+  // Programs (including Wine) pick Wayland when DISPLAY is not set.
+  if let Some(display_address) = displays.x11 {
+    builder.setenv("DISPLAY", display_address);
+  }
+  if let Some(wayland_socket) = &displays.wayland {
+    builder.setenv("WAYLAND_DISPLAY", wayland_socket);
+  }
+  builder
+    .setenv("WINEPREFIX", INNER_WINE_PREFIX)
+    .setenv("WINEDLLOVERRIDES", "winemenubuilder=''")
+    .setenv("WINE_LARGE_ADDRESS_AWARE", "1");
   // Allow gamepad hotplugging, otherwise network access or --share-net would be required.
-  args.extend(["--setenv", "SDL_JOYSTICK_DISABLE_UDEV", "1"]);
+  builder.setenv("SDL_JOYSTICK_DISABLE_UDEV", "1");
   // Extend the PATH to have access to the Wine binaries without full paths.
   let new_path = format!("{}/bin:{}", INNER_WINE_ROOT, runtime_env.original_path);
-  args.extend(["--setenv", "PATH", &new_path]);
+  builder.setenv("PATH", new_path);
   // GPU cache is saved in the game directory by default, this is undesired because most of the time
   // this directory will be read-only, so put the caches under the prefix (Bottles does the same).
-  let gl_cache_path = format!("{}/cache/gl_shader", INNER_WINE_PREFIX);
-  let dxvk_cache_path = format!("{}/cache/dxvk_state", INNER_WINE_PREFIX);
-  let mesa_cache_path = format!("{}/cache/mesa_shader", INNER_WINE_PREFIX);
-  let vkd3d_cache_path = format!("{}/cache/vkd3d_shader", INNER_WINE_PREFIX);
-  args.extend([
-    "--setenv",
-    "__GL_SHADER_DISK_CACHE",
-    "1",
-    "--setenv",
-    "__GL_SHADER_DISK_CACHE_PATH",
-    &gl_cache_path,
-    "--setenv",
-    "DXVK_STATE_CACHE_PATH",
-    &dxvk_cache_path,
-    "--setenv",
-    "MESA_SHADER_CACHE_DIR",
-    &mesa_cache_path,
-    "--setenv",
-    "VKD3D_SHADER_CACHE_PATH",
-    &vkd3d_cache_path,
-  ]);
+  builder
+    .setenv("__GL_SHADER_DISK_CACHE", "1")
+    .setenv("__GL_SHADER_DISK_CACHE_PATH", format!("{}/cache/gl_shader", INNER_WINE_PREFIX))
+    .setenv("DXVK_STATE_CACHE_PATH", format!("{}/cache/dxvk_state", INNER_WINE_PREFIX))
+    .setenv("MESA_SHADER_CACHE_DIR", format!("{}/cache/mesa_shader", INNER_WINE_PREFIX))
+    .setenv("VKD3D_SHADER_CACHE_PATH", format!("{}/cache/vkd3d_shader", INNER_WINE_PREFIX));
+  // Configure audio, after the env group because it may set variables.
+  builder.group("audio", format!("--audio={}", sandbox_config.audio));
+  for op in get_audio_ops(sandbox_config.audio, runtime_env, host) {
+    builder.op(op);
+  }
+  // Render on the selected GPU, see the PRIME section of the README.
+  if sandbox_config.gpu != GpuSelection::None {
+    let gpu_reason = match selected_gpu {
+      Some(gpu) => format!("--gpu={} ({})", sandbox_config.gpu, gpu.pci_slot),
+      None => format!("--gpu={} (no GPU found)", sandbox_config.gpu),
+    };
+    builder.group("gpu", gpu_reason);
+    if let Some(gpu) = selected_gpu {
+      for (key, value) in gpu::get_gpu_env(gpu, &gpus) {
+        builder.setenv(key, value);
+      }
+    }
+  }
   // Configure upscale mode.
-  let fsr_mode: String;
-  let fsr_strength: String;
-  match &launch_config.upscale_mode {
-    None | Some(UpscaleMode::None) => (),
-    Some(UpscaleMode::Fsr { mode, strength }) => {
-      fsr_mode = mode.to_string();
-      fsr_strength = strength.to_string();
-      args.extend([
-        "--setenv",
-        "WINE_FULLSCREEN_FSR",
-        "1",
-        "--setenv",
-        "WINE_FULLSCREEN_FSR_MODE",
-        &fsr_mode,
-        "--setenv",
-        "WINE_FULLSCREEN_FSR_STRENGTH",
-        &fsr_strength,
-      ]);
-    }
-    Some(UpscaleMode::Dlss) => {
-      args.extend([
-        "--setenv",
-        "DXVK_NVAPIHACK",
-        "0",
-        "--setenv",
-        "DXVK_ENABLE_NVAPI",
-        "1",
-      ]);
+  builder.group("upscale", format!("--upscale-mode={}", upscale_mode));
+  match upscale_mode {
+    UpscaleMode::None => (),
+    UpscaleMode::Fsr { mode, strength } => {
+      builder
+        .setenv("WINE_FULLSCREEN_FSR", "1")
+        .setenv("WINE_FULLSCREEN_FSR_MODE", mode.to_string())
+        .setenv("WINE_FULLSCREEN_FSR_STRENGTH", strength.to_string());
+    }
+    UpscaleMode::Dlss => {
+      builder
+        .setenv("DXVK_NVAPIHACK", "0")
+        .setenv("DXVK_ENABLE_NVAPI", "1");
     }
+    UpscaleMode::Gamescope { .. } => (), // Passed as gamescope options
   }
   // Configure Wine sync mode, only one mode can be set at time.
-  match launch_config.sync_mode {
-    None | Some(SyncMode::None) => (),
-    Some(SyncMode::Fsync) => {
-      args.extend(["--setenv", "WINEFSYNC", "1"]); // Default for soda runner
+  let sync_reason = match requested_sync_mode {
+    SyncMode::Auto => format!("--sync-mode=auto ({})", sync_mode),
+    mode => format!("--sync-mode={}", mode),
+  };
+  builder.group("sync", sync_reason);
+  match sync_mode {
+    SyncMode::None | SyncMode::Auto => (),
+    SyncMode::Ntsync => {
+      // WINENTSYNC is read by wine-tkg and PROTON_USE_NTSYNC by Proton, upstream Wine uses the
+      // device whenever it is available. Other modes are disabled as runners may enable them.
+      builder
+        .setenv("WINENTSYNC", "1")
+        .setenv("PROTON_USE_NTSYNC", "1")
+        .setenv("WINEFSYNC", "0")
+        .setenv("WINEESYNC", "0");
     }
-    Some(SyncMode::Esync) => {
-      args.extend(["--setenv", "WINEESYNC", "1"]);
+    SyncMode::Fsync => {
+      builder.setenv("WINEFSYNC", "1"); // Default for soda runner
+    }
+    SyncMode::Esync => {
+      builder.setenv("WINEESYNC", "1");
     }
   }
   // Configure verbosity.
   if !sandbox_config.verbose {
-    args.extend([
-      "--setenv",
-      "WINEDEBUG",
-      "fixme-all",
-      "--setenv",
-      "DXVK_LOG_LEVEL",
-      "warn",
-    ]);
+    builder
+      .group("verbosity", "no --verbose")
+      .setenv("WINEDEBUG", "fixme-all")
+      .setenv("DXVK_LOG_LEVEL", "warn");
   }
   // Set custom environment variables overrides. If there are 2 variables with the same name set by
   // --setenv, bwrap will use the rightmost one.
+  builder.group("env overrides", "--setenv");
   if let Some(env_overrides) = &runtime_env.overrides {
-    for (key, value) in env_overrides.into_iter() {
-      args.extend(["--setenv", &key, &value])
+    for (key, value) in env_overrides {
+      builder.setenv(key, value);
     }
   }
-  // Method return contains a Vec<String> because it needs to own each element, we initially declare
-  // args as Vec<&str> to make it easier to add elements (so we avoid String::from() or .into() on
-  // each element), however args still needs to be converted to Vec<String> at the end.
-  let mut final_args: Vec<String> = args.into_iter().map(String::from).collect();
-  let term = env::var("TERM").unwrap_or("xterm-256color".into());
-  let shell = env::var("SHELL").unwrap_or("bash".into());
-  let shell_params: Vec<String> = vec!["--setenv".into(), "TERM".into(), term, shell];
   // Additional mounts.
-  let mount_args = get_mount_args(mount_mappings);
-  final_args.extend(mount_args);
+  builder.group("user volumes", "--volume");
+  for op in get_mount_ops(mount_mappings) {
+    builder.op(op);
+  }
+  let term = host.env_var("TERM").unwrap_or("xterm-256color".into());
+  let shell = host.env_var("SHELL").unwrap_or("bash".into());
   // Depending on the launch params, add the necessary arguments to start a regular shell or execute
   // the specified command.
+  let mut command: Vec<String> = Vec::new();
   match &launch_config.launch_params {
     LaunchParams::Unconfigured => {
       // No launch params, so start with a regular shell.
-      final_args.extend(["--chdir".into(), "/".into()]);
-      final_args.extend(shell_params);
+      builder
+        .group("launch", "no --appdir, starting a shell")
+        .chdir("/")
+        .setenv("TERM", term);
+      command.push(shell);
     }
     LaunchParams::Configured {
       read_only,
@@ -358,69 +766,1263 @@ fn build_args(
       app_bin,
       app_args,
     } => {
+      let launch_reason = match app_bin {
+        Some(app_bin) => format!("--appdir={}, --appbin={}", app_dir, app_bin),
+        None => format!("--appdir={}, no --appbin, starting a shell", app_dir),
+      };
+      builder.group("launch", launch_reason);
       // Most games can work without issues when mounted as read-only. This also prevents polluting
       // the game directory. Also, setting the working directory is important for many games.
-      final_args.extend([
-        if *read_only {
-          "--ro-bind".into()
-        } else {
-          "--bind".into()
-        },
-        app_dir.into(),
-        INNER_APP_DIR.into(),
-        "--chdir".into(),
-        INNER_APP_DIR.into(),
-      ]);
+      builder
+        .op(BwrapOp::Bind {
+          src: app_dir.into(),
+          dst: INNER_APP_DIR.into(),
+          ro: *read_only,
+        })
+        .chdir(INNER_APP_DIR);
       if let Some(app_bin) = app_bin {
         let bin_buf = PathBuf::from(INNER_APP_DIR).join(app_bin);
         let bin_path = bin_buf
           .to_str()
           .with_context(|| format!("Invalid path: {}", bin_buf.to_string_lossy()))?;
         if launch_config.launch_params.is_windows_binary() {
-          final_args.push("wine".into());
+          command.push("wine".into());
         }
-        final_args.push(bin_path.into());
-        final_args.extend(app_args.to_owned());
+        command.push(bin_path.into());
+        command.extend(app_args.to_owned());
       } else {
         // Only app_dir was set (not app_bin), so start with default shell (useful for maintenance).
-        final_args.extend(shell_params);
+        builder.setenv("TERM", term);
+        command.push(shell);
       }
     }
   }
-  Ok(final_args)
+  // Keep the sandbox alive after the command exits, if requested. Processes are found through
+  // /proc, which only shows the ones of the sandbox with its own PID namespace; otherwise a process
+  // of the host with the same name (e.g. another copy of the game) would keep it alive.
+  if let WaitMode::Process { .. } = launch_config.wait_mode
+    && !sandbox_config.namespace_isolation
+  {
+    anyhow::bail!(
+      "--wait={} needs the PID namespace of the sandbox, it cannot be used with --no-namespace-isolation",
+      launch_config.wait_mode
+    );
+  }
+  let (wait_ops, command) = launch_config.wait_mode.wrap_command(command);
+  if launch_config.wait_mode != WaitMode::App {
+    builder.group("wait", format!("--wait={}", launch_config.wait_mode));
+    for op in wait_ops {
+      builder.op(op);
+    }
+  }
+  // Gamescope must be the outermost process, so detached processes kept by --wait are shown too.
+  let command = match &gamescope {
+    Some(gamescope) => gamescope.wrap_command(gamescope_upscale, command),
+    None => command,
+  };
+  Ok(builder.build(command))
+}
+
+/// First status reported by bwrap through `--json-status-fd`, once the sandboxed program started.
+/// It holds the same `child-pid` as `--info-fd`, which is therefore not passed.
+#[derive(Deserialize)]
+struct ChildStatus {
+  #[serde(rename = "child-pid")]
+  child_pid: u32,
 }
 
-/// Execute a program under a restricted Bubblewrap container, the output will be inherited by the
-/// current terminal and printed in real-time. See detailed parameter information at
-/// https://man.archlinux.org/man/extra/bubblewrap/bwrap.1.en.
+/// Read the PID of the sandboxed program from the bwrap status stream, blocks until the program is
+/// started. Returns `None` if bwrap exits before starting it.
+fn read_child_pid(status: &mut impl BufRead) -> Option<u32> {
+  let mut line = String::new();
+  status.read_line(&mut line).ok()?;
+  serde_json::from_str::<ChildStatus>(&line)
+    .ok()
+    .map(|status| status.child_pid)
+}
+
+/// Start a program under a restricted Bubblewrap container without waiting for it, the output will
+/// be inherited by the current terminal and printed in real-time. See detailed parameter information
+/// at https://man.archlinux.org/man/extra/bubblewrap/bwrap.1.en.
+/// The sandbox is recorded in the instance registry (see `Registry`) under the given `name` for as
+/// long as it runs.
 /// **NOTE:** keep in mind that even if runners (Wine custom builds downloaded through Bottles) are
 /// statically compiled, it does not mean they will run without additional dependencies, they are
 /// kinda independent of glibc and similar lower level stuff, however they still need the OS to
 /// provide the right dependencies, otherwise not even `notepad.exe` will run, to install these
 /// dependencies, just install `steam-native-runtime` on Arch/Manjaro.
-pub fn run(
+pub fn spawn(
+  name: &str,
   sandbox_config: &SandboxConfig,
   launch_config: &LaunchConfig,
   runtime_env: &RuntimeEnv,
   mount_mappings: &[MountMapping],
-) -> anyhow::Result<()> {
-  // Temporary file will be automatically removed when variable goes out of scope.
+) -> anyhow::Result<SandboxChild> {
+  // Temporary file will be automatically removed when the returned handle is dropped.
   let temp_file = NamedTempFile::new()?;
   let temp_file_path = temp_file
     .path()
     .to_str()
     .context("Could not get temporary file path")?;
-  let args =
-    build_args(sandbox_config, launch_config, runtime_env, mount_mappings, temp_file_path)?;
-  let mut cmd = Command::new("bwrap")
-    .args(args)
+  let bwrap_version = host::detect_bwrap_version();
+  let userns = UsernsBlocking::resolve(sandbox_config, bwrap_version);
+  let deny_userns = userns == UsernsBlocking::Seccomp;
+  // Only needs to exist until bwrap reads it, the child inherits its own descriptor.
+  let seccomp_file = match seccomp::compile(sandbox_config.seccomp, deny_userns)? {
+    Some(program) => Some(seccomp::write_filter(&program)?),
+    None => None,
+  };
+  let seccomp_fd = seccomp_file.as_ref().map(|file| file.as_raw_fd());
+  let host = SystemHost::new(
+    temp_file_path,
+    seccomp_fd.map(|fd| fd.to_string()).unwrap_or_default(),
+    bwrap_version,
+  );
+  // The nested X server replaces the one of the session, and must outlive the sandbox.
+  let nested_server = match sandbox_config.x11 {
+    X11Mode::Nested { size } => Some(NestedServer::start(size, runtime_env)?),
+    X11Mode::Host => None,
+  };
+  // Otherwise, the X server of the session is used with an untrusted cookie if requested and supported.
+  let untrusted_xauthority = match &runtime_env.display_address {
+    Some(display_address) if uses_untrusted_cookie(sandbox_config, runtime_env) => {
+      match xauth::generate_untrusted(display_address) {
+        Ok(xauthority) => Some(xauthority),
+        Err(e) => {
+          eprintln!("Could not generate an untrusted X11 cookie, using the session one: {:#}", e);
+          None
+        }
+      }
+    }
+    _ => None,
+  };
+  let sandbox_env = match (&nested_server, &untrusted_xauthority) {
+    (Some(server), _) => Some(server.runtime_env(runtime_env)?),
+    (None, Some(xauthority)) => Some(RuntimeEnv {
+      xauthority_file: Some(
+        xauthority
+          .path()
+          .to_str()
+          .context("Could not get Xauthority path")?
+          .into(),
+      ),
+      ..runtime_env.clone()
+    }),
+    (None, None) => None,
+  };
+  let runtime_env = sandbox_env.as_ref().unwrap_or(runtime_env);
+  // Same for the sockets of the D-Bus proxy, which must also outlive the sandbox.
+  let dbus_proxy = match sandbox_config.dbus {
+    DbusMode::Filtered => Some(DbusProxy::start(&sandbox_config.dbus_allow, runtime_env)?),
+    DbusMode::None | DbusMode::Full => None,
+  };
+  let proxied_env = dbus_proxy
+    .as_ref()
+    .map(|proxy| proxy.runtime_env(runtime_env));
+  let runtime_env = proxied_env.as_ref().unwrap_or(runtime_env);
+  let args = build_args(sandbox_config, launch_config, runtime_env, mount_mappings, &host)?;
+  if sandbox_config.verbose {
+    let exposed = args.binds_under(&runtime_env.xdg_runtime_dir);
+    eprintln!(
+      "Exposed from XDG_RUNTIME_DIR ({}): {}",
+      runtime_env.xdg_runtime_dir,
+      if exposed.is_empty() {
+        "nothing".into()
+      } else {
+        exposed.join(", ")
+      }
+    );
+    eprintln!("Nested user namespaces: {}", userns.describe(sandbox_config, bwrap_version));
+    if seccomp_fd.is_some() {
+      eprintln!(
+        "Denied by the seccomp filter: {}",
+        seccomp::denied_syscalls(sandbox_config.seccomp, deny_userns).join(", ")
+      );
+    }
+  }
+  // The status pipe is created with O_CLOEXEC, it must be cleared for bwrap to inherit the write end,
+  // same for the seccomp filter.
+  let (status_reader, status_writer) = io::pipe().context("Could not create status pipe")?;
+  let status_fd = status_writer.as_raw_fd();
+  let mut command = Command::new("bwrap");
+  command
+    .arg("--json-status-fd")
+    .arg(status_fd.to_string())
+    .args(args.to_vec())
     .stdout(Stdio::inherit())
-    .stderr(Stdio::inherit())
+    .stderr(Stdio::inherit());
+  // SAFETY: fcntl is async-signal-safe, and the closure does not allocate.
+  unsafe {
+    command.pre_exec(move || {
+      for fd in [Some(status_fd), seccomp_fd].into_iter().flatten() {
+        if libc::fcntl(fd, libc::F_SETFD, 0) == -1 {
+          return Err(io::Error::last_os_error());
+        }
+      }
+      Ok(())
+    });
+  }
+  let child = command
     .spawn()
     .map_err(|e| anyhow::anyhow!("Could not spawn bwrap: {}", e))?;
-  let status = cmd.wait()?;
+  // Only bwrap must hold the write end, otherwise reading would never reach EOF if bwrap fails.
+  drop(status_writer);
+  drop(seccomp_file);
+  let status = BufReader::new(status_reader);
+  let mut sandbox_child = SandboxChild::new(child, temp_file, status);
+  if let Some(server) = nested_server {
+    sandbox_child.set_nested_server(server);
+  }
+  if let Some(xauthority) = untrusted_xauthority {
+    sandbox_child.set_xauthority(xauthority);
+  }
+  if let Some(proxy) = dbus_proxy {
+    sandbox_child.set_dbus_proxy(proxy);
+  }
+  let Some(child_pid) = read_child_pid(&mut sandbox_child.status) else {
+    return Ok(sandbox_child);
+  };
+  let instance = Instance {
+    id: Instance::new_id(),
+    name: name.into(),
+    pid: sandbox_child.id(),
+    child_pid,
+    started_at: Instance::now(),
+    network_mode: sandbox_config.network_mode.clone(),
+    seccomp: sandbox_config.seccomp,
+    deny_userns,
+    runner: launch_config.runner_path.clone(),
+    prefix: launch_config.prefix_path.clone(),
+    environment: args.environment(),
+    working_dir: args.working_dir().unwrap_or("/").into(),
+  };
+  // The sandbox is already running at this point, so failing to register it is not fatal.
+  let registry = Registry::new(&runtime_env.xdg_runtime_dir);
+  match registry.register(&instance) {
+    Ok(()) => sandbox_child.set_registration(registry, instance),
+    Err(e) => eprintln!("Could not register sandbox instance: {:#}", e),
+  }
+  Ok(sandbox_child)
+}
+
+/// Same as `spawn`, but blocks until the program exits.
+pub fn run(
+  name: &str,
+  sandbox_config: &SandboxConfig,
+  launch_config: &LaunchConfig,
+  runtime_env: &RuntimeEnv,
+  mount_mappings: &[MountMapping],
+) -> anyhow::Result<()> {
+  let mut child = spawn(name, sandbox_config, launch_config, runtime_env, mount_mappings)?;
+  let status = child.wait()?;
   if status.success() {
     return Ok(());
   }
   Err(anyhow::anyhow!("The bwrap command exited with non-zero exit code"))
 }
+
+/// Resolve the bwrap command line that `run` would execute, without executing it.
+pub fn explain(
+  sandbox_config: &SandboxConfig,
+  launch_config: &LaunchConfig,
+  runtime_env: &RuntimeEnv,
+  mount_mappings: &[MountMapping],
+  format: OutputFormat,
+) -> anyhow::Result<String> {
+  let host =
+    SystemHost::new(EMPTY_FILE_PLACEHOLDER, SECCOMP_FD_PLACEHOLDER, host::detect_bwrap_version());
+  let args =
+    build_explained_args(sandbox_config, launch_config, runtime_env, mount_mappings, &host)?;
+  Ok(match format {
+    OutputFormat::Text => explain::format_text(&args),
+    OutputFormat::Shell => explain::format_shell(&args)?,
+  })
+}
+
+/// Build the arguments with placeholders for everything that is only set up when running.
+fn build_explained_args(
+  sandbox_config: &SandboxConfig,
+  launch_config: &LaunchConfig,
+  runtime_env: &RuntimeEnv,
+  mount_mappings: &[MountMapping],
+  host: &dyn HostProbe,
+) -> anyhow::Result<BwrapArgs> {
+  // The nested X server is not started, so show the display it would probably get. Same for the
+  // untrusted cookie, which is not generated.
+  let sandbox_env = match sandbox_config.x11 {
+    X11Mode::Nested { .. } => Some(RuntimeEnv {
+      display_address: Some(Display::local(nested_x11::guess_display_number()).to_string()),
+      xauthority_file: Some(NESTED_XAUTHORITY_PLACEHOLDER.into()),
+      ..runtime_env.clone()
+    }),
+    X11Mode::Host if uses_untrusted_cookie(sandbox_config, runtime_env) => Some(RuntimeEnv {
+      xauthority_file: Some(UNTRUSTED_XAUTHORITY_PLACEHOLDER.into()),
+      ..runtime_env.clone()
+    }),
+    X11Mode::Host => None,
+  };
+  let runtime_env = sandbox_env.as_ref().unwrap_or(runtime_env);
+  // Neither is the D-Bus proxy.
+  let proxied_env = match sandbox_config.dbus {
+    DbusMode::Filtered => Some(RuntimeEnv {
+      dbus_session_bus_address: format!("unix:path={}", SESSION_BUS_PROXY_PLACEHOLDER),
+      dbus_system_bus_socket: runtime_env
+        .dbus_system_bus_socket
+        .as_ref()
+        .filter(|socket| host.path_exists(Path::new(socket)))
+        .map(|_| SYSTEM_BUS_PROXY_PLACEHOLDER.into()),
+      ..runtime_env.clone()
+    }),
+    DbusMode::None | DbusMode::Full => None,
+  };
+  let runtime_env = proxied_env.as_ref().unwrap_or(runtime_env);
+  build_args(sandbox_config, launch_config, runtime_env, mount_mappings, host)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::sandbox::gpu::{Gpu, GpuVendor};
+  use crate::sandbox::hidraw::{HidrawDevice, UsbId};
+  use crate::sandbox::mount::MountConfig;
+  use crate::sandbox::user_mapping::UserMapping;
+  use std::collections::HashMap;
+  use std::path::Path;
+  use std::{env, fs};
+
+  /// Golden file with the expected output of every combination, regenerate it by running the tests
+  /// with `UPDATE_SNAPSHOTS=1` and review the changes before committing them.
+  const SNAPSHOT_PATH: &str =
+    concat!(env!("CARGO_MANIFEST_DIR"), "/src/sandbox/snapshots/build_args.txt");
+
+  struct FakeHost;
+
+  impl HostProbe for FakeHost {
+    fn char_devices(&self) -> anyhow::Result<Vec<String>> {
+      Ok(vec![
+        "/dev/hidraw0".into(),
+        "/dev/hidraw1".into(),
+        "/dev/null".into(),
+        "/dev/nvidia0".into(),
+        "/dev/nvidiactl".into(),
+        "/dev/video0".into(),
+      ])
+    }
+
+    /// A hybrid laptop, with the NVIDIA GPU rendering through PRIME offload.
+    fn gpus(&self) -> anyhow::Result<Vec<Gpu>> {
+      Ok(vec![
+        Gpu {
+          pci_slot: "0000:00:02.0".into(),
+          vendor: GpuVendor::Intel,
+          driver: Some("i915".into()),
+          card: "/dev/dri/card0".into(),
+          render_node: Some("/dev/dri/renderD128".into()),
+          nvidia_minor: None,
+          boot_vga: true,
+          integrated: true,
+        },
+        Gpu {
+          pci_slot: "0000:01:00.0".into(),
+          vendor: GpuVendor::Nvidia,
+          driver: Some("nvidia".into()),
+          card: "/dev/dri/card1".into(),
+          render_node: Some("/dev/dri/renderD129".into()),
+          nvidia_minor: Some(0),
+          boot_vga: false,
+          integrated: false,
+        },
+      ])
+    }
+
+    /// A security key and a DualSense.
+    fn hidraw_devices(&self) -> Vec<HidrawDevice> {
+      vec![
+        HidrawDevice {
+          path: "/dev/hidraw0".into(),
+          id: UsbId {
+            vendor: 0x1050,
+            product: 0x0407,
+          },
+        },
+        HidrawDevice {
+          path: "/dev/hidraw1".into(),
+          id: UsbId {
+            vendor: 0x054c,
+            product: 0x0ce6,
+          },
+        },
+      ]
+    }
+
+    fn env_var(&self, name: &str) -> Option<String> {
+      match name {
+        "TERM" => Some("xterm".into()),
+        "SHELL" => Some("/bin/bash".into()),
+        _ => None,
+      }
+    }
+
+    /// Only the soda runner ships the Wine Wayland driver, the session bus and the audio servers
+    /// are running, the ntsync module and the ALSA devices are loaded.
+    fn path_exists(&self, path: &Path) -> bool {
+      let path = path.to_string_lossy();
+      path.starts_with("/runners/soda/")
+        || path == "/run/user/1000/bus"
+        || path == "/run/user/1000/gamemode"
+        || path.starts_with("/run/user/1000/pulse")
+        || path.starts_with("/run/user/1000/pipewire-0")
+        || path == "/home/user/.config/pulse/cookie"
+        || path == "/dev/ntsync"
+        || path == "/dev/snd"
+    }
+
+    fn dir_entries(&self, path: &Path) -> Vec<String> {
+      match path.to_string_lossy().as_ref() {
+        VULKAN_ICD_DIR => vec![
+          "intel_hasvk_icd.x86_64.json".into(),
+          "intel_icd.x86_64.json".into(),
+          "lvp_icd.x86_64.json".into(),
+          "nvidia_icd.json".into(),
+          "radeon_icd.x86_64.json".into(),
+        ],
+        _ => Vec::new(),
+      }
+    }
+
+    fn hostname(&self) -> String {
+      "sandbox".into()
+    }
+
+    fn empty_file_path(&self) -> &str {
+      EMPTY_FILE_PLACEHOLDER
+    }
+
+    fn seccomp_fd(&self) -> &str {
+      SECCOMP_FD_PLACEHOLDER
+    }
+
+    fn bwrap_version(&self) -> Option<BwrapVersion> {
+      Some((0, 11, 0))
+    }
+
+    fn kernel_version(&self) -> Option<KernelVersion> {
+      Some((6, 14))
+    }
+
+    /// The soda runner supports Fsync and Esync, the ntsync runner only Ntsync.
+    fn file_contains(&self, path: &Path, needle: &[u8]) -> bool {
+      match path.to_string_lossy().as_ref() {
+        "/runners/soda/bin/wineserver" => [&b"WINEFSYNC"[..], b"WINEESYNC"].contains(&needle),
+        "/runners/ntsync/bin/wineserver" => needle == b"/dev/ntsync",
+        _ => false,
+      }
+    }
+  }
+
+  fn runtime_env() -> RuntimeEnv {
+    RuntimeEnv {
+      home_dir: "/home/user".into(),
+      dbus_session_bus_address: "unix:path=/run/user/1000/bus".into(),
+      dbus_system_bus_socket: Some("/run/dbus/system_bus_socket".into()),
+      xdg_runtime_dir: "/run/user/1000".into(),
+      original_path: "/usr/bin:/bin".into(),
+      display_address: Some(":0".into()),
+      xauthority_file: Some("/run/user/1000/xauth".into()),
+      wayland_display: None,
+      overrides: Some(HashMap::from([("MANGOHUD".into(), "1".into())])),
+    }
+  }
+
+  fn launch_params() -> Vec<(&'static str, LaunchParams)> {
+    vec![
+      ("unconfigured", LaunchParams::Unconfigured),
+      ("appdir", LaunchParams::configured(true, "/games/app".into(), None, None)),
+      (
+        "windows binary",
+        LaunchParams::configured(
+          false,
+          "/games/app".into(),
+          Some("bin/game.exe".into()),
+          Some(vec!["--windowed".into()]),
+        ),
+      ),
+      (
+        "native binary",
+        LaunchParams::configured(true, "/games/app".into(), Some("game".into()), None),
+      ),
+    ]
+  }
+
+  fn build(
+    sandbox_config: &SandboxConfig,
+    launch_config: &LaunchConfig,
+  ) -> anyhow::Result<BwrapArgs> {
+    build_with_env(sandbox_config, launch_config, &runtime_env())
+  }
+
+  fn build_with_env(
+    sandbox_config: &SandboxConfig,
+    launch_config: &LaunchConfig,
+    runtime_env: &RuntimeEnv,
+  ) -> anyhow::Result<BwrapArgs> {
+    let mount_mappings = vec![MountMapping {
+      source_path: "/data".into(),
+      target_config: MountConfig {
+        path: "/mnt/data".into(),
+        writable: true,
+      },
+    }];
+    build_args(sandbox_config, launch_config, runtime_env, &mount_mappings, &FakeHost)
+  }
+
+  fn render_matrix() -> String {
+    let mut sections: Vec<String> = Vec::new();
+    for network_mode in ["full_access", "restricted_access", "no_access"] {
+      for device_access in ["all", "minimal"] {
+        for upscale_mode in ["none", "dlss", "fsr:balanced:2"] {
+          for sync_mode in ["none", "fsync", "esync"] {
+            for (launch_name, launch_params) in launch_params() {
+              let sandbox_config = SandboxConfig {
+                user_mapping: UserMapping::Custom(300_000, 300_000),
+                network_mode: network_mode.parse().unwrap(),
+                device_access: device_access.parse().unwrap(),
+                ..Default::default()
+              };
+              let launch_config = LaunchConfig {
+                runner_path: Some("/runners/soda".into()),
+                prefix_path: Some("/bottles/game".into()),
+                launch_params,
+                upscale_mode: Some(upscale_mode.parse().unwrap()),
+                sync_mode: Some(sync_mode.parse().unwrap()),
+                wait_mode: WaitMode::App,
+                gamescope: None,
+              };
+              let args = build(&sandbox_config, &launch_config).unwrap();
+              sections.push(format!(
+                "### network={} devices={} upscale={} sync={} launch={}\n{}\n",
+                network_mode,
+                device_access,
+                upscale_mode,
+                sync_mode,
+                launch_name,
+                explain::format_text(&args)
+              ));
+            }
+          }
+        }
+      }
+    }
+    sections.join("\n")
+  }
+
+  #[test]
+  fn test_build_args_snapshot() {
+    let actual = render_matrix();
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+      fs::write(SNAPSHOT_PATH, &actual).unwrap();
+      return;
+    }
+    let expected = fs::read_to_string(SNAPSHOT_PATH)
+      .expect("Missing snapshot, run the tests with UPDATE_SNAPSHOTS=1 to create it");
+    // Report the first difference along with the combination it belongs to, comparing the whole
+    // output at once would print thousands of lines.
+    let mut header = "";
+    for (line_number, (actual_line, expected_line)) in
+      actual.lines().zip(expected.lines()).enumerate()
+    {
+      if expected_line.starts_with("### ") {
+        header = expected_line;
+      }
+      assert_eq!(
+        actual_line,
+        expected_line,
+        "Snapshot mismatch at line {} ({}), run the tests with UPDATE_SNAPSHOTS=1 if intended",
+        line_number + 1,
+        header
+      );
+    }
+    assert_eq!(actual.lines().count(), expected.lines().count(), "Snapshot length mismatch");
+  }
+
+  #[test]
+  fn test_isolation_flags() {
+    let launch_config = LaunchConfig::default();
+    let no_access = SandboxConfig::default();
+    let ops: Vec<BwrapOp> = build(&no_access, &launch_config)
+      .unwrap()
+      .ops()
+      .cloned()
+      .collect();
+    assert!(ops.contains(&BwrapOp::Unshare(Namespace::Net)));
+    assert!(ops.contains(&BwrapOp::Unshare(Namespace::User)));
+    assert!(ops.contains(&BwrapOp::DieWithParent));
+    let full_access = SandboxConfig {
+      network_mode: NetworkMode::FullAccess,
+      namespace_isolation: false,
+      ..Default::default()
+    };
+    let ops: Vec<BwrapOp> = build(&full_access, &launch_config)
+      .unwrap()
+      .ops()
+      .cloned()
+      .collect();
+    assert!(!ops.contains(&BwrapOp::Unshare(Namespace::Net)));
+    assert!(!ops.contains(&BwrapOp::Unshare(Namespace::User)));
+    assert!(ops.contains(&BwrapOp::DieWithParent));
+  }
+
+  #[test]
+  fn test_wait_mode() {
+    let launch_config = LaunchConfig {
+      launch_params: LaunchParams::configured(
+        true,
+        "/games/app".into(),
+        Some("game.exe".into()),
+        None,
+      ),
+      wait_mode: WaitMode::Wineserver,
+      ..Default::default()
+    };
+    let args = build(&SandboxConfig::default(), &launch_config).unwrap();
+    let group = args.groups.last().unwrap();
+    assert_eq!((group.name, group.reason.as_str()), ("wait", "--wait=wineserver"));
+    assert_eq!(args.command[..2], ["sh", "-c"]);
+    assert_eq!(args.command[3..], ["sh", "wine", "/app/game.exe"]);
+    // Without a PID namespace, processes of the host would be waited for too.
+    let launch_config = LaunchConfig {
+      wait_mode: "process:HC2.exe:30".parse().unwrap(),
+      ..launch_config
+    };
+    assert!(build(&SandboxConfig::default(), &launch_config).is_ok());
+    let no_isolation = SandboxConfig {
+      namespace_isolation: false,
+      ..Default::default()
+    };
+    assert!(build(&no_isolation, &launch_config).is_err());
+  }
+
+  #[test]
+  fn test_gamescope() {
+    let launch_config = LaunchConfig {
+      launch_params: LaunchParams::configured(
+        true,
+        "/games/app".into(),
+        Some("game.exe".into()),
+        None,
+      ),
+      wait_mode: WaitMode::Wineserver,
+      gamescope: Some("-w 1280 -h 720".parse().unwrap()),
+      ..Default::default()
+    };
+    let args = build(&SandboxConfig::default(), &launch_config).unwrap();
+    // Gamescope wraps the wait script, which wraps the game.
+    assert_eq!(args.command[..6], ["gamescope", "-w", "1280", "-h", "720", "--"]);
+    assert_eq!(args.command[6..8], ["sh", "-c"]);
+    assert_eq!(args.command.last().unwrap(), "/app/game.exe");
+    // Gamescope upscale modes start gamescope on their own.
+    let launch_config = LaunchConfig {
+      upscale_mode: Some("gamescope:fsr:3".parse().unwrap()),
+      ..Default::default()
+    };
+    let args = build(&SandboxConfig::default(), &launch_config).unwrap();
+    assert_eq!(
+      args.command,
+      [
+        "gamescope",
+        "--filter",
+        "fsr",
+        "--sharpness",
+        "3",
+        "--",
+        "/bin/bash"
+      ]
+    );
+    assert!(!args.environment().contains_key("WINE_FULLSCREEN_FSR"));
+    // Without the GPU, gamescope would fail once started.
+    let sandbox_config = SandboxConfig {
+      device_access: "input,sound".parse().unwrap(),
+      ..Default::default()
+    };
+    let error = build(&sandbox_config, &launch_config).unwrap_err();
+    assert_eq!(
+      error.to_string(),
+      "Gamescope needs the GPU, add gpu to --device-access=input,sound"
+    );
+    let sandbox_config = SandboxConfig {
+      device_access: "input,sound,/dev/dri".parse().unwrap(),
+      ..Default::default()
+    };
+    assert!(build(&sandbox_config, &launch_config).is_ok());
+  }
+
+  #[test]
+  fn test_display_servers() {
+    let wayland_session = RuntimeEnv {
+      wayland_display: Some("wayland-0".into()),
+      ..runtime_env()
+    };
+    let wayland_bind = BwrapOp::Bind {
+      src: "/run/user/1000/wayland-0".into(),
+      dst: "/run/user/1000/wayland-0".into(),
+      ro: false,
+    };
+    let build_for = |display_server: &str, runner: &str| {
+      let sandbox_config = SandboxConfig {
+        display_server: display_server.parse().unwrap(),
+        ..Default::default()
+      };
+      let launch_config = LaunchConfig {
+        runner_path: Some(runner.into()),
+        prefix_path: Some("/bottles/game".into()),
+        ..Default::default()
+      };
+      build_with_env(&sandbox_config, &launch_config, &wayland_session)
+    };
+    let args = build_for("wayland", "/runners/soda").unwrap();
+    let ops: Vec<&BwrapOp> = args.ops().collect();
+    assert!(ops.contains(&&wayland_bind));
+    assert!(ops.contains(&&BwrapOp::Unshare(Namespace::Ipc)));
+    let environment = args.environment();
+    assert_eq!(environment["WAYLAND_DISPLAY"], "/run/user/1000/wayland-0");
+    assert!(!environment.contains_key("DISPLAY"));
+    assert!(!environment.contains_key("XAUTHORITY"));
+    // Without X11, Wine needs its Wayland driver.
+    assert!(build_for("wayland", "/runners/old").is_err());
+    let args = build_for("x11", "/runners/old").unwrap();
+    assert!(!args.ops().any(|op| *op == wayland_bind));
+    assert!(!args.ops().any(|op| *op == BwrapOp::Unshare(Namespace::Ipc)));
+    assert_eq!(args.environment()["DISPLAY"], ":0");
+    let args = build_for("auto", "/runners/old").unwrap();
+    assert!(args.ops().any(|op| *op == wayland_bind));
+    assert!(args.environment().contains_key("DISPLAY"));
+    // Nothing to connect to.
+    let headless = RuntimeEnv {
+      display_address: None,
+      ..runtime_env()
+    };
+    let result = build_with_env(&SandboxConfig::default(), &LaunchConfig::default(), &headless);
+    assert!(result.is_err());
+  }
+
+  #[test]
+  fn test_nested_x11() {
+    // The caller replaces the display and Xauthority with the ones of the nested server.
+    let nested_session = RuntimeEnv {
+      display_address: Some(":7".into()),
+      xauthority_file: Some("/tmp/.tmpcookie".into()),
+      wayland_display: Some("wayland-0".into()),
+      ..runtime_env()
+    };
+    let sandbox_config = SandboxConfig {
+      x11: "nested:800x600".parse().unwrap(),
+      ..Default::default()
+    };
+    let args = build_with_env(&sandbox_config, &LaunchConfig::default(), &nested_session).unwrap();
+    let group = args
+      .groups
+      .iter()
+      .find(|group| group.name == "x11")
+      .unwrap();
+    assert_eq!(group.reason, "--x11=nested:800x600, DISPLAY=:7");
+    assert_eq!(
+      group.ops,
+      vec![
+        BwrapOp::Bind {
+          src: "/tmp/.X11-unix/X7".into(),
+          dst: "/tmp/.X11-unix/X7".into(),
+          ro: false,
+        },
+        BwrapOp::Bind {
+          src: "/tmp/.tmpcookie".into(),
+          dst: "/tmp/.tmpcookie".into(),
+          ro: true,
+        },
+      ]
+    );
+    assert_eq!(args.environment()["DISPLAY"], ":7");
+    // A nested X server is pointless without X11.
+    let wayland_only = SandboxConfig {
+      display_server: DisplayServer::Wayland,
+      ..sandbox_config
+    };
+    assert!(build_with_env(&wayland_only, &LaunchConfig::default(), &nested_session).is_err());
+  }
+
+  #[test]
+  fn test_explain_untrusted_cookie() {
+    let mount_mappings: Vec<MountMapping> = Vec::new();
+    // Scripts cannot load the seccomp filter.
+    let sandbox_config = SandboxConfig {
+      x11_cookie: X11Cookie::Untrusted,
+      seccomp: SeccompLevel::Off,
+      ..Default::default()
+    };
+    let args = build_explained_args(
+      &sandbox_config,
+      &LaunchConfig::default(),
+      &runtime_env(),
+      &mount_mappings,
+      &FakeHost,
+    )
+    .unwrap();
+    assert_eq!(args.environment()["XAUTHORITY"], UNTRUSTED_XAUTHORITY_PLACEHOLDER);
+    // The shell script generates the cookie itself instead of binding the placeholder.
+    let script = explain::format_shell(&args).unwrap();
+    assert!(!script.contains(UNTRUSTED_XAUTHORITY_PLACEHOLDER), "{}", script);
+    for line in [
+      "xauth -q -f \"$untrusted_cookie\" generate \"$DISPLAY\" MIT-MAGIC-COOKIE-1 untrusted timeout 300",
+      "xauth -f \"$untrusted_cookie\" nlist | sed 's/^..../ffff/' | xauth -f \"$xauthority\" nmerge -",
+      "  --ro-bind \"$xauthority\" \"$xauthority\"",
+      "  --setenv XAUTHORITY \"$xauthority\"",
+    ] {
+      assert!(
+        script
+          .lines()
+          .any(|script_line| script_line.starts_with(line)),
+        "{}",
+        script
+      );
+    }
+  }
+
+  #[test]
+  fn test_audio_modes() {
+    let audio_ops = |audio: &str| -> Vec<String> {
+      let audio = audio.parse().unwrap();
+      get_audio_ops(audio, &runtime_env(), &FakeHost)
+        .iter()
+        .map(|op| op.to_args().join(" "))
+        .collect()
+    };
+    assert!(audio_ops("none").is_empty());
+    assert_eq!(
+      audio_ops("pipewire"),
+      vec!["--ro-bind /run/user/1000/pipewire-0 /run/user/1000/pipewire-0"]
+    );
+    assert_eq!(
+      audio_ops("pulse"),
+      vec![
+        "--ro-bind /run/user/1000/pulse/native /run/user/1000/pulse/native",
+        "--ro-bind /home/user/.config/pulse/cookie /home/user/.config/pulse/cookie",
+        "--setenv PULSE_COOKIE /home/user/.config/pulse/cookie",
+      ]
+    );
+    assert_eq!(
+      audio_ops("all"),
+      vec![
+        "--ro-bind /run/user/1000/pulse/native /run/user/1000/pulse/native",
+        "--ro-bind /home/user/.config/pulse/cookie /home/user/.config/pulse/cookie",
+        "--setenv PULSE_COOKIE /home/user/.config/pulse/cookie",
+        "--ro-bind /run/user/1000/pipewire-0 /run/user/1000/pipewire-0",
+      ]
+    );
+    // The audio variables must survive --clearenv.
+    let args = build(&SandboxConfig::default(), &LaunchConfig::default()).unwrap();
+    assert!(args.environment().contains_key("PULSE_COOKIE"));
+  }
+
+  #[test]
+  fn test_runtime_dir() {
+    let sandbox_config = SandboxConfig {
+      audio: AudioMode::Pipewire,
+      x11_cookie: X11Cookie::Session,
+      runtime_sockets: vec!["gamemode".parse().unwrap()],
+      ..Default::default()
+    };
+    let args = build(&sandbox_config, &LaunchConfig::default()).unwrap();
+    // Nothing else (e.g. ssh-agent or the PipeWire manager socket) is visible.
+    assert!(
+      args
+        .ops()
+        .any(|op| *op == BwrapOp::Tmpfs("/run/user/1000".into()))
+    );
+    assert_eq!(args.binds_under("/run/user/1000"), vec!["gamemode", "bus", "xauth", "pipewire-0"]);
+    let group = args
+      .groups
+      .iter()
+      .find(|group| group.name == "runtime sockets")
+      .unwrap();
+    assert_eq!(group.reason, "--runtime-socket=gamemode");
+    // The session bus is only bound when it listens on a socket that exists.
+    let abstract_bus = RuntimeEnv {
+      dbus_session_bus_address: "unix:abstract=/tmp/dbus-x".into(),
+      ..runtime_env()
+    };
+    let args = build_with_env(&SandboxConfig::default(), &LaunchConfig::default(), &abstract_bus);
+    assert!(!args.unwrap().binds_under("/run/user/1000").contains(&"bus"));
+    // Requested sockets must exist.
+    let missing_socket = SandboxConfig {
+      runtime_sockets: vec!["ssh-agent".parse().unwrap()],
+      ..Default::default()
+    };
+    assert!(build(&missing_socket, &LaunchConfig::default()).is_err());
+  }
+
+  #[test]
+  fn test_dbus_modes() {
+    let build_for = |dbus: DbusMode, runtime_env: &RuntimeEnv| {
+      let sandbox_config = SandboxConfig {
+        dbus,
+        ..Default::default()
+      };
+      build_with_env(&sandbox_config, &LaunchConfig::default(), runtime_env).unwrap()
+    };
+    let dbus_ops = |args: &BwrapArgs| -> Vec<String> {
+      let group = args
+        .groups
+        .iter()
+        .find(|group| group.name == "dbus")
+        .unwrap();
+      group.ops.iter().map(|op| op.to_args().join(" ")).collect()
+    };
+    let args = build_for(DbusMode::Full, &runtime_env());
+    assert_eq!(
+      dbus_ops(&args),
+      vec![
+        "--ro-bind /run/dbus /run/dbus",
+        "--ro-bind /run/user/1000/bus /run/user/1000/bus"
+      ]
+    );
+    assert_eq!(args.environment()["DBUS_SESSION_BUS_ADDRESS"], "unix:path=/run/user/1000/bus");
+    let args = build_for(DbusMode::None, &runtime_env());
+    assert!(dbus_ops(&args).is_empty());
+    assert!(!args.environment().contains_key("DBUS_SESSION_BUS_ADDRESS"));
+    // The proxied sockets replace the ones of the session, at the usual locations.
+    let proxied_env = RuntimeEnv {
+      dbus_session_bus_address: "unix:path=/tmp/rcage-dbus/session-bus".into(),
+      dbus_system_bus_socket: Some("/tmp/rcage-dbus/system-bus".into()),
+      ..runtime_env()
+    };
+    let args = build_for(DbusMode::Filtered, &proxied_env);
+    assert_eq!(
+      dbus_ops(&args),
+      vec![
+        "--ro-bind /tmp/rcage-dbus/session-bus /run/user/1000/bus",
+        "--ro-bind /tmp/rcage-dbus/system-bus /run/dbus/system_bus_socket",
+      ]
+    );
+    assert_eq!(args.environment()["DBUS_SESSION_BUS_ADDRESS"], "unix:path=/run/user/1000/bus");
+  }
+
+  #[test]
+  fn test_seccomp_levels() {
+    let seccomp_group = |seccomp: SeccompLevel| {
+      let sandbox_config = SandboxConfig {
+        seccomp,
+        ..Default::default()
+      };
+      let args = build_with_env(&sandbox_config, &LaunchConfig::default(), &runtime_env()).unwrap();
+      args
+        .groups
+        .into_iter()
+        .find(|group| group.name == "seccomp")
+    };
+    assert!(seccomp_group(SeccompLevel::Off).is_none());
+    let group = seccomp_group(SeccompLevel::Strict).unwrap();
+    assert_eq!(group.reason, "--seccomp=strict");
+    assert_eq!(group.ops, vec![BwrapOp::Seccomp(SECCOMP_FD_PLACEHOLDER.into())]);
+  }
+
+  #[test]
+  fn test_resolve_sync_mode() {
+    let soda = Some(Path::new("/runners/soda"));
+    let ntsync = Some(Path::new("/runners/ntsync"));
+    let test_cases = vec![
+      (SyncMode::Auto, Some((6, 14)), ntsync, SyncMode::Ntsync),
+      (SyncMode::Auto, Some((6, 14)), soda, SyncMode::Fsync),
+      (SyncMode::Auto, Some((5, 15)), soda, SyncMode::Esync),
+      (SyncMode::Auto, None, soda, SyncMode::Esync),
+      (SyncMode::Auto, Some((6, 13)), ntsync, SyncMode::None),
+      (SyncMode::Auto, Some((6, 14)), None, SyncMode::None),
+      (SyncMode::Ntsync, Some((6, 14)), soda, SyncMode::Ntsync),
+      // Explicit modes are trusted to be supported by the runner.
+      (SyncMode::Fsync, Some((5, 15)), ntsync, SyncMode::Fsync),
+    ];
+    for (sync_mode, kernel_version, runner_path, expected) in test_cases {
+      assert_eq!(
+        resolve_sync_mode(sync_mode, kernel_version, runner_path, &FakeHost).unwrap(),
+        expected,
+        "mode: {}, kernel: {:?}, runner: {:?}",
+        sync_mode,
+        kernel_version,
+        runner_path
+      );
+    }
+    assert!(resolve_sync_mode(SyncMode::Ntsync, Some((6, 13)), soda, &FakeHost).is_err());
+  }
+
+  #[test]
+  fn test_gpu_selection() {
+    let gpu_group = |gpu: &str| {
+      let sandbox_config = SandboxConfig {
+        gpu: gpu.parse().unwrap(),
+        ..Default::default()
+      };
+      let args = build_with_env(&sandbox_config, &LaunchConfig::default(), &runtime_env());
+      args.map(|args| args.groups.into_iter().find(|group| group.name == "gpu"))
+    };
+    assert!(gpu_group("none").unwrap().is_none());
+    let group = gpu_group("auto").unwrap().unwrap();
+    assert_eq!(group.reason, "--gpu=auto (0000:01:00.0)");
+    assert!(group.ops.contains(&BwrapOp::SetEnv {
+      key: "__NV_PRIME_RENDER_OFFLOAD".into(),
+      value: "1".into(),
+    }));
+    let group = gpu_group("00:02.0").unwrap().unwrap();
+    assert_eq!(group.reason, "--gpu=0000:00:02.0 (0000:00:02.0)");
+    assert!(group.ops.contains(&BwrapOp::SetEnv {
+      key: "DRI_PRIME".into(),
+      value: "pci-0000_00_02_0".into(),
+    }));
+    assert!(gpu_group("0000:02:00.0").is_err());
+  }
+
+  #[test]
+  fn test_ntsync() {
+    let launch_config = |sync_mode: SyncMode| LaunchConfig {
+      runner_path: Some("/runners/soda".into()),
+      sync_mode: Some(sync_mode),
+      ..Default::default()
+    };
+    let sandbox_config = SandboxConfig::default();
+    let args =
+      build_with_env(&sandbox_config, &launch_config(SyncMode::Ntsync), &runtime_env()).unwrap();
+    let group = |name: &str| args.groups.iter().find(|group| group.name == name).unwrap();
+    let ntsync_bind = BwrapOp::DevBind {
+      src: NTSYNC_DEVICE.into(),
+      dst: NTSYNC_DEVICE.into(),
+    };
+    assert!(group("devices").ops.contains(&ntsync_bind));
+    assert_eq!(
+      group("sync").ops,
+      vec![
+        BwrapOp::SetEnv {
+          key: "WINENTSYNC".into(),
+          value: "1".into(),
+        },
+        BwrapOp::SetEnv {
+          key: "PROTON_USE_NTSYNC".into(),
+          value: "1".into(),
+        },
+        BwrapOp::SetEnv {
+          key: "WINEFSYNC".into(),
+          value: "0".into(),
+        },
+        BwrapOp::SetEnv {
+          key: "WINEESYNC".into(),
+          value: "0".into(),
+        },
+      ]
+    );
+    // The device is only bound when used, even if supported.
+    let args =
+      build_with_env(&sandbox_config, &launch_config(SyncMode::Auto), &runtime_env()).unwrap();
+    let group = |name: &str| args.groups.iter().find(|group| group.name == name).unwrap();
+    assert!(!group("devices").ops.contains(&ntsync_bind));
+    assert_eq!(group("sync").reason, "--sync-mode=auto (fsync)");
+  }
+
+  #[test]
+  fn test_userns_blocking() {
+    let config = |namespace_isolation: bool, allow_userns: bool| SandboxConfig {
+      namespace_isolation,
+      allow_userns,
+      ..Default::default()
+    };
+    let test_cases = vec![
+      (config(true, false), Some((0, 11, 0)), UsernsBlocking::Bwrap),
+      (config(true, false), Some((0, 8, 0)), UsernsBlocking::Bwrap),
+      (config(true, false), Some((0, 7, 0)), UsernsBlocking::Seccomp),
+      (config(true, false), None, UsernsBlocking::Seccomp),
+      // --disable-userns requires --unshare-user.
+      (config(false, false), Some((0, 11, 0)), UsernsBlocking::Seccomp),
+      (config(true, true), Some((0, 11, 0)), UsernsBlocking::Allowed),
+      (config(false, true), None, UsernsBlocking::Allowed),
+    ];
+    for (sandbox_config, bwrap_version, expected) in test_cases {
+      assert_eq!(
+        UsernsBlocking::resolve(&sandbox_config, bwrap_version),
+        expected,
+        "version: {:?}",
+        bwrap_version
+      );
+    }
+    assert_eq!(
+      UsernsBlocking::Seccomp.describe(&config(true, false), Some((0, 7, 0))),
+      "denied by the seccomp filter, bwrap 0.7.0 does not support --disable-userns"
+    );
+    // The seccomp filter is needed even without a level.
+    let sandbox_config = SandboxConfig {
+      namespace_isolation: false,
+      seccomp: SeccompLevel::Off,
+      ..Default::default()
+    };
+    let args = build_with_env(&sandbox_config, &LaunchConfig::default(), &runtime_env()).unwrap();
+    let group = |name: &str| args.groups.iter().find(|group| group.name == name).unwrap();
+    assert!(group("user namespaces").ops.is_empty());
+    assert_eq!(group("seccomp").reason, "nested user namespaces");
+    assert!(!args.ops().any(|op| *op == BwrapOp::DisableUserns));
+  }
+
+  #[test]
+  fn test_read_child_pid() {
+    let status = r#"{ "child-pid": 4242, "cgroup-namespace": 4026531835 }
+{ "exit-code": 0 }"#;
+    assert_eq!(read_child_pid(&mut status.as_bytes()), Some(4242));
+    assert_eq!(read_child_pid(&mut "".as_bytes()), None);
+  }
+
+  #[test]
+  fn test_minimal_devices() {
+    let devices = |gpu: Option<&Gpu>| -> Vec<String> {
+      let ops = get_device_ops(&DeviceAccess::minimal(), SyncMode::None, gpu, &FakeHost).unwrap();
+      ops
+        .iter()
+        .filter_map(|op| match op {
+          BwrapOp::DevBind { src, .. } => Some(src.clone()),
+          _ => None,
+        })
+        .collect()
+    };
+    assert_eq!(devices(None), vec!["/dev/input", "/dev/dri", "/dev/nvidia0", "/dev/nvidiactl"]);
+    let gpus = FakeHost.gpus().unwrap();
+    assert_eq!(
+      devices(Some(&gpus[1])),
+      vec![
+        "/dev/input",
+        "/dev/dri/card1",
+        "/dev/dri/renderD129",
+        "/dev/nvidia0",
+        "/dev/nvidiactl"
+      ]
+    );
+    // NVIDIA devices are hidden from other GPUs, and from other NVIDIA GPUs.
+    assert_eq!(
+      devices(Some(&gpus[0])),
+      vec!["/dev/input", "/dev/dri/card0", "/dev/dri/renderD128"]
+    );
+    let second_nvidia_gpu = Gpu {
+      nvidia_minor: Some(1),
+      ..gpus[1].clone()
+    };
+    assert_eq!(
+      devices(Some(&second_nvidia_gpu)),
+      vec![
+        "/dev/input",
+        "/dev/dri/card1",
+        "/dev/dri/renderD129",
+        "/dev/nvidiactl"
+      ]
+    );
+  }
+
+  #[test]
+  fn test_device_classes() {
+    let devices = |device_access: &str, sync_mode: SyncMode| -> Vec<String> {
+      let device_access = device_access.parse().unwrap();
+      let ops = get_device_ops(&device_access, sync_mode, None, &FakeHost).unwrap();
+      ops
+        .iter()
+        .filter_map(|op| match op {
+          BwrapOp::DevBind { src, .. } => Some(src.clone()),
+          _ => None,
+        })
+        .collect()
+    };
+    assert_eq!(
+      devices("camera,sound,hidraw,/dev/ttyUSB0", SyncMode::None),
+      vec![
+        "/dev/hidraw0",
+        "/dev/hidraw1",
+        "/dev/snd",
+        "/dev/video0",
+        "/dev/ttyUSB0"
+      ]
+    );
+    assert_eq!(devices("all", SyncMode::None), vec!["/dev"]);
+    assert!(devices("none", SyncMode::None).is_empty());
+    // The ntsync device follows --sync-mode, even without the class.
+    assert_eq!(devices("none", SyncMode::Ntsync), vec!["/dev/ntsync"]);
+    assert_eq!(devices("ntsync", SyncMode::Ntsync), vec!["/dev/ntsync"]);
+  }
+
+  #[test]
+  fn test_hidraw_selection() {
+    let devices_group = |device_access: &str, hidraw: &str| {
+      let sandbox_config = SandboxConfig {
+        device_access: device_access.parse().unwrap(),
+        hidraw: hidraw.parse().unwrap(),
+        ..Default::default()
+      };
+      let args = build_with_env(&sandbox_config, &LaunchConfig::default(), &runtime_env()).unwrap();
+      let group = args
+        .groups
+        .into_iter()
+        .find(|group| group.name == "devices")
+        .unwrap();
+      let hidraw_devices: Vec<String> = group
+        .ops
+        .iter()
+        .filter_map(|op| match op {
+          BwrapOp::DevBind { src, .. } if src.starts_with("/dev/hidraw") => Some(src.clone()),
+          _ => None,
+        })
+        .collect();
+      (group.reason, hidraw_devices)
+    };
+    assert_eq!(
+      devices_group("minimal", "known-controllers"),
+      (
+        "--device-access=minimal, --hidraw=known-controllers".to_string(),
+        vec!["/dev/hidraw1".to_string()]
+      )
+    );
+    assert_eq!(devices_group("minimal", "1050:0407").1, vec!["/dev/hidraw0"]);
+    assert!(devices_group("minimal", "none").1.is_empty());
+    // Every node is already exposed.
+    assert_eq!(
+      devices_group("hidraw", "known-controllers"),
+      (
+        "--device-access=hidraw".to_string(),
+        vec!["/dev/hidraw0".to_string(), "/dev/hidraw1".to_string()]
+      )
+    );
+  }
+
+  #[test]
+  fn test_vulkan_icds() {
+    let vulkan_group = |gpu: &str| {
+      let sandbox_config = SandboxConfig {
+        gpu: gpu.parse().unwrap(),
+        ..Default::default()
+      };
+      let args = build_with_env(&sandbox_config, &LaunchConfig::default(), &runtime_env()).unwrap();
+      args.groups.into_iter().find(|group| group.name == "vulkan")
+    };
+    assert!(vulkan_group("none").is_none());
+    let icd_bind = |file: &str| BwrapOp::Bind {
+      src: format!("{}/{}", VULKAN_ICD_DIR, file),
+      dst: format!("{}/{}", VULKAN_ICD_DIR, file),
+      ro: true,
+    };
+    let group = vulkan_group("dedicated").unwrap();
+    assert_eq!(group.reason, "--gpu=dedicated (NVIDIA)");
+    assert_eq!(
+      group.ops,
+      vec![
+        BwrapOp::Tmpfs(VULKAN_ICD_DIR.into()),
+        icd_bind("nvidia_icd.json")
+      ]
+    );
+    let group = vulkan_group("integrated").unwrap();
+    assert_eq!(
+      group.ops,
+      vec![
+        BwrapOp::Tmpfs(VULKAN_ICD_DIR.into()),
+        icd_bind("intel_hasvk_icd.x86_64.json"),
+        icd_bind("intel_icd.x86_64.json"),
+      ]
+    );
+  }
+}
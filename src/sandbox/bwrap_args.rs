@@ -0,0 +1,333 @@
+use std::collections::BTreeMap;
+
+/// Namespaces that can be unshared with `--unshare-*`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Namespace {
+  User,
+  Ipc,
+  Pid,
+  Net,
+  Uts,
+  Cgroup,
+}
+
+impl Namespace {
+  fn as_arg(&self) -> &'static str {
+    match self {
+      Namespace::User => "--unshare-user",
+      Namespace::Ipc => "--unshare-ipc",
+      Namespace::Pid => "--unshare-pid",
+      Namespace::Net => "--unshare-net",
+      Namespace::Uts => "--unshare-uts",
+      Namespace::Cgroup => "--unshare-cgroup",
+    }
+  }
+}
+
+/// A single bwrap option along with its parameters, see
+/// https://man.archlinux.org/man/extra/bubblewrap/bwrap.1.en for the meaning of each one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BwrapOp {
+  DieWithParent,
+  Unshare(Namespace),
+  Uid(u32),
+  Gid(u32),
+  Hostname(String),
+  /// `--bind` or `--ro-bind` depending on `ro`.
+  Bind {
+    src: String,
+    dst: String,
+    ro: bool,
+  },
+  DevBind {
+    src: String,
+    dst: String,
+  },
+  Tmpfs(String),
+  Proc(String),
+  Symlink {
+    src: String,
+    dst: String,
+  },
+  ClearEnv,
+  SetEnv {
+    key: String,
+    value: String,
+  },
+  Chdir(String),
+  /// Load the seccomp filter read from the file descriptor.
+  Seccomp(String),
+  /// Prevent the sandbox from creating nested user namespaces, requires `--unshare-user`.
+  DisableUserns,
+  /// Fail if the sandbox can still create nested user namespaces.
+  AssertUsernsDisabled,
+}
+
+impl BwrapOp {
+  /// Render the option as bwrap arguments.
+  pub fn to_args(&self) -> Vec<String> {
+    let args: Vec<&str> = match self {
+      BwrapOp::DieWithParent => vec!["--die-with-parent"],
+      BwrapOp::Unshare(namespace) => vec![namespace.as_arg()],
+      BwrapOp::Uid(uid) => return vec!["--uid".into(), uid.to_string()],
+      BwrapOp::Gid(gid) => return vec!["--gid".into(), gid.to_string()],
+      BwrapOp::Hostname(hostname) => vec!["--hostname", hostname],
+      BwrapOp::Bind { src, dst, ro } => vec![if *ro { "--ro-bind" } else { "--bind" }, src, dst],
+      BwrapOp::DevBind { src, dst } => vec!["--dev-bind", src, dst],
+      BwrapOp::Tmpfs(path) => vec!["--tmpfs", path],
+      BwrapOp::Proc(path) => vec!["--proc", path],
+      BwrapOp::Symlink { src, dst } => vec!["--symlink", src, dst],
+      BwrapOp::ClearEnv => vec!["--clearenv"],
+      BwrapOp::SetEnv { key, value } => vec!["--setenv", key, value],
+      BwrapOp::Chdir(path) => vec!["--chdir", path],
+      BwrapOp::Seccomp(fd) => vec!["--seccomp", fd],
+      BwrapOp::DisableUserns => vec!["--disable-userns"],
+      BwrapOp::AssertUsernsDisabled => vec!["--assert-userns-disabled"],
+    };
+    args.into_iter().map(String::from).collect()
+  }
+}
+
+/// Options that serve the same purpose, used to explain where each bwrap argument comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArgGroup {
+  /// Short description of the group e.g., `devices`.
+  pub name: &'static str,
+  /// Option or condition that caused the arguments to be added.
+  pub reason: String,
+  pub ops: Vec<BwrapOp>,
+}
+
+/// Complete bwrap command line, split by purpose.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BwrapArgs {
+  pub groups: Vec<ArgGroup>,
+  /// Program (and its arguments) executed inside the sandbox.
+  pub command: Vec<String>,
+}
+
+impl BwrapArgs {
+  /// Iterate over all the options, in the same order they are passed to bwrap.
+  pub fn ops(&self) -> impl Iterator<Item = &BwrapOp> {
+    self.groups.iter().flat_map(|group| group.ops.iter())
+  }
+
+  /// Environment variables the command starts with, as configured by `--clearenv` and `--setenv`
+  /// (variables inherited from bwrap are not included).
+  pub fn environment(&self) -> BTreeMap<String, String> {
+    let mut environment = BTreeMap::new();
+    for op in self.ops() {
+      match op {
+        BwrapOp::ClearEnv => environment.clear(),
+        BwrapOp::SetEnv { key, value } => {
+          environment.insert(key.clone(), value.clone());
+        }
+        _ => (),
+      }
+    }
+    environment
+  }
+
+  /// Working directory of the command, set by the last `--chdir`.
+  pub fn working_dir(&self) -> Option<&str> {
+    self.ops().fold(None, |working_dir, op| match op {
+      BwrapOp::Chdir(path) => Some(path),
+      _ => working_dir,
+    })
+  }
+
+  /// Destinations of the binds inside `dir`, relative to it, in the order they are mounted.
+  pub fn binds_under(&self, dir: &str) -> Vec<&str> {
+    self
+      .ops()
+      .filter_map(|op| match op {
+        BwrapOp::Bind { dst, .. } => dst.strip_prefix(dir)?.strip_prefix('/'),
+        _ => None,
+      })
+      .collect()
+  }
+
+  /// Render the options and command into the argument list passed to bwrap.
+  pub fn to_vec(&self) -> Vec<String> {
+    let mut args: Vec<String> = self.ops().flat_map(|op| op.to_args()).collect();
+    args.extend(self.command.iter().cloned());
+    args
+  }
+}
+
+/// Collects options in groups, every option is added to the group that was started last.
+#[derive(Debug, Default)]
+pub struct BwrapBuilder {
+  groups: Vec<ArgGroup>,
+}
+
+impl BwrapBuilder {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Start a new group, the following options will be added to it.
+  pub fn group(&mut self, name: &'static str, reason: impl Into<String>) -> &mut Self {
+    self.groups.push(ArgGroup {
+      name,
+      reason: reason.into(),
+      ops: Vec::new(),
+    });
+    self
+  }
+
+  pub fn op(&mut self, op: BwrapOp) -> &mut Self {
+    if self.groups.is_empty() {
+      self.group("other", "always");
+    }
+    if let Some(group) = self.groups.last_mut() {
+      group.ops.push(op);
+    }
+    self
+  }
+
+  pub fn unshare(&mut self, namespace: Namespace) -> &mut Self {
+    self.op(BwrapOp::Unshare(namespace))
+  }
+
+  pub fn bind(&mut self, src: impl Into<String>, dst: impl Into<String>) -> &mut Self {
+    self.op(BwrapOp::Bind {
+      src: src.into(),
+      dst: dst.into(),
+      ro: false,
+    })
+  }
+
+  pub fn ro_bind(&mut self, src: impl Into<String>, dst: impl Into<String>) -> &mut Self {
+    self.op(BwrapOp::Bind {
+      src: src.into(),
+      dst: dst.into(),
+      ro: true,
+    })
+  }
+
+  /// Bind a path to the same location inside the sandbox.
+  pub fn ro_bind_same(&mut self, path: impl Into<String>) -> &mut Self {
+    let path = path.into();
+    self.ro_bind(path.clone(), path)
+  }
+
+  pub fn tmpfs(&mut self, path: impl Into<String>) -> &mut Self {
+    self.op(BwrapOp::Tmpfs(path.into()))
+  }
+
+  pub fn symlink(&mut self, src: impl Into<String>, dst: impl Into<String>) -> &mut Self {
+    self.op(BwrapOp::Symlink {
+      src: src.into(),
+      dst: dst.into(),
+    })
+  }
+
+  pub fn setenv(&mut self, key: impl Into<String>, value: impl Into<String>) -> &mut Self {
+    self.op(BwrapOp::SetEnv {
+      key: key.into(),
+      value: value.into(),
+    })
+  }
+
+  pub fn chdir(&mut self, path: impl Into<String>) -> &mut Self {
+    self.op(BwrapOp::Chdir(path.into()))
+  }
+
+  pub fn build(self, command: Vec<String>) -> BwrapArgs {
+    BwrapArgs {
+      groups: self.groups,
+      command,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_op_rendering() {
+    let test_cases = vec![
+      (BwrapOp::DieWithParent, vec!["--die-with-parent"]),
+      (BwrapOp::Unshare(Namespace::Net), vec!["--unshare-net"]),
+      (BwrapOp::Uid(300_000), vec!["--uid", "300000"]),
+      (
+        BwrapOp::Bind {
+          src: "/a".into(),
+          dst: "/b".into(),
+          ro: true,
+        },
+        vec!["--ro-bind", "/a", "/b"],
+      ),
+      (
+        BwrapOp::Bind {
+          src: "/a".into(),
+          dst: "/b".into(),
+          ro: false,
+        },
+        vec!["--bind", "/a", "/b"],
+      ),
+      (
+        BwrapOp::SetEnv {
+          key: "A".into(),
+          value: "".into(),
+        },
+        vec!["--setenv", "A", ""],
+      ),
+      (BwrapOp::Proc("proc".into()), vec!["--proc", "proc"]),
+    ];
+    for (op, expected) in test_cases {
+      assert_eq!(op.to_args(), expected);
+    }
+  }
+
+  #[test]
+  fn test_binds_under() {
+    let mut builder = BwrapBuilder::new();
+    builder
+      .tmpfs("/run/user/1000")
+      .ro_bind_same("/run/user/1000/bus")
+      .ro_bind_same("/run/user/10000/bus")
+      .bind("/games", "/run/user/1000/pulse/native")
+      .ro_bind_same("/run/user");
+    let args = builder.build(Vec::new());
+    assert_eq!(args.binds_under("/run/user/1000"), vec!["bus", "pulse/native"]);
+  }
+
+  #[test]
+  fn test_builder_groups() {
+    let mut builder = BwrapBuilder::new();
+    builder.tmpfs("/outside");
+    builder.group("network", "--network-mode=no_access");
+    builder.unshare(Namespace::Net);
+    builder
+      .group("env", "always")
+      .setenv("A", "1")
+      .setenv("B", "2");
+    let args = builder.build(vec!["bash".into()]);
+    assert_eq!(
+      args.environment(),
+      BTreeMap::from([("A".into(), "1".into()), ("B".into(), "2".into())])
+    );
+    assert_eq!(args.working_dir(), None);
+    let names: Vec<&str> = args.groups.iter().map(|group| group.name).collect();
+    assert_eq!(names, vec!["other", "network", "env"]);
+    assert_eq!(args.groups[2].ops.len(), 2);
+    assert_eq!(
+      args.to_vec(),
+      vec![
+        "--tmpfs",
+        "/outside",
+        "--unshare-net",
+        "--setenv",
+        "A",
+        "1",
+        "--setenv",
+        "B",
+        "2",
+        "bash"
+      ]
+    );
+  }
+}
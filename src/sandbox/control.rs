@@ -0,0 +1,220 @@
+use super::registry::Instance;
+use super::seccomp;
+use anyhow::Context;
+use std::os::unix::process::CommandExt;
+use std::{fs, io, path::PathBuf, process::Command};
+
+/// Namespaces that bwrap may unshare, along with the matching nsenter option. The user namespace
+/// must be the first one, so entering the others is allowed.
+const NAMESPACES: [(&str, &str); 7] = [
+  ("user", "--user"),
+  ("mnt", "--mount"),
+  ("pid", "--pid"),
+  ("net", "--net"),
+  ("uts", "--uts"),
+  ("ipc", "--ipc"),
+  ("cgroup", "--cgroup"),
+];
+
+fn get_namespace(pid: &str, namespace: &str) -> io::Result<PathBuf> {
+  fs::read_link(format!("/proc/{}/ns/{}", pid, namespace))
+}
+
+/// Get the nsenter options for the namespaces that `pid` does not share with the current process.
+fn get_namespace_args(pid: u32) -> anyhow::Result<Vec<&'static str>> {
+  let mut args = Vec::new();
+  for (namespace, arg) in NAMESPACES {
+    let target = get_namespace(&pid.to_string(), namespace).with_context(|| {
+      format!("Could not access the {} namespace of process {}", namespace, pid)
+    })?;
+    if get_namespace("self", namespace).ok() != Some(target) {
+      args.push(arg);
+    }
+  }
+  Ok(args)
+}
+
+/// Build a command that runs `command` inside a running sandbox, i.e., in the same namespaces,
+/// environment, working directory and seccomp filter as the program launched by the sandbox. Relies
+/// on `nsenter` and `env` being available on the host and inside the sandbox respectively.
+pub fn command_in(instance: &Instance, command: &[String]) -> anyhow::Result<Command> {
+  let mut nsenter = Command::new("nsenter");
+  // The filter is loaded before nsenter, which only needs setns and fork, so the command cannot
+  // escape it. Entering the namespaces is not enough: bwrap loads the filter in the sandbox itself.
+  if let Some(program) = seccomp::compile(instance.seccomp, instance.deny_userns)? {
+    let filter = seccomp::to_bytes(&program);
+    // SAFETY: loading the filter only makes syscalls, and the closure does not allocate.
+    unsafe {
+      nsenter.pre_exec(move || seccomp::load_filter(&filter));
+    }
+  }
+  nsenter
+    .arg("--target")
+    .arg(instance.child_pid.to_string())
+    .args(get_namespace_args(instance.child_pid)?)
+    // Keep the uid and gid of the current user, they are mapped to the ones used by the sandbox.
+    .arg("--preserve-credentials")
+    .args(["--", "env", "--ignore-environment"])
+    .arg(format!("--chdir={}", instance.working_dir))
+    .args(
+      instance
+        .environment
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value)),
+    )
+    .args(command);
+  Ok(nsenter)
+}
+
+/// Get the parent PID from the contents of `/proc/<pid>/stat`.
+fn parse_parent_pid(stat: &str) -> Option<u32> {
+  // The process name is between parentheses and may contain spaces or parentheses itself.
+  let (_, fields) = stat.rsplit_once(')')?;
+  fields.split_whitespace().nth(1)?.parse().ok()
+}
+
+fn list_pids() -> anyhow::Result<Vec<u32>> {
+  let entries = fs::read_dir("/proc").context("Failed to read /proc")?;
+  Ok(
+    entries
+      .flatten()
+      .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+      .collect(),
+  )
+}
+
+/// Get the PIDs of every process running inside the sandbox, as seen from the host. If the sandbox
+/// has its own PID namespace that is every process in it, otherwise (i.e., without namespace
+/// isolation) every descendant of bwrap.
+pub fn list_processes(instance: &Instance) -> anyhow::Result<Vec<u32>> {
+  let pids = list_pids()?;
+  let child_namespace = get_namespace(&instance.child_pid.to_string(), "pid").ok();
+  let own_namespace = get_namespace("self", "pid").ok();
+  if child_namespace.is_some() && child_namespace != own_namespace {
+    return Ok(
+      pids
+        .into_iter()
+        .filter(|pid| get_namespace(&pid.to_string(), "pid").ok() == child_namespace)
+        .collect(),
+    );
+  }
+  let parents: Vec<(u32, u32)> = pids
+    .into_iter()
+    .filter_map(|pid| {
+      let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+      Some((pid, parse_parent_pid(&stat)?))
+    })
+    .collect();
+  let mut descendants = vec![instance.pid];
+  let mut index = 0;
+  while index < descendants.len() {
+    let parent = descendants[index];
+    descendants.extend(
+      parents
+        .iter()
+        .filter(|(_, parent_pid)| *parent_pid == parent)
+        .map(|(pid, _)| *pid),
+    );
+    index += 1;
+  }
+  descendants.remove(0);
+  Ok(descendants)
+}
+
+/// Send a signal to every process inside the sandbox, returns how many processes were signaled.
+pub fn signal(instance: &Instance, signal: libc::c_int) -> anyhow::Result<usize> {
+  let mut count = 0;
+  for pid in list_processes(instance)? {
+    // SAFETY: kill has no memory safety requirements, a PID that no longer exists only makes it
+    // return an error.
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } == 0 {
+      count += 1;
+    }
+  }
+  Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::process;
+
+  /// The current process as a sandbox, so there are no namespaces to enter.
+  fn current_instance(seccomp: &str, deny_userns: bool) -> Instance {
+    Instance {
+      id: "0000abcd".into(),
+      name: "game".into(),
+      pid: process::id(),
+      child_pid: process::id(),
+      started_at: 0,
+      network_mode: "no_access".parse().unwrap(),
+      seccomp: seccomp.parse().unwrap(),
+      deny_userns,
+      runner: None,
+      prefix: None,
+      environment: [("WINEPREFIX".to_string(), "/var/lib/wine".to_string())].into(),
+      working_dir: "/".into(),
+    }
+  }
+
+  #[test]
+  fn test_parse_parent_pid() {
+    let test_cases = vec![
+      ("42 (bwrap) S 41 42 41 0 -1", Some(41)),
+      ("43 (wine (x) 1) S 42 43 41 0 -1", Some(42)),
+      ("44 (broken", None),
+    ];
+    for (stat, expected) in test_cases {
+      assert_eq!(parse_parent_pid(stat), expected);
+    }
+  }
+
+  #[test]
+  fn test_command_in() {
+    let instance = Instance {
+      working_dir: "/app".into(),
+      ..current_instance("off", false)
+    };
+    let command = command_in(&instance, &["winecfg".into()]).unwrap();
+    let args: Vec<String> = command
+      .get_args()
+      .map(|arg| arg.to_string_lossy().to_string())
+      .collect();
+    let pid = process::id().to_string();
+    assert_eq!(
+      args,
+      vec![
+        "--target",
+        &pid,
+        "--preserve-credentials",
+        "--",
+        "env",
+        "--ignore-environment",
+        "--chdir=/app",
+        "WINEPREFIX=/var/lib/wine",
+        "winecfg"
+      ]
+    );
+  }
+
+  #[test]
+  fn test_command_in_status() {
+    let run = |instance: &Instance, script: &str| {
+      let command = ["sh".to_string(), "-c".to_string(), script.to_string()];
+      command_in(instance, &command).unwrap().status()
+    };
+    // Skipped if nsenter is missing, or not allowed to run on this host.
+    let unfiltered = current_instance("off", false);
+    if !run(&unfiltered, "true").is_ok_and(|status| status.success()) {
+      eprintln!("nsenter cannot run on this host, skipping");
+      return;
+    }
+    let status = run(&unfiltered, "echo $WINEPREFIX | grep -qx /var/lib/wine; exit $((3 + $?))");
+    assert_eq!(status.unwrap().code(), Some(3));
+    // The command runs with the filter of the sandbox (mode 2 in /proc), and no_new_privs.
+    let filtered = current_instance("default", true);
+    let script = "grep -q '^NoNewPrivs:[[:space:]]*1$' /proc/self/status \
+      && grep -q '^Seccomp:[[:space:]]*2$' /proc/self/status";
+    assert!(run(&filtered, script).unwrap().success());
+  }
+}
@@ -0,0 +1,317 @@
+use super::nested_x11::find_program;
+use super::sandbox::RuntimeEnv;
+use super::sandbox_config::deserialize_from_str;
+use anyhow::Context;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::io::{self, PipeReader, Read};
+use std::os::fd::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+use tempfile::TempDir;
+
+/// Location of the system bus socket when `DBUS_SYSTEM_BUS_ADDRESS` is not set, also where the
+/// sandbox expects it.
+pub const SYSTEM_BUS_SOCKET: &str = "/run/dbus/system_bus_socket";
+
+/// Controls the access to the D-Bus session and system buses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DbusMode {
+  /// Neither bus is reachable from the sandbox.
+  None,
+  /// Both buses are reached through xdg-dbus-proxy, which only allows the names in `--dbus-allow`.
+  Filtered,
+  /// Both buses are exposed as they are, any service can be called e.g., systemd, NetworkManager
+  /// or the secret service.
+  Full,
+}
+
+impl FromStr for DbusMode {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "none" | "n" => Ok(DbusMode::None),
+      "filtered" | "f" => Ok(DbusMode::Filtered),
+      "full" => Ok(DbusMode::Full),
+      _ => Err(format!("Invalid D-Bus mode: {}", s)),
+    }
+  }
+}
+
+impl fmt::Display for DbusMode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let mode_str = match self {
+      DbusMode::None => "none",
+      DbusMode::Filtered => "filtered",
+      DbusMode::Full => "full",
+    };
+    write!(f, "{}", mode_str)
+  }
+}
+
+impl Serialize for DbusMode {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for DbusMode {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserialize_from_str(deserializer)
+  }
+}
+
+/// What a sandboxed program can do with a bus name, each policy implies the previous ones.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DbusPolicy {
+  /// The name is listed, and its owner can be introspected.
+  See,
+  /// Methods can be called, and signals received.
+  Talk,
+  /// The name can be acquired e.g., `org.mpris.MediaPlayer2.game`.
+  Own,
+}
+
+impl fmt::Display for DbusPolicy {
+  /// Same names as the xdg-dbus-proxy options.
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let policy_str = match self {
+      DbusPolicy::See => "see",
+      DbusPolicy::Talk => "talk",
+      DbusPolicy::Own => "own",
+    };
+    write!(f, "{}", policy_str)
+  }
+}
+
+/// Entry of the allow-list used with `--dbus=filtered`, looks like `[system:]POLICY:NAME` e.g.,
+/// `talk:org.freedesktop.Notifications`, `own:org.mpris.MediaPlayer2.*` or
+/// `system:talk:org.freedesktop.UPower`. Rules apply to the session bus unless prefixed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbusRule {
+  pub system: bool,
+  pub policy: DbusPolicy,
+  /// Bus name, may end with `.*` to match every name under a prefix.
+  pub name: String,
+}
+
+impl DbusRule {
+  /// Option understood by xdg-dbus-proxy e.g., `--talk=org.freedesktop.Notifications`.
+  pub fn to_arg(&self) -> String {
+    format!("--{}={}", self.policy, self.name)
+  }
+}
+
+/// Bus names are made of elements separated by dots, see
+/// https://dbus.freedesktop.org/doc/dbus-specification.html#message-protocol-names-bus.
+fn is_valid_bus_name(name: &str) -> bool {
+  let prefix = name.strip_suffix(".*").unwrap_or(name);
+  !prefix.is_empty()
+    && prefix.split('.').all(|element| {
+      !element.is_empty()
+        && element
+          .chars()
+          .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    })
+}
+
+impl FromStr for DbusRule {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let (system, rule) = match s.strip_prefix("system:") {
+      Some(rule) => (true, rule),
+      None => (false, s),
+    };
+    let (policy, name) = rule
+      .split_once(':')
+      .ok_or_else(|| format!("Invalid D-Bus rule, expected POLICY:NAME: {}", s))?;
+    let policy = match policy.to_lowercase().as_str() {
+      "see" => DbusPolicy::See,
+      "talk" => DbusPolicy::Talk,
+      "own" => DbusPolicy::Own,
+      _ => return Err(format!("Invalid D-Bus policy, one of see, talk or own: {}", s)),
+    };
+    if !is_valid_bus_name(name) {
+      return Err(format!("Invalid D-Bus name: {}", s));
+    }
+    Ok(DbusRule {
+      system,
+      policy,
+      name: name.into(),
+    })
+  }
+}
+
+impl fmt::Display for DbusRule {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.system {
+      write!(f, "system:")?;
+    }
+    write!(f, "{}:{}", self.policy, self.name)
+  }
+}
+
+impl Serialize for DbusRule {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for DbusRule {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserialize_from_str(deserializer)
+  }
+}
+
+/// Path of the socket a bus listens on, if any. The address may list several transports separated
+/// by `;`, each one with `key=value` options separated by `,`.
+pub fn bus_socket_path(address: &str) -> Option<&str> {
+  address
+    .split(';')
+    .filter_map(|transport| transport.strip_prefix("unix:"))
+    .flat_map(|options| options.split(','))
+    .find_map(|option| option.strip_prefix("path="))
+}
+
+/// xdg-dbus-proxy running on the host, it exits when dropped.
+pub struct DbusProxy {
+  child: Child,
+  /// The proxy exits once this end of its sync pipe is closed, even if rcage is killed.
+  _sync: PipeReader,
+  /// Contains the proxied sockets.
+  dir: TempDir,
+  has_system_bus: bool,
+}
+
+impl DbusProxy {
+  /// Start a filtering proxy for the session bus, and for the system bus if there is one. Waits
+  /// until both proxied sockets accept connections.
+  pub fn start(rules: &[DbusRule], runtime_env: &RuntimeEnv) -> anyhow::Result<Self> {
+    let program = find_program("xdg-dbus-proxy", &runtime_env.original_path)
+      .context("Could not find xdg-dbus-proxy, install it to use --dbus=filtered")?;
+    let dir = tempfile::Builder::new().prefix("rcage-dbus-").tempdir()?;
+    let has_system_bus = runtime_env
+      .dbus_system_bus_socket
+      .as_ref()
+      .is_some_and(|socket| Path::new(socket).exists());
+    let (sync_reader, sync_writer) = io::pipe().context("Could not create sync pipe")?;
+    let sync_fd = sync_writer.as_raw_fd();
+    let mut command = Command::new(program);
+    command.arg(format!("--fd={}", sync_fd));
+    let mut add_bus = |address: &str, socket: &str, system: bool| {
+      command
+        .arg(address)
+        .arg(dir.path().join(socket))
+        .arg("--filter")
+        .args(
+          rules
+            .iter()
+            .filter(|rule| rule.system == system)
+            .map(DbusRule::to_arg),
+        );
+    };
+    add_bus(&runtime_env.dbus_session_bus_address, "session-bus", false);
+    if let (true, Some(system_socket)) = (has_system_bus, &runtime_env.dbus_system_bus_socket) {
+      add_bus(&format!("unix:path={}", system_socket), "system-bus", true);
+    }
+    command.stdin(Stdio::null()).stdout(Stdio::null());
+    // SAFETY: fcntl is async-signal-safe, and the closure does not allocate.
+    unsafe {
+      command.pre_exec(move || {
+        if libc::fcntl(sync_fd, libc::F_SETFD, 0) == -1 {
+          return Err(io::Error::last_os_error());
+        }
+        Ok(())
+      });
+    }
+    let mut child = command
+      .spawn()
+      .map_err(|e| anyhow::anyhow!("Could not spawn xdg-dbus-proxy: {}", e))?;
+    drop(sync_writer);
+    // The proxy writes a single byte once it is listening.
+    let mut sync_reader = sync_reader;
+    let mut ready = [0u8; 1];
+    if !matches!(sync_reader.read(&mut ready), Ok(1)) {
+      let _ = child.kill();
+      let _ = child.wait();
+      anyhow::bail!("xdg-dbus-proxy exited before being ready");
+    }
+    Ok(Self {
+      child,
+      _sync: sync_reader,
+      dir,
+      has_system_bus,
+    })
+  }
+
+  /// Session as seen by the sandbox, i.e., the proxied sockets replace the ones of the session.
+  pub fn runtime_env(&self, runtime_env: &RuntimeEnv) -> RuntimeEnv {
+    let socket_path = |socket: &str| self.dir.path().join(socket).to_string_lossy().to_string();
+    RuntimeEnv {
+      dbus_session_bus_address: format!("unix:path={}", socket_path("session-bus")),
+      dbus_system_bus_socket: self.has_system_bus.then(|| socket_path("system-bus")),
+      ..runtime_env.clone()
+    }
+  }
+}
+
+impl Drop for DbusProxy {
+  fn drop(&mut self) {
+    // SAFETY: kill has no memory safety requirements.
+    unsafe { libc::kill(self.child.id() as libc::pid_t, libc::SIGTERM) };
+    let _ = self.child.wait();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_dbus_rule_parsing() {
+    let test_cases = vec![
+      ("talk:org.freedesktop.Notifications", Ok("--talk=org.freedesktop.Notifications")),
+      ("own:org.mpris.MediaPlayer2.*", Ok("--own=org.mpris.MediaPlayer2.*")),
+      ("system:see:org.freedesktop.UPower", Ok("--see=org.freedesktop.UPower")),
+      ("SEE:com.feralinteractive.GameMode", Ok("--see=com.feralinteractive.GameMode")),
+      ("org.freedesktop.Notifications", Err(())),
+      ("call:org.freedesktop.Notifications", Err(())),
+      ("talk:", Err(())),
+      ("talk:org..freedesktop", Err(())),
+      ("talk:org.*.Notifications", Err(())),
+      ("talk:*", Err(())),
+    ];
+    for (input, expected) in test_cases {
+      let result = DbusRule::from_str(input).map_err(|_| ());
+      assert_eq!(
+        result.clone().map(|rule| rule.to_arg()),
+        expected.map(String::from),
+        "input: {}",
+        input
+      );
+      if let Ok(rule) = result {
+        assert_eq!(DbusRule::from_str(&rule.to_string()), Ok(rule));
+      }
+    }
+    assert!(
+      DbusRule::from_str("system:talk:org.freedesktop.UPower")
+        .unwrap()
+        .system
+    );
+  }
+
+  #[test]
+  fn test_bus_socket_path() {
+    let test_cases = vec![
+      ("unix:path=/run/user/1000/bus", Some("/run/user/1000/bus")),
+      ("unix:abstract=/tmp/dbus-x,guid=abc;unix:path=/tmp/bus", Some("/tmp/bus")),
+      ("unix:abstract=/tmp/dbus-x,guid=abc", None),
+      ("tcp:host=localhost,port=1234", None),
+    ];
+    for (input, expected) in test_cases {
+      assert_eq!(bus_socket_path(input), expected, "input: {}", input);
+    }
+  }
+}
@@ -1,4 +1,5 @@
 use anyhow::Context;
+use std::fmt;
 
 /// Represents a value as used by the DISPLAY environment variable.
 /// The format is: `hostname:display_number.screen_number`.
@@ -17,6 +18,15 @@ pub struct Display {
 }
 
 impl Display {
+  /// Display of a server running on this machine e.g., a nested X server started by rcage.
+  pub fn local(display_number: u32) -> Self {
+    Self {
+      hostname: None,
+      display_number,
+      screen_number: None,
+    }
+  }
+
   pub fn from_str(display: &str) -> anyhow::Result<Self> {
     let parts: Vec<&str> = display.split(':').collect();
     if parts.len() != 2 {
@@ -54,6 +64,17 @@ impl Display {
   }
 }
 
+impl fmt::Display for Display {
+  /// Formats the value the same way as `DISPLAY` e.g., `:1` or `localhost:0.0`.
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}:{}", self.hostname.as_deref().unwrap_or(""), self.display_number)?;
+    if let Some(screen_number) = self.screen_number {
+      write!(f, ".{}", screen_number)?;
+    }
+    Ok(())
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -152,4 +173,13 @@ mod tests {
     };
     assert_eq!(display.get_socket_path(), "/tmp/.X11-unix/X1");
   }
+
+  #[test]
+  fn test_local_display() {
+    let display = Display::local(12);
+    assert_eq!(display.to_string(), ":12");
+    assert_eq!(display.get_socket_path(), "/tmp/.X11-unix/X12");
+    assert_eq!(Display::from_str(&display.to_string()).unwrap(), display);
+    assert_eq!(Display::from_str("localhost:0.1").unwrap().to_string(), "localhost:0.1");
+  }
 }
@@ -0,0 +1,279 @@
+use super::bwrap_args::{BwrapArgs, BwrapOp};
+use super::xauth::UNTRUSTED_COOKIE_TIMEOUT;
+use std::str::FromStr;
+
+/// Stands for the temporary empty file used to hide host files (e.g. `/etc/hostname`), the real
+/// file only exists while the sandbox is running.
+pub const EMPTY_FILE_PLACEHOLDER: &str = "<empty file>";
+
+/// Stands for the Xauthority file generated for a nested X server, which is not started by explain.
+pub const NESTED_XAUTHORITY_PLACEHOLDER: &str = "<nested xauthority>";
+
+/// Stands for the Xauthority file with the untrusted cookie, which is not generated by explain.
+pub const UNTRUSTED_XAUTHORITY_PLACEHOLDER: &str = "<untrusted xauthority>";
+
+/// Stand for the sockets of xdg-dbus-proxy, which is not started by explain.
+pub const SESSION_BUS_PROXY_PLACEHOLDER: &str = "<session bus proxy>";
+pub const SYSTEM_BUS_PROXY_PLACEHOLDER: &str = "<system bus proxy>";
+
+/// Stands for the file descriptor of the seccomp filter, which is only compiled when running.
+pub const SECCOMP_FD_PLACEHOLDER: &str = "<seccomp filter>";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+  /// Human readable arguments, grouped by purpose.
+  Text,
+  /// POSIX shell script that runs the same bwrap command.
+  Shell,
+}
+
+impl FromStr for OutputFormat {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "text" | "t" => Ok(OutputFormat::Text),
+      "shell" | "s" => Ok(OutputFormat::Shell),
+      _ => Err(format!("Invalid output format: {}", s)),
+    }
+  }
+}
+
+/// Quote an argument so it can be safely pasted in a POSIX shell, arguments that only contain safe
+/// characters are left as-is to keep the output readable.
+pub fn shell_quote(arg: &str) -> String {
+  let is_safe = |c: char| c.is_ascii_alphanumeric() || "_-+=:,./@%".contains(c);
+  if !arg.is_empty() && arg.chars().all(is_safe) {
+    return arg.to_string();
+  }
+  format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+pub fn format_text(args: &BwrapArgs) -> String {
+  let mut output: Vec<String> = Vec::new();
+  for group in &args.groups {
+    output.push(format!("# {} ({})", group.name, group.reason));
+    if group.ops.is_empty() {
+      output.push("  (no arguments)".into());
+    }
+    for op in &group.ops {
+      let quoted: Vec<String> = op.to_args().iter().map(|arg| shell_quote(arg)).collect();
+      output.push(format!("  {}", quoted.join(" ")));
+    }
+  }
+  output.push("# command".into());
+  let quoted: Vec<String> = args.command.iter().map(|arg| shell_quote(arg)).collect();
+  output.push(format!("  {}", quoted.join(" ")));
+  output.join("\n")
+}
+
+/// Whether any argument of `args` is `placeholder`.
+fn uses_placeholder(args: &BwrapArgs, placeholder: &str) -> bool {
+  args
+    .groups
+    .iter()
+    .flat_map(|group| &group.ops)
+    .any(|op| op.to_args().iter().any(|arg| arg == placeholder))
+}
+
+pub fn format_shell(args: &BwrapArgs) -> anyhow::Result<String> {
+  if uses_placeholder(args, NESTED_XAUTHORITY_PLACEHOLDER) {
+    anyhow::bail!(
+      "--format=shell does not support --x11=nested, the nested X server is only started when running the sandbox"
+    );
+  }
+  if [SESSION_BUS_PROXY_PLACEHOLDER, SYSTEM_BUS_PROXY_PLACEHOLDER]
+    .iter()
+    .any(|placeholder| uses_placeholder(args, placeholder))
+  {
+    anyhow::bail!(
+      "--format=shell does not support --dbus=filtered, the D-Bus proxy is only started when running the sandbox"
+    );
+  }
+  // The filter cannot be passed from a shell script, see `seccomp::write_filter`.
+  if args.ops().any(|op| matches!(op, BwrapOp::Seccomp(_))) {
+    anyhow::bail!(
+      "--format=shell does not support seccomp filters, they are only compiled when running the sandbox; use --seccomp=off (and --allow-userns if bwrap is older than 0.8.0)"
+    );
+  }
+  let quote = |arg: &str| match arg {
+    EMPTY_FILE_PLACEHOLDER => "\"$empty_file\"".to_string(),
+    UNTRUSTED_XAUTHORITY_PLACEHOLDER => "\"$xauthority\"".to_string(),
+    _ => shell_quote(arg),
+  };
+  let mut output: Vec<String> = vec![
+    "#!/bin/sh".into(),
+    format!("# Generated by {} {}.", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+    "set -e".into(),
+    "empty_file=\"$(mktemp)\"".into(),
+  ];
+  if uses_placeholder(args, UNTRUSTED_XAUTHORITY_PLACEHOLDER) {
+    // Same as `xauth::generate_untrusted`, the entry written by xauth is bound to the hostname so
+    // its family is replaced by FamilyWild (ffff) to match the one of the sandbox.
+    output.extend([
+      "xauthority=\"$(mktemp)\"".into(),
+      "untrusted_cookie=\"$(mktemp)\"".into(),
+      "trap 'rm -f \"$empty_file\" \"$xauthority\" \"$untrusted_cookie\"' EXIT".into(),
+      "# untrusted X11 cookie (--x11-cookie=untrusted)".into(),
+      format!(
+        "xauth -q -f \"$untrusted_cookie\" generate \"$DISPLAY\" MIT-MAGIC-COOKIE-1 untrusted timeout {}",
+        UNTRUSTED_COOKIE_TIMEOUT
+      ),
+      "xauth -f \"$untrusted_cookie\" nlist | sed 's/^..../ffff/' | xauth -f \"$xauthority\" nmerge -"
+        .into(),
+    ]);
+  } else {
+    output.push("trap 'rm -f \"$empty_file\"' EXIT".into());
+  }
+  output.push("set --".into());
+  for group in &args.groups {
+    output.push(format!("# {} ({})", group.name, group.reason));
+    let lines: Vec<String> = group
+      .ops
+      .iter()
+      .map(|op| {
+        op.to_args()
+          .iter()
+          .map(|arg| quote(arg))
+          .collect::<Vec<String>>()
+          .join(" ")
+      })
+      .collect();
+    if lines.is_empty() {
+      continue;
+    }
+    output.push(format!("set -- \"$@\" \\\n  {}", lines.join(" \\\n  ")));
+  }
+  output.push("# command".into());
+  let command: Vec<String> = args.command.iter().map(|arg| quote(arg)).collect();
+  output.push(format!("set -- \"$@\" {}", command.join(" ")));
+  output.push("bwrap \"$@\"".into());
+  Ok(output.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::sandbox::bwrap_args::ArgGroup;
+
+  fn sample_args() -> BwrapArgs {
+    BwrapArgs {
+      groups: vec![
+        ArgGroup {
+          name: "seccomp",
+          reason: "--seccomp=default".into(),
+          ops: vec![BwrapOp::Seccomp(SECCOMP_FD_PLACEHOLDER.into())],
+        },
+        ArgGroup {
+          name: "network",
+          reason: "--network-mode=restricted_access".into(),
+          ops: vec![
+            BwrapOp::Tmpfs("/etc/ssl".into()),
+            BwrapOp::Bind {
+              src: EMPTY_FILE_PLACEHOLDER.into(),
+              dst: "/etc/hosts".into(),
+              ro: true,
+            },
+          ],
+        },
+        ArgGroup {
+          name: "env overrides",
+          reason: "--setenv".into(),
+          ops: vec![],
+        },
+      ],
+      command: ["wine", "/app/my game.exe", "--windowed"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect(),
+    }
+  }
+
+  #[test]
+  fn test_shell_quote() {
+    let test_cases = vec![
+      ("--bind", "--bind"),
+      ("/usr/share", "/usr/share"),
+      ("winemenubuilder=''", "'winemenubuilder='\\'''\\'''"),
+      ("my game", "'my game'"),
+      ("$HOME", "'$HOME'"),
+      ("", "''"),
+    ];
+    for (input, expected) in test_cases {
+      assert_eq!(shell_quote(input), expected);
+    }
+  }
+
+  #[test]
+  fn test_format_text() {
+    let expected = "# seccomp (--seccomp=default)
+  --seccomp '<seccomp filter>'
+# network (--network-mode=restricted_access)
+  --tmpfs /etc/ssl
+  --ro-bind '<empty file>' /etc/hosts
+# env overrides (--setenv)
+  (no arguments)
+# command
+  wine '/app/my game.exe' --windowed";
+    assert_eq!(format_text(&sample_args()), expected);
+  }
+
+  #[test]
+  fn test_format_shell() {
+    let expected = "#!/bin/sh
+# Generated by raptor-cage VERSION.
+set -e
+empty_file=\"$(mktemp)\"
+trap 'rm -f \"$empty_file\"' EXIT
+set --
+# network (--network-mode=restricted_access)
+set -- \"$@\" \\
+  --tmpfs /etc/ssl \\
+  --ro-bind \"$empty_file\" /etc/hosts
+# env overrides (--setenv)
+# command
+set -- \"$@\" wine '/app/my game.exe' --windowed
+bwrap \"$@\"";
+    let expected = expected.replace("VERSION", env!("CARGO_PKG_VERSION"));
+    let mut args = sample_args();
+    args.groups.retain(|group| group.name != "seccomp");
+    assert_eq!(format_shell(&args).unwrap(), expected);
+  }
+
+  #[test]
+  fn test_format_shell_seccomp() {
+    let error = format_shell(&sample_args()).unwrap_err();
+    assert!(error.to_string().contains("--seccomp=off"), "{}", error);
+  }
+
+  #[test]
+  fn test_format_shell_nested_x11() {
+    let mut args = sample_args();
+    args.groups.push(ArgGroup {
+      name: "x11",
+      reason: "--x11=nested, DISPLAY=:1".into(),
+      ops: vec![BwrapOp::Bind {
+        src: NESTED_XAUTHORITY_PLACEHOLDER.into(),
+        dst: NESTED_XAUTHORITY_PLACEHOLDER.into(),
+        ro: true,
+      }],
+    });
+    let error = format_shell(&args).unwrap_err();
+    assert!(error.to_string().contains("--x11=nested"), "{}", error);
+  }
+
+  #[test]
+  fn test_format_shell_dbus_proxy() {
+    let mut args = sample_args();
+    args.groups.push(ArgGroup {
+      name: "dbus",
+      reason: "--dbus=filtered".into(),
+      ops: vec![BwrapOp::Bind {
+        src: SESSION_BUS_PROXY_PLACEHOLDER.into(),
+        dst: "/run/user/1000/bus".into(),
+        ro: true,
+      }],
+    });
+    let error = format_shell(&args).unwrap_err();
+    assert!(error.to_string().contains("--dbus=filtered"), "{}", error);
+  }
+}
@@ -0,0 +1,139 @@
+use super::sandbox_config::deserialize_from_str;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+pub const MIN_GAMESCOPE_SHARPNESS: u8 = 0;
+pub const MAX_GAMESCOPE_SHARPNESS: u8 = 20;
+
+/// Upscale filters implemented by gamescope, unlike `UpscaleMode::Fsr` they work with any game
+/// (including native ones), as long as the game renders at a lower resolution than the output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GamescopeFilter {
+  /// AMD FidelityFX Super Resolution 1.0.
+  Fsr,
+  /// NVIDIA Image Scaling.
+  Nis,
+}
+
+impl FromStr for GamescopeFilter {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "fsr" | "f" => Ok(GamescopeFilter::Fsr),
+      "nis" | "n" => Ok(GamescopeFilter::Nis),
+      _ => Err(format!("Invalid gamescope filter: {}", s)),
+    }
+  }
+}
+
+impl fmt::Display for GamescopeFilter {
+  /// Same values as the ones accepted by `gamescope --filter`.
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let filter_str = match self {
+      GamescopeFilter::Fsr => "fsr",
+      GamescopeFilter::Nis => "nis",
+    };
+    write!(f, "{}", filter_str)
+  }
+}
+
+/// Runs the launched program as a child of gamescope, inside the sandbox. Gamescope runs nested
+/// i.e., as a window of the host display server, see https://github.com/ValveSoftware/gamescope.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Gamescope {
+  /// Additional gamescope options e.g., `-w 1280 -h 720 -W 2560 -H 1440 -r 60`.
+  pub args: Vec<String>,
+}
+
+impl Gamescope {
+  /// Wrap `command` so it runs under gamescope, optionally upscaled with the given filter and
+  /// sharpness (0 is the sharpest).
+  pub fn wrap_command(
+    &self,
+    upscale: Option<(GamescopeFilter, u8)>,
+    command: Vec<String>,
+  ) -> Vec<String> {
+    let mut wrapped = vec!["gamescope".to_string()];
+    if let Some((filter, sharpness)) = upscale {
+      wrapped.extend([
+        "--filter".into(),
+        filter.to_string(),
+        "--sharpness".into(),
+        sharpness.to_string(),
+      ]);
+    }
+    wrapped.extend(self.args.iter().cloned());
+    wrapped.push("--".into());
+    wrapped.extend(command);
+    wrapped
+  }
+}
+
+impl FromStr for Gamescope {
+  type Err = String;
+  /// Options are separated by whitespace, quoting is not supported.
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let args: Vec<String> = s.split_whitespace().map(String::from).collect();
+    if args.iter().any(|arg| arg == "--") {
+      return Err("Gamescope options must not contain --, the command is added by rcage".into());
+    }
+    Ok(Gamescope { args })
+  }
+}
+
+impl fmt::Display for Gamescope {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.args.join(" "))
+  }
+}
+
+impl Serialize for Gamescope {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for Gamescope {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserialize_from_str(deserializer)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_gamescope_parsing() {
+    let gamescope = Gamescope::from_str(" -w 1280  -h 720 ").unwrap();
+    assert_eq!(gamescope.args, vec!["-w", "1280", "-h", "720"]);
+    assert_eq!(gamescope.to_string(), "-w 1280 -h 720");
+    assert_eq!(Gamescope::from_str("").unwrap(), Gamescope::default());
+    assert!(Gamescope::from_str("-f -- game").is_err());
+  }
+
+  #[test]
+  fn test_wrap_command() {
+    let gamescope = Gamescope::from_str("-f").unwrap();
+    let command = vec!["wine".to_string(), "/app/game.exe".to_string()];
+    assert_eq!(
+      gamescope.wrap_command(None, command.clone()),
+      vec!["gamescope", "-f", "--", "wine", "/app/game.exe"]
+    );
+    assert_eq!(
+      gamescope.wrap_command(Some((GamescopeFilter::Nis, 5)), command),
+      vec![
+        "gamescope",
+        "--filter",
+        "nis",
+        "--sharpness",
+        "5",
+        "-f",
+        "--",
+        "wine",
+        "/app/game.exe"
+      ]
+    );
+  }
+}
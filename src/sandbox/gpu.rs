@@ -0,0 +1,513 @@
+use super::sandbox_config::deserialize_from_str;
+use anyhow::Context;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Where the kernel lists the DRM devices i.e., one `cardN` entry per GPU.
+pub const DRM_CLASS_DIR: &str = "/sys/class/drm";
+/// Where the NVIDIA driver describes each GPU, in a directory named after its PCI address.
+pub const NVIDIA_GPUS_DIR: &str = "/proc/driver/nvidia/gpus";
+/// Where the Vulkan loader looks for the drivers (ICDs) shipped by the distribution.
+pub const VULKAN_ICD_DIR: &str = "/usr/share/vulkan/icd.d";
+
+/// Manufacturer of a GPU, from its PCI vendor ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpuVendor {
+  Amd,
+  Intel,
+  Nvidia,
+  Other(u16),
+}
+
+impl GpuVendor {
+  fn from_pci_id(vendor_id: u16) -> Self {
+    match vendor_id {
+      0x1002 => GpuVendor::Amd,
+      0x8086 => GpuVendor::Intel,
+      0x10de => GpuVendor::Nvidia,
+      other => GpuVendor::Other(other),
+    }
+  }
+
+  /// Prefix of the device names reported by Vulkan e.g., "NVIDIA GeForce RTX 3060 Laptop GPU".
+  fn vulkan_name_prefix(self) -> Option<&'static str> {
+    match self {
+      GpuVendor::Amd => Some("AMD"),
+      GpuVendor::Intel => Some("Intel"),
+      GpuVendor::Nvidia => Some("NVIDIA"),
+      GpuVendor::Other(_) => None,
+    }
+  }
+}
+
+impl fmt::Display for GpuVendor {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      GpuVendor::Amd => write!(f, "AMD"),
+      GpuVendor::Intel => write!(f, "Intel"),
+      GpuVendor::Nvidia => write!(f, "NVIDIA"),
+      GpuVendor::Other(vendor_id) => write!(f, "vendor {:04x}", vendor_id),
+    }
+  }
+}
+
+/// GPU found under `/sys/class/drm`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gpu {
+  /// PCI address e.g., `0000:01:00.0`.
+  pub pci_slot: String,
+  pub vendor: GpuVendor,
+  /// Kernel driver e.g., `nvidia`, `nouveau`, `amdgpu` or `i915`.
+  pub driver: Option<String>,
+  /// Primary node e.g., `/dev/dri/card1`.
+  pub card: String,
+  /// Render node e.g., `/dev/dri/renderD129`.
+  pub render_node: Option<String>,
+  /// Minor number of the NVIDIA device i.e., `N` in `/dev/nvidiaN`, only with the NVIDIA driver.
+  pub nvidia_minor: Option<u32>,
+  /// Whether the firmware used this GPU to display the boot screen, it is the one Mesa uses by
+  /// default.
+  pub boot_vga: bool,
+  /// Whether the GPU is built into the CPU, see `detect_gpus`.
+  pub integrated: bool,
+}
+
+impl Gpu {
+  /// Whether the GPU uses the proprietary NVIDIA driver rather than Mesa.
+  pub fn is_nvidia_proprietary(&self) -> bool {
+    self.driver.as_deref() == Some("nvidia")
+  }
+
+  /// Path of the NVIDIA device e.g., `/dev/nvidia0`.
+  pub fn nvidia_device(&self) -> Option<String> {
+    self
+      .nvidia_minor
+      .map(|minor| format!("/dev/nvidia{}", minor))
+  }
+
+  /// Prefixes of the Vulkan ICD files that drive this GPU e.g., `radeon_icd.x86_64.json` (RADV)
+  /// and `amd_icd64.json` (AMDVLK), `None` if the vendor is unknown.
+  pub fn vulkan_icd_prefixes(&self) -> Option<&'static [&'static str]> {
+    match self.vendor {
+      GpuVendor::Nvidia if self.is_nvidia_proprietary() => Some(&["nvidia_icd"]),
+      GpuVendor::Nvidia => Some(&["nouveau_icd"]),
+      GpuVendor::Amd => Some(&["radeon_icd", "amd_"]),
+      GpuVendor::Intel => Some(&["intel_"]),
+      GpuVendor::Other(_) => None,
+    }
+  }
+
+  /// PCI bus of the GPU e.g., `0x01` for `0000:01:00.0`.
+  fn pci_bus(&self) -> Option<u8> {
+    let bus = self.pci_slot.split(':').nth(1)?;
+    u8::from_str_radix(bus, 16).ok()
+  }
+}
+
+impl fmt::Display for Gpu {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let kind = if self.integrated {
+      "integrated"
+    } else {
+      "dedicated"
+    };
+    write!(f, "{} {} ({}", self.pci_slot, self.vendor, kind)?;
+    if let Some(driver) = &self.driver {
+      write!(f, ", {} driver", driver)?;
+    }
+    write!(f, ", {}", self.card)?;
+    if let Some(render_node) = &self.render_node {
+      write!(f, ", {}", render_node)?;
+    }
+    if let Some(nvidia_device) = self.nvidia_device() {
+      write!(f, ", {}", nvidia_device)?;
+    }
+    if self.boot_vga {
+      write!(f, ", boot VGA")?;
+    }
+    write!(f, ")")
+  }
+}
+
+/// Find the GPUs listed in `drm_class_dir` (i.e. `DRM_CLASS_DIR`), sorted by PCI address. Devices
+/// that are not on the PCI bus (e.g. simpledrm) are ignored. NVIDIA device numbers are read from
+/// `nvidia_gpus_dir` (i.e. `NVIDIA_GPUS_DIR`).
+///
+/// Telling integrated GPUs apart is a heuristic: NVIDIA does not make x86 iGPUs, Intel places them
+/// on the root bus (unlike Arc cards), and AMD APUs drive the boot screen of hybrid laptops, which
+/// is also what Mesa assumes with `DRI_PRIME=1`.
+pub fn detect_gpus(drm_class_dir: &Path, nvidia_gpus_dir: &Path) -> anyhow::Result<Vec<Gpu>> {
+  let mut gpus = Vec::new();
+  // Without any DRM driver loaded (e.g. in a container) the class is not registered.
+  let entries = match fs::read_dir(drm_class_dir) {
+    Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(gpus),
+    entries => {
+      entries.with_context(|| format!("Failed to read directory: {}", drm_class_dir.display()))?
+    }
+  };
+  for entry in entries.flatten() {
+    let name = entry.file_name().to_string_lossy().into_owned();
+    // Skip the connectors e.g., card1-eDP-1, and the render nodes, which are found below.
+    let is_card = name
+      .strip_prefix("card")
+      .is_some_and(|index| !index.is_empty() && index.chars().all(|c| c.is_ascii_digit()));
+    if !is_card {
+      continue;
+    }
+    let device_dir = entry.path().join("device");
+    let Some(vendor_id) = read_pci_id(&device_dir.join("vendor")) else {
+      continue;
+    };
+    let Some(pci_slot) = fs::canonicalize(&device_dir)
+      .ok()
+      .and_then(|path| Some(path.file_name()?.to_string_lossy().into_owned()))
+    else {
+      continue;
+    };
+    let driver = fs::read_link(device_dir.join("driver"))
+      .ok()
+      .and_then(|path| Some(path.file_name()?.to_string_lossy().into_owned()));
+    let render_node = fs::read_dir(device_dir.join("drm"))
+      .into_iter()
+      .flatten()
+      .flatten()
+      .map(|entry| entry.file_name().to_string_lossy().into_owned())
+      .find(|name| name.starts_with("renderD"))
+      .map(|name| format!("/dev/dri/{}", name));
+    let boot_vga =
+      fs::read_to_string(device_dir.join("boot_vga")).is_ok_and(|value| value.trim() == "1");
+    let nvidia_minor = read_nvidia_minor(&nvidia_gpus_dir.join(&pci_slot).join("information"));
+    gpus.push(Gpu {
+      pci_slot,
+      vendor: GpuVendor::from_pci_id(vendor_id),
+      driver,
+      card: format!("/dev/dri/{}", name),
+      render_node,
+      nvidia_minor,
+      boot_vga,
+      integrated: false,
+    });
+  }
+  gpus.sort_by(|a, b| a.pci_slot.cmp(&b.pci_slot));
+  let hybrid = gpus.len() > 1;
+  for gpu in &mut gpus {
+    gpu.integrated = match gpu.vendor {
+      GpuVendor::Nvidia => false,
+      GpuVendor::Intel => gpu.pci_bus() == Some(0),
+      GpuVendor::Amd | GpuVendor::Other(_) => hybrid && gpu.boot_vga,
+    };
+  }
+  Ok(gpus)
+}
+
+/// GPUs of the machine running raptor-cage.
+pub fn detect_host_gpus() -> anyhow::Result<Vec<Gpu>> {
+  detect_gpus(Path::new(DRM_CLASS_DIR), Path::new(NVIDIA_GPUS_DIR))
+}
+
+/// Read the `Device Minor: 0` line of the NVIDIA GPU information.
+fn read_nvidia_minor(path: &Path) -> Option<u32> {
+  let information = fs::read_to_string(path).ok()?;
+  information.lines().find_map(|line| {
+    let (key, value) = line.split_once(':')?;
+    (key.trim() == "Device Minor").then(|| value.trim().parse().ok())?
+  })
+}
+
+/// Read an ID such as `0x10de` from sysfs.
+fn read_pci_id(path: &Path) -> Option<u16> {
+  let value = fs::read_to_string(path).ok()?;
+  u16::from_str_radix(value.trim().strip_prefix("0x")?, 16).ok()
+}
+
+/// Controls which GPU renders the games on systems with more than one e.g., hybrid laptops.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GpuSelection {
+  /// Nothing is configured, the drivers pick the GPU, usually the integrated one.
+  None,
+  /// The dedicated GPU if any, otherwise the only one.
+  Auto,
+  /// The GPU built into the CPU.
+  Integrated,
+  /// The first dedicated GPU, by PCI address.
+  Dedicated,
+  /// The GPU at the given PCI address e.g., `0000:01:00.0`.
+  Pci(String),
+}
+
+impl GpuSelection {
+  /// Find the selected GPU, `Ok(None)` if none is selected, or there is no GPU with `Auto`.
+  pub fn resolve<'a>(&self, gpus: &'a [Gpu]) -> anyhow::Result<Option<&'a Gpu>> {
+    let integrated = gpus.iter().find(|gpu| gpu.integrated);
+    let dedicated = gpus.iter().find(|gpu| !gpu.integrated);
+    match self {
+      GpuSelection::None => Ok(None),
+      GpuSelection::Auto => Ok(dedicated.or(integrated)),
+      GpuSelection::Integrated => integrated.map(Some).ok_or_else(|| {
+        anyhow::anyhow!("No integrated GPU found, see `rcage list --category gpus`")
+      }),
+      GpuSelection::Dedicated => dedicated
+        .map(Some)
+        .ok_or_else(|| anyhow::anyhow!("No dedicated GPU found, see `rcage list --category gpus`")),
+      GpuSelection::Pci(pci_slot) => gpus
+        .iter()
+        .find(|gpu| &gpu.pci_slot == pci_slot)
+        .map(Some)
+        .ok_or_else(|| {
+          anyhow::anyhow!("No GPU found at {}, see `rcage list --category gpus`", pci_slot)
+        }),
+    }
+  }
+}
+
+/// Environment variables that make OpenGL, Vulkan and DXVK render on `gpu`, picked out of `gpus`.
+/// See the PRIME section of the README.
+pub fn get_gpu_env(gpu: &Gpu, gpus: &[Gpu]) -> Vec<(&'static str, String)> {
+  let mut env = Vec::new();
+  if gpu.is_nvidia_proprietary() {
+    // Render offload is only needed when another GPU drives the display.
+    if !gpu.boot_vga {
+      env.push(("__NV_PRIME_RENDER_OFFLOAD", "1".into()));
+    }
+    env.push(("__GLX_VENDOR_LIBRARY_NAME", "nvidia".into()));
+    env.push(("__VK_LAYER_NV_optimus", "NVIDIA_only".into()));
+  } else {
+    // Mesa accepts the PCI address as a tag, which also reorders the Vulkan devices.
+    env.push(("DRI_PRIME", format!("pci-{}", gpu.pci_slot.replace([':', '.'], "_"))));
+    env.push(("__GLX_VENDOR_LIBRARY_NAME", "mesa".into()));
+    env.push(("__VK_LAYER_NV_optimus", "non_NVIDIA_only".into()));
+  }
+  // DXVK matches a substring of the Vulkan device name, which is only unique per vendor.
+  let same_vendor = gpus
+    .iter()
+    .filter(|other| other.vendor == gpu.vendor)
+    .count();
+  if let Some(prefix) = gpu.vendor.vulkan_name_prefix()
+    && same_vendor == 1
+  {
+    env.push(("DXVK_FILTER_DEVICE_NAME", prefix.into()));
+  }
+  env
+}
+
+/// Expand a PCI address to its full form e.g., `01:00.0` to `0000:01:00.0`.
+fn parse_pci_slot(s: &str) -> Option<String> {
+  let pci_slot = if s.matches(':').count() == 1 {
+    format!("0000:{}", s)
+  } else {
+    s.to_string()
+  };
+  let (domain, rest) = pci_slot.split_once(':')?;
+  let (bus, rest) = rest.split_once(':')?;
+  let (device, function) = rest.split_once('.')?;
+  let is_hex =
+    |value: &str, len: usize| value.len() == len && value.chars().all(|c| c.is_ascii_hexdigit());
+  (is_hex(domain, 4) && is_hex(bus, 2) && is_hex(device, 2) && is_hex(function, 1))
+    .then(|| pci_slot.to_lowercase())
+}
+
+impl FromStr for GpuSelection {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "none" | "n" => Ok(GpuSelection::None),
+      "auto" | "a" => Ok(GpuSelection::Auto),
+      "integrated" | "i" => Ok(GpuSelection::Integrated),
+      "dedicated" | "d" => Ok(GpuSelection::Dedicated),
+      _ => parse_pci_slot(s)
+        .map(GpuSelection::Pci)
+        .ok_or_else(|| format!("Invalid GPU: {}", s)),
+    }
+  }
+}
+
+impl fmt::Display for GpuSelection {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      GpuSelection::None => write!(f, "none"),
+      GpuSelection::Auto => write!(f, "auto"),
+      GpuSelection::Integrated => write!(f, "integrated"),
+      GpuSelection::Dedicated => write!(f, "dedicated"),
+      GpuSelection::Pci(pci_slot) => write!(f, "{}", pci_slot),
+    }
+  }
+}
+
+impl Serialize for GpuSelection {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for GpuSelection {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserialize_from_str(deserializer)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::os::unix::fs::symlink;
+  use tempfile::TempDir;
+
+  /// Lay out a GPU the way sysfs does, with `cardN` linking to the PCI device.
+  fn add_gpu(sys: &Path, pci_slot: &str, vendor: &str, driver: &str, card: u8, boot_vga: bool) {
+    let device_dir = sys.join("devices").join(pci_slot);
+    fs::create_dir_all(device_dir.join("drm").join(format!("card{}", card))).unwrap();
+    fs::create_dir_all(
+      device_dir
+        .join("drm")
+        .join(format!("renderD{}", 128 + card)),
+    )
+    .unwrap();
+    fs::write(device_dir.join("vendor"), format!("{}\n", vendor)).unwrap();
+    fs::write(device_dir.join("boot_vga"), if boot_vga { "1\n" } else { "0\n" }).unwrap();
+    let driver_dir = sys.join("drivers").join(driver);
+    fs::create_dir_all(&driver_dir).unwrap();
+    symlink(&driver_dir, device_dir.join("driver")).unwrap();
+    let card_dir = sys.join("class/drm").join(format!("card{}", card));
+    fs::create_dir_all(&card_dir).unwrap();
+    symlink(&device_dir, card_dir.join("device")).unwrap();
+    // Connectors are listed too.
+    fs::create_dir_all(sys.join("class/drm").join(format!("card{}-eDP-1", card))).unwrap();
+    if driver == "nvidia" {
+      let information_dir = sys.join("nvidia").join(pci_slot);
+      fs::create_dir_all(&information_dir).unwrap();
+      let information = "Model: \t\t NVIDIA GeForce RTX 3060 Laptop GPU\nDevice Minor: \t 0\n";
+      fs::write(information_dir.join("information"), information).unwrap();
+    }
+  }
+
+  fn detect(sys: &TempDir) -> Vec<Gpu> {
+    detect_gpus(&sys.path().join("class/drm"), &sys.path().join("nvidia")).unwrap()
+  }
+
+  fn intel_nvidia_laptop() -> TempDir {
+    let sys = TempDir::new().unwrap();
+    add_gpu(sys.path(), "0000:01:00.0", "0x10de", "nvidia", 1, false);
+    add_gpu(sys.path(), "0000:00:02.0", "0x8086", "i915", 0, true);
+    sys
+  }
+
+  #[test]
+  fn test_detect_gpus() {
+    let sys = intel_nvidia_laptop();
+    // simpledrm is not a PCI device.
+    fs::create_dir_all(sys.path().join("class/drm/card2/device")).unwrap();
+    let gpus = detect(&sys);
+    assert_eq!(
+      gpus,
+      vec![
+        Gpu {
+          pci_slot: "0000:00:02.0".into(),
+          vendor: GpuVendor::Intel,
+          driver: Some("i915".into()),
+          card: "/dev/dri/card0".into(),
+          render_node: Some("/dev/dri/renderD128".into()),
+          nvidia_minor: None,
+          boot_vga: true,
+          integrated: true,
+        },
+        Gpu {
+          pci_slot: "0000:01:00.0".into(),
+          vendor: GpuVendor::Nvidia,
+          driver: Some("nvidia".into()),
+          card: "/dev/dri/card1".into(),
+          render_node: Some("/dev/dri/renderD129".into()),
+          nvidia_minor: Some(0),
+          boot_vga: false,
+          integrated: false,
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn test_detect_amd_gpus() {
+    let sys = TempDir::new().unwrap();
+    add_gpu(sys.path(), "0000:03:00.0", "0x1002", "amdgpu", 0, false);
+    add_gpu(sys.path(), "0000:c4:00.0", "0x1002", "amdgpu", 1, true);
+    let gpus = detect(&sys);
+    let integrated: Vec<bool> = gpus.iter().map(|gpu| gpu.integrated).collect();
+    assert_eq!(integrated, vec![false, true]);
+    assert!(
+      detect_gpus(&sys.path().join("missing"), &sys.path().join("missing"))
+        .unwrap()
+        .is_empty()
+    );
+    // Without a second GPU the boot one is not assumed to be integrated.
+    let sys = TempDir::new().unwrap();
+    add_gpu(sys.path(), "0000:03:00.0", "0x1002", "amdgpu", 0, true);
+    let gpus = detect(&sys);
+    assert!(!gpus[0].integrated);
+  }
+
+  #[test]
+  fn test_gpu_selection_parsing() {
+    let test_cases = vec![
+      ("none", Ok(GpuSelection::None)),
+      ("a", Ok(GpuSelection::Auto)),
+      ("Integrated", Ok(GpuSelection::Integrated)),
+      ("dedicated", Ok(GpuSelection::Dedicated)),
+      ("0000:01:00.0", Ok(GpuSelection::Pci("0000:01:00.0".into()))),
+      ("C4:00.0", Ok(GpuSelection::Pci("0000:c4:00.0".into()))),
+      ("01:00", Err("Invalid GPU: 01:00".to_string())),
+      ("nvidia", Err("Invalid GPU: nvidia".to_string())),
+    ];
+    for (input, expected) in test_cases {
+      assert_eq!(input.parse::<GpuSelection>(), expected, "input: {}", input);
+    }
+  }
+
+  #[test]
+  fn test_gpu_selection() {
+    let sys = intel_nvidia_laptop();
+    let gpus = detect(&sys);
+    let resolve = |selection: GpuSelection| {
+      selection
+        .resolve(&gpus)
+        .unwrap()
+        .map(|gpu| gpu.pci_slot.clone())
+    };
+    assert_eq!(resolve(GpuSelection::None), None);
+    assert_eq!(resolve(GpuSelection::Auto), Some("0000:01:00.0".into()));
+    assert_eq!(resolve(GpuSelection::Dedicated), Some("0000:01:00.0".into()));
+    assert_eq!(resolve(GpuSelection::Integrated), Some("0000:00:02.0".into()));
+    assert!(
+      GpuSelection::Pci("0000:02:00.0".into())
+        .resolve(&gpus)
+        .is_err()
+    );
+    assert!(GpuSelection::Dedicated.resolve(&gpus[..1]).is_err());
+    assert_eq!(GpuSelection::Auto.resolve(&[]).unwrap(), None);
+  }
+
+  #[test]
+  fn test_gpu_env() {
+    let sys = intel_nvidia_laptop();
+    let gpus = detect(&sys);
+    assert_eq!(
+      get_gpu_env(&gpus[1], &gpus),
+      vec![
+        ("__NV_PRIME_RENDER_OFFLOAD", "1".to_string()),
+        ("__GLX_VENDOR_LIBRARY_NAME", "nvidia".to_string()),
+        ("__VK_LAYER_NV_optimus", "NVIDIA_only".to_string()),
+        ("DXVK_FILTER_DEVICE_NAME", "NVIDIA".to_string()),
+      ]
+    );
+    assert_eq!(
+      get_gpu_env(&gpus[0], &gpus),
+      vec![
+        ("DRI_PRIME", "pci-0000_00_02_0".to_string()),
+        ("__GLX_VENDOR_LIBRARY_NAME", "mesa".to_string()),
+        ("__VK_LAYER_NV_optimus", "non_NVIDIA_only".to_string()),
+        ("DXVK_FILTER_DEVICE_NAME", "Intel".to_string()),
+      ]
+    );
+  }
+}
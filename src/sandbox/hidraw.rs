@@ -0,0 +1,242 @@
+use super::sandbox_config::deserialize_from_str;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Where the kernel lists the hidraw devices i.e., one `hidrawN` entry per `/dev/hidrawN` node.
+pub const HIDRAW_CLASS_DIR: &str = "/sys/class/hidraw";
+
+/// Vendor and product ID of a USB (or Bluetooth) HID device e.g., `054c:0ce6` for a DualSense.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UsbId {
+  pub vendor: u16,
+  pub product: u16,
+}
+
+impl FromStr for UsbId {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let parse_hex = |value: &str| {
+      (value.len() == 4)
+        .then(|| u16::from_str_radix(value, 16).ok())
+        .flatten()
+    };
+    s.split_once(':')
+      .and_then(|(vendor, product)| {
+        Some(UsbId {
+          vendor: parse_hex(vendor)?,
+          product: parse_hex(product)?,
+        })
+      })
+      .ok_or_else(|| format!("Invalid USB ID: {}, expected VID:PID e.g., 054c:0ce6", s))
+  }
+}
+
+impl fmt::Display for UsbId {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{:04x}:{:04x}", self.vendor, self.product)
+  }
+}
+
+const fn usb_id(vendor: u16, product: u16) -> UsbId {
+  UsbId { vendor, product }
+}
+
+/// Controllers that need hidraw for rumble, gyro, LEDs or their touchpad, used by the
+/// `known-controllers` preset.
+pub const KNOWN_CONTROLLERS: [(UsbId, &str); 11] = [
+  (usb_id(0x054c, 0x05c4), "Sony DualShock 4"),
+  (usb_id(0x054c, 0x09cc), "Sony DualShock 4 (2nd gen)"),
+  (usb_id(0x054c, 0x0ba0), "Sony DualShock 4 USB wireless adaptor"),
+  (usb_id(0x054c, 0x0ce6), "Sony DualSense"),
+  (usb_id(0x054c, 0x0df2), "Sony DualSense Edge"),
+  (usb_id(0x057e, 0x2006), "Nintendo Switch Joy-Con (L)"),
+  (usb_id(0x057e, 0x2007), "Nintendo Switch Joy-Con (R)"),
+  (usb_id(0x057e, 0x2009), "Nintendo Switch Pro Controller"),
+  (usb_id(0x28de, 0x1102), "Valve Steam Controller"),
+  (usb_id(0x28de, 0x1142), "Valve Steam Controller dongle"),
+  (usb_id(0x28de, 0x1205), "Valve Steam Deck"),
+];
+
+/// hidraw devices exposed to the sandbox, matched by their USB IDs so that other HID devices (e.g.
+/// security keys) stay hidden.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HidrawSelection {
+  /// Includes every device in `KNOWN_CONTROLLERS`.
+  pub known_controllers: bool,
+  pub ids: Vec<UsbId>,
+}
+
+impl HidrawSelection {
+  pub fn is_empty(&self) -> bool {
+    !self.known_controllers && self.ids.is_empty()
+  }
+
+  pub fn matches(&self, id: UsbId) -> bool {
+    self.ids.contains(&id)
+      || (self.known_controllers && KNOWN_CONTROLLERS.iter().any(|(known, _)| *known == id))
+  }
+}
+
+impl FromStr for HidrawSelection {
+  type Err = String;
+  /// A comma separated list of USB IDs and presets e.g., `known-controllers,046d:c21d`.
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let mut selection = HidrawSelection::default();
+    for item in s.split(',').map(str::trim) {
+      match item.to_lowercase().as_str() {
+        "none" | "n" => (),
+        "known-controllers" | "k" => selection.known_controllers = true,
+        _ => {
+          let id = item.parse()?;
+          if !selection.ids.contains(&id) {
+            selection.ids.push(id);
+          }
+        }
+      }
+    }
+    Ok(selection)
+  }
+}
+
+impl fmt::Display for HidrawSelection {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let mut items: Vec<String> = Vec::new();
+    if self.known_controllers {
+      items.push("known-controllers".into());
+    }
+    items.extend(self.ids.iter().map(ToString::to_string));
+    if items.is_empty() {
+      write!(f, "none")
+    } else {
+      write!(f, "{}", items.join(","))
+    }
+  }
+}
+
+impl Serialize for HidrawSelection {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for HidrawSelection {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserialize_from_str(deserializer)
+  }
+}
+
+/// A `/dev/hidrawN` node and the device behind it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HidrawDevice {
+  pub path: String,
+  pub id: UsbId,
+}
+
+/// Find the hidraw nodes listed in `hidraw_class_dir` (i.e. `HIDRAW_CLASS_DIR`), sorted by path.
+/// Nodes whose device cannot be identified are ignored.
+pub fn detect_hidraw_devices(hidraw_class_dir: &Path) -> Vec<HidrawDevice> {
+  let mut devices: Vec<HidrawDevice> = fs::read_dir(hidraw_class_dir)
+    .into_iter()
+    .flatten()
+    .flatten()
+    .filter_map(|entry| {
+      let uevent = fs::read_to_string(entry.path().join("device/uevent")).ok()?;
+      Some(HidrawDevice {
+        path: format!("/dev/{}", entry.file_name().to_string_lossy()),
+        id: parse_hid_id(&uevent)?,
+      })
+    })
+    .collect();
+  devices.sort_by(|a, b| a.path.cmp(&b.path));
+  devices
+}
+
+/// Read the `HID_ID=0003:0000054C:00000CE6` line of a HID device uevent, the first field is the
+/// bus (USB or Bluetooth), then the vendor and product IDs.
+fn parse_hid_id(uevent: &str) -> Option<UsbId> {
+  let hid_id = uevent
+    .lines()
+    .find_map(|line| line.strip_prefix("HID_ID="))?;
+  let mut fields = hid_id.trim().split(':').skip(1);
+  let vendor = u32::from_str_radix(fields.next()?, 16).ok()?;
+  let product = u32::from_str_radix(fields.next()?, 16).ok()?;
+  Some(UsbId {
+    vendor: u16::try_from(vendor).ok()?,
+    product: u16::try_from(product).ok()?,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tempfile::TempDir;
+
+  #[test]
+  fn test_hidraw_selection_parsing() {
+    let test_cases = vec![
+      ("none", Ok(HidrawSelection::default()), "none"),
+      (
+        "054C:0CE6, known-controllers,054c:0ce6",
+        Ok(HidrawSelection {
+          known_controllers: true,
+          ids: vec![usb_id(0x054c, 0x0ce6)],
+        }),
+        "known-controllers,054c:0ce6",
+      ),
+      ("054c", Err("Invalid USB ID: 054c, expected VID:PID e.g., 054c:0ce6".to_string()), ""),
+      ("54c:ce6", Err("Invalid USB ID: 54c:ce6, expected VID:PID e.g., 054c:0ce6".to_string()), ""),
+    ];
+    for (input, expected, display) in test_cases {
+      let selection = input.parse::<HidrawSelection>();
+      assert_eq!(selection, expected, "input: {}", input);
+      if let Ok(selection) = selection {
+        assert_eq!(selection.to_string(), display, "input: {}", input);
+      }
+    }
+  }
+
+  #[test]
+  fn test_hidraw_selection_matches() {
+    let dualsense = usb_id(0x054c, 0x0ce6);
+    let yubikey = usb_id(0x1050, 0x0407);
+    let known_controllers: HidrawSelection = "known-controllers".parse().unwrap();
+    assert!(known_controllers.matches(dualsense));
+    assert!(!known_controllers.matches(yubikey));
+    let explicit: HidrawSelection = "1050:0407".parse().unwrap();
+    assert!(!explicit.matches(dualsense));
+    assert!(explicit.matches(yubikey));
+  }
+
+  #[test]
+  fn test_detect_hidraw_devices() {
+    let sys = TempDir::new().unwrap();
+    let add_node = |name: &str, uevent: &str| {
+      let device_dir = sys.path().join(name).join("device");
+      fs::create_dir_all(&device_dir).unwrap();
+      fs::write(device_dir.join("uevent"), uevent).unwrap();
+    };
+    add_node(
+      "hidraw1",
+      "DRIVER=playstation\nHID_ID=0005:0000054C:00000CE6\nHID_NAME=DualSense Wireless Controller\n",
+    );
+    add_node("hidraw0", "DRIVER=hid-generic\nHID_ID=0003:00001050:00000407\n");
+    add_node("hidraw2", "DRIVER=hid-generic\n");
+    assert_eq!(
+      detect_hidraw_devices(sys.path()),
+      vec![
+        HidrawDevice {
+          path: "/dev/hidraw0".into(),
+          id: usb_id(0x1050, 0x0407),
+        },
+        HidrawDevice {
+          path: "/dev/hidraw1".into(),
+          id: usb_id(0x054c, 0x0ce6),
+        },
+      ]
+    );
+    assert!(detect_hidraw_devices(&sys.path().join("missing")).is_empty());
+  }
+}
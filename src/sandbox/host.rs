@@ -0,0 +1,196 @@
+use super::gpu::{self, Gpu};
+use super::hidraw::{self, HidrawDevice};
+use super::sandbox_config::current_timestamp_hex;
+use std::{env, fs, os::unix::fs::FileTypeExt, path::Path, process::Command};
+
+/// Version of bwrap as `(major, minor, patch)` e.g., `(0, 11, 0)`.
+pub type BwrapVersion = (u32, u32, u32);
+/// Version of the running kernel as `(major, minor)` e.g., `(6, 14)`.
+pub type KernelVersion = (u32, u32);
+
+/// Everything `bwrap::build_args` needs to know about the host, besides `RuntimeEnv`. Abstracted so
+/// the generated arguments can be tested without depending on the machine running the tests.
+pub trait HostProbe {
+  /// Paths of the character devices found directly under `/dev` e.g., `/dev/nvidia0`.
+  fn char_devices(&self) -> anyhow::Result<Vec<String>>;
+  /// GPUs of the machine, see `gpu::detect_gpus`.
+  fn gpus(&self) -> anyhow::Result<Vec<Gpu>>;
+  /// hidraw nodes of the machine, see `hidraw::detect_hidraw_devices`.
+  fn hidraw_devices(&self) -> Vec<HidrawDevice>;
+  /// Value of an environment variable of the current process, `None` if it is not set.
+  fn env_var(&self, name: &str) -> Option<String>;
+  /// Whether a file or directory exists on the host e.g., a library shipped by the runner.
+  fn path_exists(&self, path: &Path) -> bool;
+  /// Sorted names of the entries of a directory, empty if it cannot be read.
+  fn dir_entries(&self, path: &Path) -> Vec<String>;
+  /// Hostname used inside the sandbox.
+  fn hostname(&self) -> String;
+  /// Path of an empty file, bound over host files (e.g. `/etc/hostname`) to hide them.
+  fn empty_file_path(&self) -> &str;
+  /// File descriptor of the compiled seccomp filter, inherited by bwrap.
+  fn seccomp_fd(&self) -> &str;
+  /// Version of the installed bwrap, `None` if it could not be determined.
+  fn bwrap_version(&self) -> Option<BwrapVersion>;
+  /// Version of the running kernel, `None` if it could not be determined.
+  fn kernel_version(&self) -> Option<KernelVersion>;
+  /// Whether a file contains the given bytes e.g., an environment variable read by a runner binary.
+  fn file_contains(&self, path: &Path, needle: &[u8]) -> bool;
+}
+
+/// Probes the machine the sandbox runs on.
+pub struct SystemHost {
+  empty_file_path: String,
+  seccomp_fd: String,
+  bwrap_version: Option<BwrapVersion>,
+}
+
+impl SystemHost {
+  /// The file at `empty_file_path`, and the one open as `seccomp_fd`, must exist for as long as the
+  /// sandbox is being set up. The version is given rather than detected, as the seccomp filter
+  /// depends on it too, see `detect_bwrap_version`.
+  pub fn new(
+    empty_file_path: impl Into<String>,
+    seccomp_fd: impl Into<String>,
+    bwrap_version: Option<BwrapVersion>,
+  ) -> Self {
+    Self {
+      empty_file_path: empty_file_path.into(),
+      seccomp_fd: seccomp_fd.into(),
+      bwrap_version,
+    }
+  }
+}
+
+/// Run `bwrap --version`, which prints e.g., `bubblewrap 0.11.0`.
+pub fn detect_bwrap_version() -> Option<BwrapVersion> {
+  let output = Command::new("bwrap").arg("--version").output().ok()?;
+  parse_bwrap_version(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn parse_bwrap_version(output: &str) -> Option<BwrapVersion> {
+  let version = output.trim().strip_prefix("bubblewrap ")?;
+  // Only the numeric prefix of each component counts e.g., `0.8.0-rc1`.
+  let mut components = version.split('.').map(|component| {
+    let digits: String = component
+      .chars()
+      .take_while(|c| c.is_ascii_digit())
+      .collect();
+    digits.parse::<u32>().ok()
+  });
+  let major = components.next()??;
+  let minor = components.next()??;
+  let patch = components.next().flatten().unwrap_or(0);
+  Some((major, minor, patch))
+}
+
+/// Parse the kernel release e.g., `6.14.2-arch1-1`.
+fn parse_kernel_version(release: &str) -> Option<KernelVersion> {
+  let mut components = release.trim().split(['.', '-']);
+  let major = components.next()?.parse().ok()?;
+  let minor = components.next()?.parse().ok()?;
+  Some((major, minor))
+}
+
+impl HostProbe for SystemHost {
+  fn char_devices(&self) -> anyhow::Result<Vec<String>> {
+    let mut devices = Vec::new();
+    let entries = fs::read_dir("/dev")?;
+    for entry in entries.flatten() {
+      let path = entry.path();
+      let metadata = path.metadata()?;
+      if !metadata.file_type().is_char_device() {
+        continue;
+      }
+      if let Some(path_str) = path.to_str() {
+        devices.push(path_str.to_string());
+      }
+    }
+    Ok(devices)
+  }
+
+  fn gpus(&self) -> anyhow::Result<Vec<Gpu>> {
+    gpu::detect_host_gpus()
+  }
+
+  fn hidraw_devices(&self) -> Vec<HidrawDevice> {
+    hidraw::detect_hidraw_devices(Path::new(hidraw::HIDRAW_CLASS_DIR))
+  }
+
+  fn env_var(&self, name: &str) -> Option<String> {
+    env::var(name).ok()
+  }
+
+  fn path_exists(&self, path: &Path) -> bool {
+    path.exists()
+  }
+
+  fn dir_entries(&self, path: &Path) -> Vec<String> {
+    let mut entries: Vec<String> = fs::read_dir(path)
+      .into_iter()
+      .flatten()
+      .flatten()
+      .map(|entry| entry.file_name().to_string_lossy().into_owned())
+      .collect();
+    entries.sort();
+    entries
+  }
+
+  /// Based on the current timestamp, so each sandbox gets a different hostname.
+  fn hostname(&self) -> String {
+    current_timestamp_hex()
+  }
+
+  fn empty_file_path(&self) -> &str {
+    &self.empty_file_path
+  }
+
+  fn seccomp_fd(&self) -> &str {
+    &self.seccomp_fd
+  }
+
+  fn bwrap_version(&self) -> Option<BwrapVersion> {
+    self.bwrap_version
+  }
+
+  fn kernel_version(&self) -> Option<KernelVersion> {
+    let release = fs::read_to_string("/proc/sys/kernel/osrelease").ok()?;
+    parse_kernel_version(&release)
+  }
+
+  fn file_contains(&self, path: &Path, needle: &[u8]) -> bool {
+    fs::read(path).is_ok_and(|content| content.windows(needle.len()).any(|window| window == needle))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_bwrap_version() {
+    let test_cases = vec![
+      ("bubblewrap 0.11.0\n", Some((0, 11, 0))),
+      ("bubblewrap 0.8.0-rc1", Some((0, 8, 0))),
+      ("bubblewrap 0.4", Some((0, 4, 0))),
+      ("bubblewrap", None),
+      ("", None),
+    ];
+    for (input, expected) in test_cases {
+      assert_eq!(parse_bwrap_version(input), expected, "input: {}", input);
+    }
+  }
+
+  #[test]
+  fn test_parse_kernel_version() {
+    let test_cases = vec![
+      ("6.14.2-arch1-1\n", Some((6, 14))),
+      ("5.16.0", Some((5, 16))),
+      ("6.15-rc1", Some((6, 15))),
+      ("6", None),
+      ("", None),
+    ];
+    for (input, expected) in test_cases {
+      assert_eq!(parse_kernel_version(input), expected, "input: {}", input);
+    }
+  }
+}
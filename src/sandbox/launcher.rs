@@ -0,0 +1,245 @@
+use super::bwrap;
+use super::dbus_proxy::DbusProxy;
+use super::explain::OutputFormat;
+use super::mount::MountMapping;
+use super::nested_x11::NestedServer;
+use super::registry::{Instance, Registry};
+use super::sandbox::{LaunchConfig, LaunchParams, RuntimeEnv, SandboxConfig};
+use std::io::{BufReader, PipeReader};
+use std::process::{Child, ExitStatus};
+use tempfile::NamedTempFile;
+
+/// High level entry point to run a program inside a sandbox, see `Sandbox::builder`.
+pub struct Sandbox {
+  /// Name shown in the instance registry e.g., the profile name.
+  pub name: String,
+  pub sandbox_config: SandboxConfig,
+  pub launch_config: LaunchConfig,
+  pub runtime_env: RuntimeEnv,
+  pub mount_mappings: Vec<MountMapping>,
+}
+
+impl Sandbox {
+  pub fn builder() -> SandboxBuilder {
+    SandboxBuilder::default()
+  }
+
+  /// Start the sandbox without waiting for it to finish, the output is inherited from the current
+  /// process.
+  pub fn spawn(&self) -> anyhow::Result<SandboxChild> {
+    bwrap::spawn(
+      &self.name,
+      &self.sandbox_config,
+      &self.launch_config,
+      &self.runtime_env,
+      &self.mount_mappings,
+    )
+  }
+
+  /// Get the bwrap command line that `spawn` would execute, see `bwrap::explain`.
+  pub fn explain(&self, format: OutputFormat) -> anyhow::Result<String> {
+    bwrap::explain(
+      &self.sandbox_config,
+      &self.launch_config,
+      &self.runtime_env,
+      &self.mount_mappings,
+      format,
+    )
+  }
+}
+
+/// Builds a `Sandbox`, options that are not set use the same defaults as `rcage run` i.e., a shell
+/// without Wine runner nor prefix, and a runtime environment read from the current process.
+#[derive(Default)]
+pub struct SandboxBuilder {
+  name: Option<String>,
+  sandbox_config: SandboxConfig,
+  launch_config: LaunchConfig,
+  runtime_env: Option<RuntimeEnv>,
+  mount_mappings: Vec<MountMapping>,
+}
+
+impl SandboxBuilder {
+  /// Name shown in the instance registry, defaults to the launched binary.
+  pub fn name(mut self, name: impl Into<String>) -> Self {
+    self.name = Some(name.into());
+    self
+  }
+
+  pub fn sandbox_config(mut self, sandbox_config: SandboxConfig) -> Self {
+    self.sandbox_config = sandbox_config;
+    self
+  }
+
+  pub fn launch_config(mut self, launch_config: LaunchConfig) -> Self {
+    self.launch_config = launch_config;
+    self
+  }
+
+  pub fn runtime_env(mut self, runtime_env: RuntimeEnv) -> Self {
+    self.runtime_env = Some(runtime_env);
+    self
+  }
+
+  /// Add a single mount point, mappings are applied in the same order they are added.
+  pub fn mount(mut self, mount_mapping: MountMapping) -> Self {
+    self.mount_mappings.push(mount_mapping);
+    self
+  }
+
+  pub fn mounts(mut self, mount_mappings: impl IntoIterator<Item = MountMapping>) -> Self {
+    self.mount_mappings.extend(mount_mappings);
+    self
+  }
+
+  pub fn build(self) -> anyhow::Result<Sandbox> {
+    let runtime_env = match self.runtime_env {
+      Some(runtime_env) => runtime_env,
+      None => RuntimeEnv::from_env()?,
+    };
+    let name = self
+      .name
+      .unwrap_or_else(|| match &self.launch_config.launch_params {
+        LaunchParams::Configured {
+          app_bin: Some(app_bin),
+          ..
+        } => app_bin.clone(),
+        _ => "shell".into(),
+      });
+    Ok(Sandbox {
+      name,
+      sandbox_config: self.sandbox_config,
+      launch_config: self.launch_config,
+      runtime_env,
+      mount_mappings: self.mount_mappings,
+    })
+  }
+
+  /// Shortcut for `build()` followed by `Sandbox::spawn`.
+  pub fn spawn(self) -> anyhow::Result<SandboxChild> {
+    self.build()?.spawn()
+  }
+}
+
+/// Handle to a running sandbox, dropping it does not stop the sandbox (but it does stop its nested X
+/// server, if any).
+pub struct SandboxChild {
+  child: Child,
+  // Bound over host files to hide them, kept alive for as long as the sandbox could be running.
+  _empty_file: NamedTempFile,
+  /// Read end of `--json-status-fd`, kept open because bwrap writes its exit code before exiting.
+  pub(super) status: BufReader<PipeReader>,
+  registration: Option<(Registry, Instance)>,
+  /// Private X server used by the sandbox (see `--x11`), stopped when this handle is dropped.
+  _nested_server: Option<NestedServer>,
+  /// Untrusted X11 cookie bound into the sandbox (see `--x11-cookie`).
+  _xauthority: Option<NamedTempFile>,
+  /// Filters the D-Bus access of the sandbox (see `--dbus`), stopped when this handle is dropped.
+  _dbus_proxy: Option<DbusProxy>,
+}
+
+impl SandboxChild {
+  pub(super) fn new(
+    child: Child,
+    empty_file: NamedTempFile,
+    status: BufReader<PipeReader>,
+  ) -> Self {
+    Self {
+      child,
+      _empty_file: empty_file,
+      status,
+      registration: None,
+      _nested_server: None,
+      _xauthority: None,
+      _dbus_proxy: None,
+    }
+  }
+
+  pub(super) fn set_nested_server(&mut self, server: NestedServer) {
+    self._nested_server = Some(server);
+  }
+
+  pub(super) fn set_xauthority(&mut self, xauthority: NamedTempFile) {
+    self._xauthority = Some(xauthority);
+  }
+
+  pub(super) fn set_dbus_proxy(&mut self, dbus_proxy: DbusProxy) {
+    self._dbus_proxy = Some(dbus_proxy);
+  }
+
+  /// Remove the instance from the registry once the sandbox exits.
+  pub(super) fn set_registration(&mut self, registry: Registry, instance: Instance) {
+    self.registration = Some((registry, instance));
+  }
+
+  /// Entry of the sandbox in the instance registry, `None` if it could not be registered.
+  pub fn instance(&self) -> Option<&Instance> {
+    self.registration.as_ref().map(|(_, instance)| instance)
+  }
+
+  /// Process id of bwrap (as seen from the host).
+  pub fn id(&self) -> u32 {
+    self.child.id()
+  }
+
+  fn unregister(&mut self) {
+    if let Some((registry, instance)) = self.registration.take() {
+      let _ = registry.unregister(&instance.id);
+    }
+  }
+
+  /// Block until the sandbox exits.
+  pub fn wait(&mut self) -> anyhow::Result<ExitStatus> {
+    let status = self.child.wait()?;
+    self.unregister();
+    Ok(status)
+  }
+
+  /// Get the exit status if the sandbox already exited, without blocking.
+  pub fn try_wait(&mut self) -> anyhow::Result<Option<ExitStatus>> {
+    let status = self.child.try_wait()?;
+    if status.is_some() {
+      self.unregister();
+    }
+    Ok(status)
+  }
+
+  /// Kill bwrap, the sandboxed processes are killed along with it (see `--die-with-parent`).
+  pub fn kill(&mut self) -> anyhow::Result<()> {
+    Ok(self.child.kill()?)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::sandbox::user_mapping::UserMapping;
+
+  #[test]
+  fn test_builder_defaults() {
+    let runtime_env = RuntimeEnv {
+      home_dir: "/home/user".into(),
+      dbus_session_bus_address: "unix:path=/run/user/1000/bus".into(),
+      dbus_system_bus_socket: Some("/run/dbus/system_bus_socket".into()),
+      xdg_runtime_dir: "/run/user/1000".into(),
+      original_path: "/usr/bin".into(),
+      display_address: Some(":0".into()),
+      xauthority_file: Some("/run/user/1000/xauth".into()),
+      wayland_display: None,
+      overrides: None,
+    };
+    let sandbox = Sandbox::builder()
+      .sandbox_config(SandboxConfig {
+        user_mapping: UserMapping::Custom(300_000, 300_000),
+        ..Default::default()
+      })
+      .runtime_env(runtime_env)
+      .build()
+      .unwrap();
+    assert!(sandbox.launch_config.runner_path.is_none());
+    assert!(sandbox.mount_mappings.is_empty());
+    let output = sandbox.explain(OutputFormat::Text).unwrap();
+    assert!(output.contains("  --uid 300000"));
+    assert!(output.contains("# launch (no --appdir, starting a shell)"));
+  }
+}
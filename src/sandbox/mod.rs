@@ -1,8 +1,25 @@
 pub mod bottles;
 pub mod bwrap;
+pub mod bwrap_args;
+pub mod control;
+pub mod dbus_proxy;
 mod display;
+pub mod explain;
+pub mod gamescope;
+pub mod gpu;
+pub mod hidraw;
+pub mod host;
+pub mod launcher;
 pub mod mount;
+pub mod nested_x11;
+pub mod registry;
+pub mod runtime_dir;
 pub mod sandbox;
 mod sandbox_config;
+pub mod seccomp;
 pub mod user_mapping;
+pub mod wait;
 pub mod wine;
+pub mod xauth;
+
+pub use launcher::{Sandbox, SandboxBuilder, SandboxChild};
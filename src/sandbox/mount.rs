@@ -1,3 +1,5 @@
+use super::sandbox_config::deserialize_from_str;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
 use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
@@ -55,6 +57,9 @@ impl fmt::Display for MountError {
   }
 }
 
+impl std::error::Error for MountError {}
+
+// TODO: deny other important dirs besides "/".
 fn resolve_mount_path(mount_path: &str) -> Result<PathBuf, MountError> {
   let path = PathBuf::from(mount_path);
   // This will help us identify paths that we want to be forbidden to mount (such as "/"), bwrap
@@ -68,7 +73,7 @@ fn resolve_mount_path(mount_path: &str) -> Result<PathBuf, MountError> {
 
 /// Contains the configuration for a single path and flags with the `path:flags`
 /// syntax. For multiple paths see `MountMapping`.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct MountConfig {
   pub path: PathBuf,
   pub writable: bool,
@@ -88,9 +93,31 @@ impl FromStr for MountConfig {
   }
 }
 
+impl fmt::Display for MountConfig {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.path.to_string_lossy())?;
+    if self.writable {
+      write!(f, ":rw")?;
+    }
+    Ok(())
+  }
+}
+
+impl Serialize for MountConfig {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for MountConfig {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserialize_from_str(deserializer)
+  }
+}
+
 /// Represents a source path, target path and mount flags, valid values look
 /// like `source:target:flags`.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct MountMapping {
   pub source_path: PathBuf,
   pub target_config: MountConfig,
@@ -115,6 +142,24 @@ impl FromStr for MountMapping {
   }
 }
 
+impl fmt::Display for MountMapping {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}:{}", self.source_path.to_string_lossy(), self.target_config)
+  }
+}
+
+impl Serialize for MountMapping {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for MountMapping {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserialize_from_str(deserializer)
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
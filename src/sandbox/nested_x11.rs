@@ -0,0 +1,229 @@
+use super::display::Display;
+use super::sandbox::RuntimeEnv;
+use super::sandbox_config::deserialize_from_str;
+use super::xauth::encode_xauthority;
+use anyhow::Context;
+use rand::Rng;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+use tempfile::NamedTempFile;
+
+/// Size of the nested X server screen when not set.
+const DEFAULT_NESTED_SIZE: (u32, u32) = (1280, 720);
+
+/// Controls which X server the sandbox connects to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum X11Mode {
+  /// The X server of the session, any X11 client can read the input and the contents of every
+  /// other window on the same server e.g., keylog or take screenshots of the whole desktop.
+  Host,
+  /// A private X server started by rcage (Xephyr, or rootful Xwayland on Wayland sessions) shown as
+  /// a window of the session, the sandbox can only see its own windows.
+  Nested { size: Option<(u32, u32)> },
+}
+
+impl FromStr for X11Mode {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let lower = s.to_lowercase();
+    match lower.as_str() {
+      "host" | "h" => return Ok(X11Mode::Host),
+      "nested" | "n" => return Ok(X11Mode::Nested { size: None }),
+      _ => (),
+    }
+    // Nested mode with a size looks like "nested:WIDTHxHEIGHT".
+    let size = lower
+      .strip_prefix("nested:")
+      .or_else(|| lower.strip_prefix("n:"))
+      .and_then(|size| size.split_once('x'))
+      .and_then(|(width, height)| Some((width.parse::<u32>().ok()?, height.parse::<u32>().ok()?)))
+      .filter(|(width, height)| *width > 0 && *height > 0);
+    match size {
+      Some(size) => Ok(X11Mode::Nested { size: Some(size) }),
+      None => Err(format!("Invalid X11 mode: {}", s)),
+    }
+  }
+}
+
+impl fmt::Display for X11Mode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      X11Mode::Host => write!(f, "host"),
+      X11Mode::Nested { size: None } => write!(f, "nested"),
+      X11Mode::Nested {
+        size: Some((width, height)),
+      } => write!(f, "nested:{}x{}", width, height),
+    }
+  }
+}
+
+impl Serialize for X11Mode {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for X11Mode {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserialize_from_str(deserializer)
+  }
+}
+
+/// Find an executable in the directories of `path_var` (same syntax as `PATH`).
+pub(super) fn find_program(name: &str, path_var: &str) -> Option<PathBuf> {
+  path_var
+    .split(':')
+    .map(|dir| Path::new(dir).join(name))
+    .find(|path| path.is_file())
+}
+
+/// First display number without an X11 socket, only a guess since another server could take it
+/// before ours starts. Nested servers pick their own number, this is meant for `explain`.
+pub fn guess_display_number() -> u32 {
+  (1..)
+    .find(|number| !Path::new(&Display::local(*number).get_socket_path()).exists())
+    .unwrap_or(1)
+}
+
+/// A private X server running on the host, stopped when dropped.
+pub struct NestedServer {
+  child: Child,
+  pub display: Display,
+  /// Contains a freshly generated cookie, only valid for this server.
+  pub xauthority: NamedTempFile,
+}
+
+impl NestedServer {
+  /// Start a nested X server as a window of the current session, and wait until it accepts
+  /// connections. The server picks a free display number and reports it through `-displayfd`.
+  pub fn start(size: Option<(u32, u32)>, runtime_env: &RuntimeEnv) -> anyhow::Result<Self> {
+    let (width, height) = size.unwrap_or(DEFAULT_NESTED_SIZE);
+    let geometry = format!("{}x{}", width, height);
+    let xwayland = find_program("Xwayland", &runtime_env.original_path);
+    let xephyr = find_program("Xephyr", &runtime_env.original_path);
+    let mut command = match (&runtime_env.wayland_display, xwayland, xephyr) {
+      (Some(_), Some(xwayland), _) => {
+        // Without -rootless, Xwayland shows the whole X screen in a single window.
+        let mut command = Command::new(xwayland);
+        command.args(["-geometry", &geometry]);
+        command
+      }
+      (_, _, Some(xephyr)) if runtime_env.display_address.is_some() => {
+        let mut command = Command::new(xephyr);
+        command.args(["-screen", &geometry, "-resizeable"]);
+        command
+      }
+      _ => anyhow::bail!(
+        "Could not start a nested X server, install Xephyr (or Xwayland on Wayland sessions)"
+      ),
+    };
+    let cookie: [u8; 16] = rand::thread_rng().r#gen();
+    let mut xauthority = NamedTempFile::new()?;
+    xauthority.write_all(&encode_xauthority(&cookie))?;
+    xauthority.flush()?;
+    let xauthority_path = xauthority
+      .path()
+      .to_str()
+      .context("Could not get Xauthority path")?
+      .to_string();
+    // Same as the status pipe of bwrap, the write end must be inherited by the server.
+    let (display_reader, display_writer) = io::pipe().context("Could not create display pipe")?;
+    let display_fd = display_writer.as_raw_fd();
+    command
+      .arg("-displayfd")
+      .arg(display_fd.to_string())
+      .args(["-auth", &xauthority_path, "-nolisten", "tcp", "-noreset"])
+      .stdin(Stdio::null())
+      .stdout(Stdio::null());
+    // SAFETY: fcntl is async-signal-safe, and the closure does not allocate.
+    unsafe {
+      command.pre_exec(move || {
+        if libc::fcntl(display_fd, libc::F_SETFD, 0) == -1 {
+          return Err(io::Error::last_os_error());
+        }
+        Ok(())
+      });
+    }
+    let mut child = command
+      .spawn()
+      .map_err(|e| anyhow::anyhow!("Could not spawn the nested X server: {}", e))?;
+    drop(display_writer);
+    // The server only reports its display number once it is ready to accept connections.
+    let mut line = String::new();
+    let _ = BufReader::new(display_reader).read_line(&mut line);
+    let Ok(display_number) = line.trim().parse::<u32>() else {
+      let _ = child.kill();
+      let _ = child.wait();
+      anyhow::bail!("The nested X server exited before reporting its display");
+    };
+    Ok(Self {
+      child,
+      display: Display::local(display_number),
+      xauthority,
+    })
+  }
+
+  /// Session as seen by the sandbox, i.e., the nested server replaces the one of the session.
+  pub fn runtime_env(&self, runtime_env: &RuntimeEnv) -> anyhow::Result<RuntimeEnv> {
+    let xauthority_path = self
+      .xauthority
+      .path()
+      .to_str()
+      .context("Could not get Xauthority path")?;
+    Ok(RuntimeEnv {
+      display_address: Some(self.display.to_string()),
+      xauthority_file: Some(xauthority_path.into()),
+      ..runtime_env.clone()
+    })
+  }
+}
+
+impl Drop for NestedServer {
+  /// SIGTERM lets the server remove its socket and lock file.
+  fn drop(&mut self) {
+    // SAFETY: kill has no memory safety requirements.
+    unsafe { libc::kill(self.child.id() as libc::pid_t, libc::SIGTERM) };
+    let _ = self.child.wait();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_x11_mode_parsing() {
+    let test_cases = vec![
+      ("host", Ok(X11Mode::Host)),
+      ("Nested", Ok(X11Mode::Nested { size: None })),
+      (
+        "nested:1920x1080",
+        Ok(X11Mode::Nested {
+          size: Some((1920, 1080)),
+        }),
+      ),
+      (
+        "n:800X600",
+        Ok(X11Mode::Nested {
+          size: Some((800, 600)),
+        }),
+      ),
+      ("nested:1920", Err(())),
+      ("nested:0x1080", Err(())),
+      ("xephyr", Err(())),
+    ];
+    for (input, expected) in test_cases {
+      let result = X11Mode::from_str(input).map_err(|_| ());
+      assert_eq!(result, expected, "input: {}", input);
+      if let Ok(mode) = result {
+        assert_eq!(X11Mode::from_str(&mode.to_string()), Ok(mode));
+      }
+    }
+  }
+}
@@ -0,0 +1,194 @@
+use super::sandbox::NetworkMode;
+use super::seccomp::SeccompLevel;
+use anyhow::Context;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::BTreeMap,
+  env, fs,
+  path::{Path, PathBuf},
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A running sandbox, recorded so it can be found by other `rcage` invocations.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Instance {
+  /// Random identifier, unique among the running instances.
+  pub id: String,
+  /// Profile used to start the sandbox, or the launched command if no profile was used.
+  pub name: String,
+  /// PID of the bwrap process.
+  pub pid: u32,
+  /// PID of the first process inside the sandbox, as seen from the host.
+  pub child_pid: u32,
+  /// Seconds since the UNIX epoch.
+  pub started_at: u64,
+  pub network_mode: NetworkMode,
+  /// Filter loaded in the sandbox, also loaded in the commands run by `rcage exec`.
+  pub seccomp: SeccompLevel,
+  pub deny_userns: bool,
+  pub runner: Option<PathBuf>,
+  pub prefix: Option<PathBuf>,
+  /// Environment and working directory of the launched command, used to run other commands in the
+  /// same conditions.
+  pub environment: BTreeMap<String, String>,
+  pub working_dir: String,
+}
+
+impl Instance {
+  pub fn new_id() -> String {
+    format!("{:08x}", rand::thread_rng().r#gen::<u32>())
+  }
+
+  pub fn now() -> u64 {
+    SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|duration| duration.as_secs())
+      .unwrap_or_default()
+  }
+
+  /// Check if bwrap is still running, a PID that was reused by another program does not count.
+  pub fn is_running(&self) -> bool {
+    match fs::read_to_string(format!("/proc/{}/comm", self.pid)) {
+      Ok(comm) => comm.trim_end() == "bwrap",
+      Err(_) => false,
+    }
+  }
+}
+
+/// Directory with one JSON file per running instance, entries of sandboxes that exited without
+/// removing their own entry (e.g. because rcage was killed) are pruned when listing.
+pub struct Registry {
+  dir: PathBuf,
+}
+
+impl Registry {
+  /// Use the registry under the given runtime directory (usually `$XDG_RUNTIME_DIR`).
+  pub fn new(runtime_dir: impl AsRef<Path>) -> Self {
+    Self {
+      dir: runtime_dir
+        .as_ref()
+        .join(env!("CARGO_PKG_NAME"))
+        .join("instances"),
+    }
+  }
+
+  pub fn from_env() -> anyhow::Result<Self> {
+    let runtime_dir = env::var("XDG_RUNTIME_DIR")
+      .context("Failed to read environment variable: XDG_RUNTIME_DIR")?;
+    Ok(Self::new(runtime_dir))
+  }
+
+  fn get_instance_path(&self, id: &str) -> PathBuf {
+    self.dir.join(format!("{}.json", id))
+  }
+
+  pub fn register(&self, instance: &Instance) -> anyhow::Result<()> {
+    fs::create_dir_all(&self.dir)
+      .with_context(|| format!("Failed to create directory: {}", self.dir.to_string_lossy()))?;
+    let path = self.get_instance_path(&instance.id);
+    let content = serde_json::to_string_pretty(instance)?;
+    fs::write(&path, content)
+      .with_context(|| format!("Failed to write instance: {}", path.to_string_lossy()))
+  }
+
+  pub fn unregister(&self, id: &str) -> anyhow::Result<()> {
+    let path = self.get_instance_path(id);
+    fs::remove_file(&path)
+      .with_context(|| format!("Failed to remove instance: {}", path.to_string_lossy()))
+  }
+
+  /// Get the running instances sorted by start time, removing the stale ones.
+  pub fn list(&self) -> anyhow::Result<Vec<Instance>> {
+    let mut instances = Vec::new();
+    if !self.dir.exists() {
+      return Ok(instances);
+    }
+    let entries = fs::read_dir(&self.dir)
+      .with_context(|| format!("Failed to read directory: {}", self.dir.to_string_lossy()))?;
+    for entry in entries.flatten() {
+      let path = entry.path();
+      if path.extension().is_none_or(|extension| extension != "json") {
+        continue;
+      }
+      // Unreadable entries are treated as stale, they could have been left by an older version.
+      let instance: Option<Instance> = fs::read_to_string(&path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok());
+      match instance {
+        Some(instance) if instance.is_running() => instances.push(instance),
+        _ => {
+          let _ = fs::remove_file(&path);
+        }
+      }
+    }
+    instances.sort_by_key(|instance| instance.started_at);
+    Ok(instances)
+  }
+
+  /// Find a running instance by ID or by name, a name must match a single instance.
+  pub fn find(&self, query: &str) -> anyhow::Result<Instance> {
+    let instances = self.list()?;
+    if let Some(instance) = instances.iter().find(|instance| instance.id == query) {
+      return Ok(instance.clone());
+    }
+    let mut matches: Vec<Instance> = instances
+      .into_iter()
+      .filter(|instance| instance.name == query)
+      .collect();
+    match matches.len() {
+      0 => anyhow::bail!("No running instance found: {}", query),
+      1 => Ok(matches.remove(0)),
+      _ => anyhow::bail!("Multiple instances named {} are running, use the ID instead", query),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::process;
+  use tempfile::TempDir;
+
+  fn sample_instance(id: &str, name: &str, pid: u32) -> Instance {
+    Instance {
+      id: id.into(),
+      name: name.into(),
+      pid,
+      child_pid: pid + 1,
+      started_at: 1_700_000_000,
+      network_mode: NetworkMode::NoAccess,
+      seccomp: SeccompLevel::Default,
+      deny_userns: false,
+      runner: Some("/runners/soda".into()),
+      prefix: None,
+      environment: BTreeMap::from([("WINEPREFIX".into(), "/var/lib/wine".into())]),
+      working_dir: "/app".into(),
+    }
+  }
+
+  #[test]
+  fn test_prune_stale_instances() {
+    let runtime_dir = TempDir::new().unwrap();
+    let registry = Registry::new(runtime_dir.path());
+    assert!(registry.list().unwrap().is_empty());
+    // The test process is not bwrap, so the entry must be considered stale.
+    let instance = sample_instance("0000abcd", "game", process::id());
+    registry.register(&instance).unwrap();
+    let path = registry.get_instance_path(&instance.id);
+    assert!(path.exists());
+    assert!(registry.list().unwrap().is_empty());
+    assert!(!path.exists());
+    assert!(registry.find("game").is_err());
+  }
+
+  #[test]
+  fn test_instance_serialization() {
+    let instance = sample_instance("0000abcd", "game", 100);
+    let json = serde_json::to_string(&instance).unwrap();
+    assert!(json.contains(r#""network_mode":"no_access""#));
+    assert!(json.contains(r#""seccomp":"default""#));
+    let parsed: Instance = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed, instance);
+  }
+}
@@ -0,0 +1,71 @@
+use super::sandbox_config::deserialize_from_str;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+/// Entry of the host XDG_RUNTIME_DIR exposed on request, on top of the ones needed by the enabled
+/// permissions e.g., `gamemode` or `discord-ipc-0`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeSocket {
+  /// Path relative to XDG_RUNTIME_DIR, without `..` components.
+  pub name: String,
+}
+
+impl FromStr for RuntimeSocket {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let path = Path::new(s);
+    let is_relative = !s.is_empty()
+      && path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if !is_relative {
+      return Err(format!("Invalid runtime socket, must be relative to XDG_RUNTIME_DIR: {}", s));
+    }
+    Ok(RuntimeSocket {
+      name: s.trim_end_matches('/').into(),
+    })
+  }
+}
+
+impl fmt::Display for RuntimeSocket {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.name)
+  }
+}
+
+impl Serialize for RuntimeSocket {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for RuntimeSocket {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserialize_from_str(deserializer)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_runtime_socket_parsing() {
+    let test_cases = vec![
+      ("gamemode", Ok("gamemode")),
+      ("discord-ipc-0", Ok("discord-ipc-0")),
+      ("app/com.discordapp.Discord/", Ok("app/com.discordapp.Discord")),
+      ("", Err(())),
+      ("/run/user/1000/bus", Err(())),
+      ("../1001/bus", Err(())),
+      ("pulse/../bus", Err(())),
+      ("./bus", Err(())),
+    ];
+    for (input, expected) in test_cases {
+      let result = RuntimeSocket::from_str(input).map_err(|_| ());
+      assert_eq!(result.map(|socket| socket.name), expected.map(String::from), "input: {}", input);
+    }
+  }
+}
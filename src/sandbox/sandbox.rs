@@ -1,14 +1,26 @@
 use super::bottles;
+use super::dbus_proxy::{self, DbusMode, DbusRule};
+use super::gamescope::Gamescope;
+use super::gpu::GpuSelection;
+use super::hidraw::HidrawSelection;
+use super::nested_x11::X11Mode;
+use super::runtime_dir::RuntimeSocket;
+use super::sandbox_config::deserialize_from_str;
+use super::seccomp::SeccompLevel;
 use super::user_mapping::UserMapping;
+use super::wait::WaitMode;
 use super::wine::{SyncMode, UpscaleMode};
+use super::xauth::X11Cookie;
 use anyhow::Context;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
 use std::env;
-use std::path::PathBuf;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
 
 /// Represents network configuration options.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum NetworkMode {
   /// Allows complete network access.
   FullAccess,
@@ -31,42 +43,302 @@ impl FromStr for NetworkMode {
   }
 }
 
-#[derive(Debug, Clone)]
+impl fmt::Display for NetworkMode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let mode_str = match self {
+      NetworkMode::FullAccess => "full_access",
+      NetworkMode::RestrictedAccess => "restricted_access",
+      NetworkMode::NoAccess => "no_access",
+    };
+    write!(f, "{}", mode_str)
+  }
+}
+
+impl Serialize for NetworkMode {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for NetworkMode {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserialize_from_str(deserializer)
+  }
+}
+
+/// Kind of devices that can be exposed to the sandbox, see `DeviceAccess`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DeviceClass {
+  /// Keyboards, mice and gamepads i.e., `/dev/input`.
+  Input,
+  /// `/dev/uinput`, used by some games and tools to create virtual gamepads. It also allows
+  /// injecting keystrokes into the host.
+  Uinput,
+  /// `/dev/dri` and the NVIDIA devices, or only the nodes of the GPU selected by `--gpu`.
+  Gpu,
+  /// Raw HID devices e.g., for the rumble, gyro and LEDs of some gamepads. Security keys are HID
+  /// devices too.
+  Hidraw,
+  /// ALSA devices i.e., `/dev/snd`, not needed when using an audio server.
+  Sound,
+  /// Cameras and capture cards i.e., `/dev/video*` and `/dev/media*`.
+  Video,
+  /// `/dev/ntsync`, already exposed with `--sync-mode=ntsync`.
+  Ntsync,
+}
+
+/// Classes exposed by `--device-access=minimal`. `Uinput` is opt-in since it allows injecting
+/// keystrokes into the host.
+const MINIMAL_DEVICE_CLASSES: [DeviceClass; 2] = [DeviceClass::Input, DeviceClass::Gpu];
+
+impl FromStr for DeviceClass {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "input" | "gamepad" => Ok(DeviceClass::Input),
+      "uinput" => Ok(DeviceClass::Uinput),
+      "gpu" => Ok(DeviceClass::Gpu),
+      "hidraw" => Ok(DeviceClass::Hidraw),
+      "sound" => Ok(DeviceClass::Sound),
+      "video" | "camera" => Ok(DeviceClass::Video),
+      "ntsync" => Ok(DeviceClass::Ntsync),
+      _ => Err(format!("Invalid device class: {}", s)),
+    }
+  }
+}
+
+impl fmt::Display for DeviceClass {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let class_str = match self {
+      DeviceClass::Input => "input",
+      DeviceClass::Uinput => "uinput",
+      DeviceClass::Gpu => "gpu",
+      DeviceClass::Hidraw => "hidraw",
+      DeviceClass::Sound => "sound",
+      DeviceClass::Video => "video",
+      DeviceClass::Ntsync => "ntsync",
+    };
+    write!(f, "{}", class_str)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum DeviceAccess {
   /// Allow access to all devices.
   All,
+  /// Only the devices of the given classes, plus the given device paths e.g., `/dev/ttyUSB0`.
+  Selected {
+    classes: Vec<DeviceClass>,
+    paths: Vec<String>,
+  },
+}
+
+impl DeviceAccess {
   /// Minimal set of input and GPU devices for games to work.
-  Minimal,
+  pub fn minimal() -> Self {
+    DeviceAccess::Selected {
+      classes: MINIMAL_DEVICE_CLASSES.to_vec(),
+      paths: Vec::new(),
+    }
+  }
+}
+
+/// Whether `path` is a file under /dev, `..` components are rejected so the path cannot escape it.
+fn is_device_path(path: &str) -> bool {
+  let mut components = Path::new(path).components();
+  if components.next() != Some(Component::RootDir)
+    || components.next() != Some(Component::Normal("dev".as_ref()))
+  {
+    return false;
+  }
+  let mut rest = components.peekable();
+  rest.peek().is_some() && rest.all(|component| matches!(component, Component::Normal(_)))
 }
 
 impl FromStr for DeviceAccess {
+  type Err = String;
+  /// Either `all`, or a comma separated list of classes, presets (`minimal` and `none`) and device
+  /// paths e.g., `minimal,sound,/dev/ttyUSB0`.
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    if matches!(s.to_lowercase().as_str(), "all" | "a") {
+      return Ok(DeviceAccess::All);
+    }
+    let mut classes = Vec::new();
+    let mut paths = Vec::new();
+    for item in s.split(',').map(str::trim) {
+      match item.to_lowercase().as_str() {
+        "minimal" | "m" => classes.extend(MINIMAL_DEVICE_CLASSES),
+        "none" | "n" => (),
+        _ if item.starts_with('/') => {
+          if !is_device_path(item) {
+            return Err(format!("Invalid device path: {}, it must be under /dev", item));
+          }
+          if !paths.iter().any(|path| path == item) {
+            paths.push(item.to_string());
+          }
+        }
+        _ => classes.push(item.parse()?),
+      }
+    }
+    classes.sort();
+    classes.dedup();
+    Ok(DeviceAccess::Selected { classes, paths })
+  }
+}
+
+impl fmt::Display for DeviceAccess {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      DeviceAccess::All => write!(f, "all"),
+      DeviceAccess::Selected { classes, paths } => {
+        if paths.is_empty() && classes.as_slice() == MINIMAL_DEVICE_CLASSES {
+          return write!(f, "minimal");
+        }
+        let items: Vec<String> = classes
+          .iter()
+          .map(ToString::to_string)
+          .chain(paths.iter().cloned())
+          .collect();
+        if items.is_empty() {
+          write!(f, "none")
+        } else {
+          write!(f, "{}", items.join(","))
+        }
+      }
+    }
+  }
+}
+
+impl Serialize for DeviceAccess {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for DeviceAccess {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserialize_from_str(deserializer)
+  }
+}
+
+/// Audio servers the sandboxed programs can connect to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AudioMode {
+  /// No audio at all.
+  None,
+  /// Only the PipeWire socket.
+  Pipewire,
+  /// Only the PulseAudio socket (also provided by pipewire-pulse), along with its cookie.
+  Pulse,
+  /// Both PulseAudio and PipeWire.
+  All,
+}
+
+impl FromStr for AudioMode {
   type Err = String;
   fn from_str(s: &str) -> Result<Self, Self::Err> {
     match s.to_lowercase().as_str() {
-      "all" | "a" => Ok(DeviceAccess::All),
-      "minimal" | "m" => Ok(DeviceAccess::Minimal),
-      _ => Err(format!("Invalid device access mode: {}", s)),
+      "none" | "n" => Ok(AudioMode::None),
+      "pipewire" | "pw" => Ok(AudioMode::Pipewire),
+      "pulse" | "pa" => Ok(AudioMode::Pulse),
+      "all" | "a" => Ok(AudioMode::All),
+      _ => Err(format!("Invalid audio mode: {}", s)),
     }
   }
 }
 
+impl fmt::Display for AudioMode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let mode_str = match self {
+      AudioMode::None => "none",
+      AudioMode::Pipewire => "pipewire",
+      AudioMode::Pulse => "pulse",
+      AudioMode::All => "all",
+    };
+    write!(f, "{}", mode_str)
+  }
+}
+
+impl Serialize for AudioMode {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for AudioMode {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserialize_from_str(deserializer)
+  }
+}
+
+/// Display servers the sandboxed programs can connect to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisplayServer {
+  /// Only X11 (or Xwayland), the most compatible option.
+  X11,
+  /// Only Wayland, X11 is not reachable from the sandbox. Wine programs need a runner that ships
+  /// the Wine Wayland driver.
+  Wayland,
+  /// Whatever the session provides, i.e., X11 if `DISPLAY` is set and Wayland if `WAYLAND_DISPLAY`
+  /// is set.
+  Auto,
+}
+
+impl FromStr for DisplayServer {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "x11" | "x" => Ok(DisplayServer::X11),
+      "wayland" | "w" => Ok(DisplayServer::Wayland),
+      "auto" | "a" => Ok(DisplayServer::Auto),
+      _ => Err(format!("Invalid display server: {}", s)),
+    }
+  }
+}
+
+impl fmt::Display for DisplayServer {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let server_str = match self {
+      DisplayServer::X11 => "x11",
+      DisplayServer::Wayland => "wayland",
+      DisplayServer::Auto => "auto",
+    };
+    write!(f, "{}", server_str)
+  }
+}
+
+impl Serialize for DisplayServer {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for DisplayServer {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserialize_from_str(deserializer)
+  }
+}
+
 fn get_env_var(name: &str) -> anyhow::Result<String> {
   env::var(name).with_context(|| format!("Failed to read environment variable: {}", name))
 }
 
-// TODO: as of 2024-09 Wine Wayland is not finished, add the necessary args when it gets shipped
-// with official releases. Wayland seems to also use a socket e.g. "/run/user/<uid>/wayland-0".
+#[derive(Clone)]
 pub struct RuntimeEnv {
   pub home_dir: String,
   pub dbus_session_bus_address: String,
+  /// Path of the D-Bus system bus socket, `None` if the system bus is not reachable through one.
+  pub dbus_system_bus_socket: Option<String>,
   pub xdg_runtime_dir: String,
   /// Represents the unmodified value of the PATH variable.
   pub original_path: String,
-  /// X11 display address, can look like `:0`, `:1` or `localhost:0.0`. This is required even if
-  /// running on Wayland.
-  pub display_address: String,
+  /// X11 display address, can look like `:0`, `:1` or `localhost:0.0`. Usually set on Wayland
+  /// sessions too, pointing to Xwayland.
+  pub display_address: Option<String>,
   /// Needed on X11 sessions, and by Gamescope.
-  pub xauthority_file: String,
+  pub xauthority_file: Option<String>,
+  /// Wayland socket name relative to `xdg_runtime_dir` (e.g. `wayland-0`), or an absolute path.
+  pub wayland_display: Option<String>,
   /// Additional env variables set (e.g. set by the user or Bottles).
   pub overrides: Option<HashMap<String, String>>,
 }
@@ -75,17 +347,26 @@ impl RuntimeEnv {
   pub fn from_env() -> anyhow::Result<Self> {
     let home_dir = get_env_var("HOME")?;
     let dbus_session_bus_address = get_env_var("DBUS_SESSION_BUS_ADDRESS")?;
+    // DBUS_SYSTEM_BUS_ADDRESS is rarely set, the system bus is usually at its default location.
+    let dbus_system_bus_socket = match env::var("DBUS_SYSTEM_BUS_ADDRESS") {
+      Ok(address) => dbus_proxy::bus_socket_path(&address).map(String::from),
+      Err(_) => Some(dbus_proxy::SYSTEM_BUS_SOCKET.into()),
+    };
     let xdg_runtime_dir = get_env_var("XDG_RUNTIME_DIR")?;
     let original_path = get_env_var("PATH")?;
-    let display_address = get_env_var("DISPLAY")?;
-    let xauthority_file = get_env_var("XAUTHORITY")?;
+    // Whether the display server variables are required depends on --display-server.
+    let display_address = env::var("DISPLAY").ok();
+    let xauthority_file = env::var("XAUTHORITY").ok();
+    let wayland_display = env::var("WAYLAND_DISPLAY").ok();
     Ok(Self {
       home_dir,
       dbus_session_bus_address,
+      dbus_system_bus_socket,
       xdg_runtime_dir,
       original_path,
       display_address,
       xauthority_file,
+      wayland_display,
       overrides: None,
     })
   }
@@ -102,6 +383,28 @@ pub struct SandboxConfig {
   pub network_mode: NetworkMode,
   /// Controls what devices are accessible from within the sandbox.
   pub device_access: DeviceAccess,
+  /// Controls which GPU renders the games on systems with more than one.
+  pub gpu: GpuSelection,
+  /// hidraw devices exposed even without the hidraw device class e.g., gamepads.
+  pub hidraw: HidrawSelection,
+  /// Controls which audio server sockets are exposed to the sandbox.
+  pub audio: AudioMode,
+  /// Controls which display server sockets are exposed to the sandbox.
+  pub display_server: DisplayServer,
+  /// Controls whether the X server of the session, or a private one, is exposed to the sandbox.
+  pub x11: X11Mode,
+  /// Controls the cookie used to connect to the X server of the session.
+  pub x11_cookie: X11Cookie,
+  /// Controls the access to the D-Bus session and system buses.
+  pub dbus: DbusMode,
+  /// Bus names the sandbox can see, talk to or own with `DbusMode::Filtered`.
+  pub dbus_allow: Vec<DbusRule>,
+  /// Additional entries of XDG_RUNTIME_DIR exposed to the sandbox.
+  pub runtime_sockets: Vec<RuntimeSocket>,
+  /// Controls the syscalls denied inside the sandbox.
+  pub seccomp: SeccompLevel,
+  /// Allows the sandbox to create nested user namespaces, which are denied by default.
+  pub allow_userns: bool,
   /// Configures various options such as WINEDEBUG and DXVK_LOG_LEVEL.
   pub verbose: bool,
 }
@@ -112,7 +415,18 @@ impl Default for SandboxConfig {
       namespace_isolation: true,
       user_mapping: UserMapping::Random,
       network_mode: NetworkMode::NoAccess,
-      device_access: DeviceAccess::Minimal,
+      device_access: DeviceAccess::minimal(),
+      gpu: GpuSelection::None,
+      hidraw: HidrawSelection::default(),
+      audio: AudioMode::All,
+      display_server: DisplayServer::Auto,
+      x11: X11Mode::Host,
+      x11_cookie: X11Cookie::Session,
+      dbus: DbusMode::Full,
+      dbus_allow: Vec::new(),
+      runtime_sockets: Vec::new(),
+      seccomp: SeccompLevel::Default,
+      allow_userns: false,
       verbose: false,
     }
   }
@@ -154,9 +468,6 @@ impl LaunchParams {
   }
 }
 
-// TODO: detect GPUs and configure environment to use the dedicated GPU, currently bottles uses
-// lspci and grep, however it does not seem to work in many scenarios. See
-// https://github.com/bottlesdevs/Bottles/blob/540f6fc0d4c2853e2a62cab98548ce3210c7352a/bottles/backend/utils/gpu.py.
 pub struct LaunchConfig {
   pub runner_path: Option<PathBuf>,
   pub prefix_path: Option<PathBuf>,
@@ -166,20 +477,27 @@ pub struct LaunchConfig {
   pub upscale_mode: Option<UpscaleMode>,
   /// Optional Wine sync mode.
   pub sync_mode: Option<SyncMode>,
+  /// Controls when the sandbox is torn down.
+  pub wait_mode: WaitMode,
+  /// Run the application under gamescope, inside the sandbox.
+  pub gamescope: Option<Gamescope>,
 }
 
 impl LaunchConfig {
+  /// Relative runner and prefix paths are resolved against `data_root`, or against the default
+  /// Bottles data root if not set.
   pub fn new(
     runner_path: Option<PathBuf>,
     prefix_path: Option<PathBuf>,
     launch_params: Option<LaunchParams>,
     upscale_mode: Option<UpscaleMode>,
     sync_mode: Option<SyncMode>,
+    data_root: Option<PathBuf>,
   ) -> anyhow::Result<Self> {
-    let data_root: Option<PathBuf> = if runner_path.is_some() || prefix_path.is_some() {
-      Some(bottles::get_data_root()?)
-    } else {
-      None
+    let data_root: Option<PathBuf> = match data_root {
+      Some(data_root) => Some(data_root),
+      None if runner_path.is_some() || prefix_path.is_some() => Some(bottles::get_data_root()?),
+      None => None,
     };
     let runner_path = runner_path.map(|path| match &data_root {
       Some(data_root) if !path.is_absolute() => data_root.join("runners").join(path),
@@ -195,6 +513,98 @@ impl LaunchConfig {
       launch_params: launch_params.unwrap_or(LaunchParams::Unconfigured),
       upscale_mode,
       sync_mode,
+      wait_mode: WaitMode::App,
+      gamescope: None,
     })
   }
 }
+
+impl Default for LaunchConfig {
+  /// No runner nor prefix, starting a shell.
+  fn default() -> Self {
+    LaunchConfig {
+      runner_path: None,
+      prefix_path: None,
+      launch_params: LaunchParams::Unconfigured,
+      upscale_mode: None,
+      sync_mode: None,
+      wait_mode: WaitMode::App,
+      gamescope: None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_device_access_parsing() {
+    let selected = |classes: &[DeviceClass], paths: &[&str]| DeviceAccess::Selected {
+      classes: classes.to_vec(),
+      paths: paths.iter().map(|path| path.to_string()).collect(),
+    };
+    let test_cases = vec![
+      ("all", Ok(DeviceAccess::All), "all"),
+      ("m", Ok(DeviceAccess::minimal()), "minimal"),
+      ("gpu,input", Ok(DeviceAccess::minimal()), "minimal"),
+      (
+        "minimal,uinput",
+        Ok(selected(&[DeviceClass::Input, DeviceClass::Uinput, DeviceClass::Gpu], &[])),
+        "input,uinput,gpu",
+      ),
+      ("none", Ok(selected(&[], &[])), "none"),
+      (
+        "gamepad,hidraw,sound,camera,gpu",
+        Ok(selected(
+          &[
+            DeviceClass::Input,
+            DeviceClass::Gpu,
+            DeviceClass::Hidraw,
+            DeviceClass::Sound,
+            DeviceClass::Video,
+          ],
+          &[],
+        )),
+        "input,gpu,hidraw,sound,video",
+      ),
+      (
+        "minimal, ntsync, /dev/ttyUSB0",
+        Ok(selected(
+          &[DeviceClass::Input, DeviceClass::Gpu, DeviceClass::Ntsync],
+          &["/dev/ttyUSB0"],
+        )),
+        "input,gpu,ntsync,/dev/ttyUSB0",
+      ),
+      (
+        "/etc/shadow",
+        Err("Invalid device path: /etc/shadow, it must be under /dev".to_string()),
+        "",
+      ),
+      (
+        "input,/dev/../root",
+        Err("Invalid device path: /dev/../root, it must be under /dev".to_string()),
+        "",
+      ),
+      ("/dev/", Err("Invalid device path: /dev/, it must be under /dev".to_string()), ""),
+      (
+        "/dev/../root,/dev/null,/dev/../root",
+        Err("Invalid device path: /dev/../root, it must be under /dev".to_string()),
+        "",
+      ),
+      (
+        "/dev/null,/dev/ttyS0,/dev/null",
+        Ok(selected(&[], &["/dev/null", "/dev/ttyS0"])),
+        "/dev/null,/dev/ttyS0",
+      ),
+      ("gpu,printer", Err("Invalid device class: printer".to_string()), ""),
+    ];
+    for (input, expected, display) in test_cases {
+      let device_access = input.parse::<DeviceAccess>();
+      assert_eq!(device_access, expected, "input: {}", input);
+      if let Ok(device_access) = device_access {
+        assert_eq!(device_access.to_string(), display, "input: {}", input);
+      }
+    }
+  }
+}
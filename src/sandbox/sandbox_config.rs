@@ -1,6 +1,7 @@
+use serde::{Deserialize, Deserializer};
 use std::{
-  fs,
-  os::unix::fs::FileTypeExt,
+  fmt,
+  str::FromStr,
   time::{SystemTime, UNIX_EPOCH},
 };
 
@@ -15,24 +16,14 @@ pub fn current_timestamp_hex() -> String {
   format!("{:x}", seconds)
 }
 
-pub fn find_nvidia_devices() -> anyhow::Result<Vec<String>> {
-  let mut nvidia_devices = Vec::new();
-  let entries = fs::read_dir("/dev")?;
-  for entry in entries.flatten() {
-    let path = entry.path();
-    let metadata = path.metadata()?;
-    if metadata.file_type().is_char_device() {
-      let file_name = path
-        .file_name()
-        .unwrap_or_default()
-        .to_str()
-        .unwrap_or_default();
-      if file_name.starts_with("nvidia") {
-        if let Some(path_str) = path.to_str() {
-          nvidia_devices.push(path_str.to_string());
-        }
-      }
-    }
-  }
-  Ok(nvidia_devices)
+/// Deserialize a value from its string representation by reusing its `FromStr` implementation, this
+/// way profiles accept exactly the same syntax as the command line parameters.
+pub fn deserialize_from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+  D: Deserializer<'de>,
+  T: FromStr,
+  T::Err: fmt::Display,
+{
+  let value = String::deserialize(deserializer)?;
+  T::from_str(&value).map_err(serde::de::Error::custom)
 }
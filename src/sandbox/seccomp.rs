@@ -0,0 +1,521 @@
+use super::sandbox_config::deserialize_from_str;
+use anyhow::Context;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Seek, Write};
+use std::str::FromStr;
+
+/// Controls the syscalls denied inside the sandbox, see `get_rules`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SeccompLevel {
+  /// No filter is installed.
+  Off,
+  /// Denies syscalls that games never need and that expand the kernel attack surface e.g.,
+  /// ptrace, bpf or mount, along with ioctls that inject input into the terminal.
+  Default,
+  /// Same as `Default`, also denies io_uring and changing the execution domain (personality).
+  Strict,
+}
+
+impl FromStr for SeccompLevel {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "off" | "o" => Ok(SeccompLevel::Off),
+      "default" | "d" => Ok(SeccompLevel::Default),
+      "strict" | "s" => Ok(SeccompLevel::Strict),
+      _ => Err(format!("Invalid seccomp level: {}", s)),
+    }
+  }
+}
+
+impl fmt::Display for SeccompLevel {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let level_str = match self {
+      SeccompLevel::Off => "off",
+      SeccompLevel::Default => "default",
+      SeccompLevel::Strict => "strict",
+    };
+    write!(f, "{}", level_str)
+  }
+}
+
+impl Serialize for SeccompLevel {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for SeccompLevel {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserialize_from_str(deserializer)
+  }
+}
+
+/// Syscall numbers of the native (x86_64) and compat (i386) ABIs, 32-bit Wine programs use the
+/// latter, so both must be filtered. `None` if the syscall does not exist in that ABI.
+#[derive(Debug, Clone, Copy)]
+struct Syscall {
+  name: &'static str,
+  x86_64: Option<u32>,
+  i386: Option<u32>,
+}
+
+const fn syscall(name: &'static str, x86_64: Option<u32>, i386: Option<u32>) -> Syscall {
+  Syscall { name, x86_64, i386 }
+}
+
+const PTRACE: Syscall = syscall("ptrace", Some(101), Some(26));
+const KEXEC_LOAD: Syscall = syscall("kexec_load", Some(246), Some(283));
+const KEXEC_FILE_LOAD: Syscall = syscall("kexec_file_load", Some(320), None);
+const ADD_KEY: Syscall = syscall("add_key", Some(248), Some(286));
+const REQUEST_KEY: Syscall = syscall("request_key", Some(249), Some(287));
+const KEYCTL: Syscall = syscall("keyctl", Some(250), Some(288));
+const BPF: Syscall = syscall("bpf", Some(321), Some(357));
+const PERF_EVENT_OPEN: Syscall = syscall("perf_event_open", Some(298), Some(336));
+const USERFAULTFD: Syscall = syscall("userfaultfd", Some(323), Some(374));
+const MOUNT: Syscall = syscall("mount", Some(165), Some(21));
+const UMOUNT: Syscall = syscall("umount", None, Some(22));
+const UMOUNT2: Syscall = syscall("umount2", Some(166), Some(52));
+const PIVOT_ROOT: Syscall = syscall("pivot_root", Some(155), Some(217));
+const OPEN_TREE: Syscall = syscall("open_tree", Some(428), Some(428));
+const MOVE_MOUNT: Syscall = syscall("move_mount", Some(429), Some(429));
+const FSOPEN: Syscall = syscall("fsopen", Some(430), Some(430));
+const FSCONFIG: Syscall = syscall("fsconfig", Some(431), Some(431));
+const FSMOUNT: Syscall = syscall("fsmount", Some(432), Some(432));
+const FSPICK: Syscall = syscall("fspick", Some(433), Some(433));
+const MOUNT_SETATTR: Syscall = syscall("mount_setattr", Some(442), Some(442));
+const IOCTL: Syscall = syscall("ioctl", Some(16), Some(54));
+const PERSONALITY: Syscall = syscall("personality", Some(135), Some(136));
+const IO_URING_SETUP: Syscall = syscall("io_uring_setup", Some(425), Some(425));
+const IO_URING_ENTER: Syscall = syscall("io_uring_enter", Some(426), Some(426));
+const IO_URING_REGISTER: Syscall = syscall("io_uring_register", Some(427), Some(427));
+const UNSHARE: Syscall = syscall("unshare", Some(272), Some(310));
+const CLONE: Syscall = syscall("clone", Some(56), Some(120));
+const CLONE3: Syscall = syscall("clone3", Some(435), Some(435));
+
+/// Inject input into the terminal, or read the Linux console.
+const TIOCSTI: u32 = 0x5412;
+const TIOCLINUX: u32 = 0x541c;
+
+const PER_LINUX: u32 = 0x0000;
+const PER_LINUX32: u32 = 0x0008;
+/// Queries the current personality without changing it.
+const PERSONALITY_QUERY: u32 = 0xffff_ffff;
+
+const CLONE_NEWUSER: u32 = 0x1000_0000;
+
+/// Which calls of a syscall are denied, arguments are compared by their lower 32 bits.
+#[derive(Debug, Clone, Copy)]
+enum Condition {
+  Always,
+  /// The argument at the given index is one of the values.
+  ArgIn(u32, &'static [u32]),
+  /// The argument at the given index is none of the values.
+  ArgNotIn(u32, &'static [u32]),
+  /// The argument at the given index has any of the bits of the mask set.
+  ArgHasBits(u32, u32),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Rule {
+  syscall: Syscall,
+  condition: Condition,
+  /// Error returned by denied calls, EPERM unless the caller is expected to fall back on ENOSYS.
+  errno: i32,
+}
+
+const fn deny(syscall: Syscall) -> Rule {
+  deny_if(syscall, Condition::Always)
+}
+
+const fn deny_if(syscall: Syscall, condition: Condition) -> Rule {
+  Rule {
+    syscall,
+    condition,
+    errno: libc::EPERM,
+  }
+}
+
+/// Rules of the level, plus the ones that prevent creating user namespaces if `deny_userns`.
+fn get_rules(level: SeccompLevel, deny_userns: bool) -> Vec<Rule> {
+  let mut rules = match level {
+    SeccompLevel::Off => Vec::new(),
+    SeccompLevel::Default | SeccompLevel::Strict => vec![
+      deny(PTRACE),
+      deny(KEXEC_LOAD),
+      deny(KEXEC_FILE_LOAD),
+      deny(ADD_KEY),
+      deny(REQUEST_KEY),
+      deny(KEYCTL),
+      deny(BPF),
+      deny(PERF_EVENT_OPEN),
+      deny(USERFAULTFD),
+      deny(MOUNT),
+      deny(UMOUNT),
+      deny(UMOUNT2),
+      deny(PIVOT_ROOT),
+      deny(OPEN_TREE),
+      deny(MOVE_MOUNT),
+      deny(FSOPEN),
+      deny(FSCONFIG),
+      deny(FSMOUNT),
+      deny(FSPICK),
+      deny(MOUNT_SETATTR),
+      deny_if(IOCTL, Condition::ArgIn(1, &[TIOCSTI, TIOCLINUX])),
+    ],
+  };
+  if level == SeccompLevel::Strict {
+    rules.extend([
+      deny_if(PERSONALITY, Condition::ArgNotIn(0, &[PER_LINUX, PER_LINUX32, PERSONALITY_QUERY])),
+      deny(IO_URING_SETUP),
+      deny(IO_URING_ENTER),
+      deny(IO_URING_REGISTER),
+    ]);
+  }
+  if deny_userns {
+    // The flags of clone3 are in a struct that cannot be inspected, libc falls back to clone if it
+    // is not implemented.
+    rules.extend([
+      deny_if(UNSHARE, Condition::ArgHasBits(0, CLONE_NEWUSER)),
+      deny_if(CLONE, Condition::ArgHasBits(0, CLONE_NEWUSER)),
+      Rule {
+        errno: libc::ENOSYS,
+        ..deny(CLONE3)
+      },
+    ]);
+  }
+  rules
+}
+
+/// Values of `AUDIT_ARCH_*`, reported in `seccomp_data.arch`.
+const AUDIT_ARCH_X86_64: u32 = 0xc000_003e;
+const AUDIT_ARCH_I386: u32 = 0x4000_0003;
+
+/// Syscalls of the x32 ABI share the x86_64 arch, and are told apart by this bit.
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// Offsets of the `seccomp_data` fields.
+const NR_OFFSET: u32 = 0;
+const ARCH_OFFSET: u32 = 4;
+const ARGS_OFFSET: u32 = 16;
+
+const LD_ABS: u16 = (libc::BPF_LD | libc::BPF_W | libc::BPF_ABS) as u16;
+const JEQ: u16 = (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16;
+const JGE: u16 = (libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K) as u16;
+const JSET: u16 = (libc::BPF_JMP | libc::BPF_JSET | libc::BPF_K) as u16;
+const RET: u16 = (libc::BPF_RET | libc::BPF_K) as u16;
+
+const RET_ALLOW: u32 = libc::SECCOMP_RET_ALLOW;
+const RET_DENY: u32 = ret_errno(libc::EPERM);
+
+const fn ret_errno(errno: i32) -> u32 {
+  libc::SECCOMP_RET_ERRNO | errno as u32
+}
+
+/// Classic BPF instruction, same layout as `struct sock_filter`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Instruction {
+  pub code: u16,
+  pub jt: u8,
+  pub jf: u8,
+  pub k: u32,
+}
+
+fn stmt(code: u16, k: u32) -> Instruction {
+  Instruction {
+    code,
+    jt: 0,
+    jf: 0,
+    k,
+  }
+}
+
+fn jump(code: u16, k: u32, jt: usize, jf: usize) -> anyhow::Result<Instruction> {
+  Ok(Instruction {
+    code,
+    jt: u8::try_from(jt).context("Seccomp jump out of range")?,
+    jf: u8::try_from(jf).context("Seccomp jump out of range")?,
+    k,
+  })
+}
+
+/// Lower 32 bits of a syscall argument (little endian).
+fn arg_offset(index: u32) -> u32 {
+  ARGS_OFFSET + index * 8
+}
+
+/// Instructions that deny the syscall `nr` when the condition of the rule matches, they expect the
+/// syscall number in the accumulator and leave it there for the next rule.
+fn compile_rule(nr: u32, rule: &Rule) -> anyhow::Result<Vec<Instruction>> {
+  let deny = stmt(RET, ret_errno(rule.errno));
+  let mut body = Vec::new();
+  match rule.condition {
+    Condition::Always => body.push(deny),
+    Condition::ArgIn(index, values) | Condition::ArgNotIn(index, values) => {
+      let deny_on_match = matches!(rule.condition, Condition::ArgIn(..));
+      let count = values.len();
+      body.push(stmt(LD_ABS, arg_offset(index)));
+      for (i, value) in values.iter().enumerate() {
+        // On match, jump to the deny return (after the last comparison) or past it.
+        let (jt, jf) = match (deny_on_match, i + 1 == count) {
+          (true, true) => (0, 1),
+          (true, false) => (count - 1 - i, 0),
+          (false, _) => (count - i, 0),
+        };
+        body.push(jump(JEQ, *value, jt, jf)?);
+      }
+      body.push(deny);
+      body.push(stmt(LD_ABS, NR_OFFSET));
+    }
+    Condition::ArgHasBits(index, mask) => {
+      body.push(stmt(LD_ABS, arg_offset(index)));
+      body.push(jump(JSET, mask, 0, 1)?);
+      body.push(deny);
+      body.push(stmt(LD_ABS, NR_OFFSET));
+    }
+  }
+  let mut instructions = vec![jump(JEQ, nr, 0, body.len())?];
+  instructions.extend(body);
+  Ok(instructions)
+}
+
+/// Compile the filter for the given level, `None` if no filter is needed. Denied syscalls fail
+/// with an error instead of killing the process, so programs can fall back or report the error.
+/// With `deny_userns`, the filter also prevents creating user namespaces, for bwrap versions
+/// without `--disable-userns`.
+pub fn compile(level: SeccompLevel, deny_userns: bool) -> anyhow::Result<Option<Vec<Instruction>>> {
+  let rules = get_rules(level, deny_userns);
+  if rules.is_empty() {
+    return Ok(None);
+  }
+  if !cfg!(target_arch = "x86_64") {
+    anyhow::bail!("Seccomp filters are only supported on x86_64, see --seccomp and --allow-userns");
+  }
+  let mut program = vec![stmt(LD_ABS, ARCH_OFFSET)];
+  for (audit_arch, is_native) in [(AUDIT_ARCH_X86_64, true), (AUDIT_ARCH_I386, false)] {
+    let mut section = vec![stmt(LD_ABS, NR_OFFSET)];
+    if is_native {
+      section.push(jump(JGE, X32_SYSCALL_BIT, 0, 1)?);
+      section.push(stmt(RET, RET_DENY));
+    }
+    for rule in &rules {
+      let nr = match is_native {
+        true => rule.syscall.x86_64,
+        false => rule.syscall.i386,
+      };
+      if let Some(nr) = nr {
+        section.extend(compile_rule(nr, rule)?);
+      }
+    }
+    section.push(stmt(RET, RET_ALLOW));
+    program.push(jump(JEQ, audit_arch, 0, section.len())?);
+    program.extend(section);
+  }
+  // Any other ABI is unexpected on x86_64.
+  program.push(stmt(RET, RET_DENY));
+  Ok(Some(program))
+}
+
+/// Serialize the filter as an array of `struct sock_filter`, the format read by `bwrap --seccomp`.
+pub fn to_bytes(program: &[Instruction]) -> Vec<u8> {
+  let mut bytes = Vec::with_capacity(program.len() * 8);
+  for instruction in program {
+    bytes.extend(instruction.code.to_ne_bytes());
+    bytes.push(instruction.jt);
+    bytes.push(instruction.jf);
+    bytes.extend(instruction.k.to_ne_bytes());
+  }
+  bytes
+}
+
+/// Load a filter serialized by `to_bytes` into the current process, setting `no_new_privs` first
+/// as required for unprivileged processes. The filter is inherited by children and kept across
+/// `execve`. It only makes syscalls, so it can be called between `fork` and `exec`.
+pub fn load_filter(bytes: &[u8]) -> io::Result<()> {
+  let filter = libc::sock_fprog {
+    len: (bytes.len() / 8) as u16,
+    filter: bytes.as_ptr() as *mut libc::sock_filter,
+  };
+  // SAFETY: `filter` points to `len` instructions, which the kernel copies before returning.
+  unsafe {
+    if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+      || libc::syscall(libc::SYS_seccomp, libc::SECCOMP_SET_MODE_FILTER, 0, &filter) != 0
+    {
+      return Err(io::Error::last_os_error());
+    }
+  }
+  Ok(())
+}
+
+/// Names of the syscalls denied by a filter (even if only some of their calls are), for display.
+pub fn denied_syscalls(level: SeccompLevel, deny_userns: bool) -> Vec<&'static str> {
+  get_rules(level, deny_userns)
+    .iter()
+    .map(|rule| rule.syscall.name)
+    .collect()
+}
+
+/// Write the compiled filter to an anonymous file, ready to be read by bwrap from the start.
+pub fn write_filter(program: &[Instruction]) -> anyhow::Result<File> {
+  let mut file = tempfile::tempfile().context("Could not create the seccomp filter file")?;
+  file.write_all(&to_bytes(program))?;
+  file.rewind()?;
+  Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Run the filter the same way the kernel does, returns the action for the given syscall.
+  fn evaluate(program: &[Instruction], arch: u32, nr: u32, args: [u32; 6]) -> u32 {
+    let load = |offset: u32| match offset {
+      NR_OFFSET => nr,
+      ARCH_OFFSET => arch,
+      offset => args[((offset - ARGS_OFFSET) / 8) as usize],
+    };
+    let (mut pc, mut accumulator) = (0, 0);
+    loop {
+      let instruction = program[pc];
+      let condition = match instruction.code {
+        LD_ABS => {
+          accumulator = load(instruction.k);
+          pc += 1;
+          continue;
+        }
+        RET => return instruction.k,
+        JEQ => accumulator == instruction.k,
+        JGE => accumulator >= instruction.k,
+        JSET => accumulator & instruction.k != 0,
+        code => panic!("Unexpected instruction: {:#x}", code),
+      };
+      let offset = if condition {
+        instruction.jt
+      } else {
+        instruction.jf
+      };
+      pc += 1 + offset as usize;
+    }
+  }
+
+  #[test]
+  fn test_seccomp_level_parsing() {
+    for level in [
+      SeccompLevel::Off,
+      SeccompLevel::Default,
+      SeccompLevel::Strict,
+    ] {
+      assert_eq!(SeccompLevel::from_str(&level.to_string()), Ok(level));
+    }
+    assert_eq!(SeccompLevel::from_str("S"), Ok(SeccompLevel::Strict));
+    assert!(SeccompLevel::from_str("paranoid").is_err());
+  }
+
+  #[test]
+  fn test_compile() {
+    assert_eq!(compile(SeccompLevel::Off, false).unwrap(), None);
+    let default = compile(SeccompLevel::Default, false).unwrap().unwrap();
+    let strict = compile(SeccompLevel::Strict, false).unwrap().unwrap();
+    assert_eq!(to_bytes(&default).len(), default.len() * 8);
+    let x86_64 = |program: &[Instruction], nr: u32, args: [u32; 6]| {
+      evaluate(program, AUDIT_ARCH_X86_64, nr, args)
+    };
+    let i386 = |program: &[Instruction], nr: u32, args: [u32; 6]| {
+      evaluate(program, AUDIT_ARCH_I386, nr, args)
+    };
+    let no_args = [0; 6];
+    for program in [&default, &strict] {
+      assert_eq!(x86_64(program, 101, no_args), RET_DENY); // ptrace
+      assert_eq!(x86_64(program, 39, no_args), RET_ALLOW); // getpid
+      assert_eq!(x86_64(program, 16, [0, TIOCSTI, 0, 0, 0, 0]), RET_DENY);
+      assert_eq!(x86_64(program, 16, [0, TIOCLINUX, 0, 0, 0, 0]), RET_DENY);
+      assert_eq!(x86_64(program, 16, [0, 0x5401, 0, 0, 0, 0]), RET_ALLOW); // TCGETS
+      assert_eq!(x86_64(program, X32_SYSCALL_BIT | 39, no_args), RET_DENY);
+      // The compat table uses different numbers e.g., 101 is ioperm and 26 is ptrace.
+      assert_eq!(i386(program, 26, no_args), RET_DENY);
+      assert_eq!(i386(program, 101, no_args), RET_ALLOW);
+      assert_eq!(i386(program, 22, no_args), RET_DENY); // umount
+      assert_eq!(i386(program, 54, [0, TIOCSTI, 0, 0, 0, 0]), RET_DENY);
+      assert_eq!(evaluate(program, 0xc000_00b7, 101, no_args), RET_DENY); // aarch64
+    }
+    // Strict also denies io_uring, and personalities other than the usual ones.
+    assert_eq!(x86_64(&default, 425, no_args), RET_ALLOW);
+    assert_eq!(x86_64(&strict, 425, no_args), RET_DENY);
+    assert_eq!(i386(&strict, 427, no_args), RET_DENY);
+    let addr_no_randomize = [0x0040000, 0, 0, 0, 0, 0];
+    assert_eq!(x86_64(&default, 135, addr_no_randomize), RET_ALLOW);
+    assert_eq!(x86_64(&strict, 135, addr_no_randomize), RET_DENY);
+    assert_eq!(i386(&strict, 136, addr_no_randomize), RET_DENY);
+    for personality in [PER_LINUX, PER_LINUX32, PERSONALITY_QUERY] {
+      assert_eq!(x86_64(&strict, 135, [personality, 0, 0, 0, 0, 0]), RET_ALLOW);
+    }
+  }
+
+  #[test]
+  fn test_compile_deny_userns() {
+    // Needs a filter even without a level.
+    let program = compile(SeccompLevel::Off, true).unwrap().unwrap();
+    let clone_newuser = [CLONE_NEWUSER | libc::SIGCHLD as u32, 0, 0, 0, 0, 0];
+    let clone_thread = [
+      libc::CLONE_VM as u32 | libc::CLONE_THREAD as u32,
+      0,
+      0,
+      0,
+      0,
+      0,
+    ];
+    for (arch, unshare, clone) in [(AUDIT_ARCH_X86_64, 272, 56), (AUDIT_ARCH_I386, 310, 120)] {
+      assert_eq!(evaluate(&program, arch, unshare, clone_newuser), RET_DENY);
+      assert_eq!(evaluate(&program, arch, clone, clone_newuser), RET_DENY);
+      assert_eq!(evaluate(&program, arch, clone, clone_thread), RET_ALLOW);
+      assert_eq!(evaluate(&program, arch, 435, [0; 6]), ret_errno(libc::ENOSYS));
+    }
+    // Other rules are not affected.
+    assert_eq!(evaluate(&program, AUDIT_ARCH_X86_64, 101, [0; 6]), RET_ALLOW);
+    let program = compile(SeccompLevel::Default, true).unwrap().unwrap();
+    assert_eq!(evaluate(&program, AUDIT_ARCH_X86_64, 101, [0; 6]), RET_DENY);
+    assert_eq!(evaluate(&program, AUDIT_ARCH_X86_64, 272, clone_newuser), RET_DENY);
+    assert_eq!(denied_syscalls(SeccompLevel::Off, true), vec!["unshare", "clone", "clone3"]);
+  }
+
+  #[test]
+  fn test_native_syscall_numbers() {
+    let native = [
+      (PTRACE, libc::SYS_ptrace),
+      (KEXEC_LOAD, libc::SYS_kexec_load),
+      (KEXEC_FILE_LOAD, libc::SYS_kexec_file_load),
+      (ADD_KEY, libc::SYS_add_key),
+      (REQUEST_KEY, libc::SYS_request_key),
+      (KEYCTL, libc::SYS_keyctl),
+      (BPF, libc::SYS_bpf),
+      (PERF_EVENT_OPEN, libc::SYS_perf_event_open),
+      (USERFAULTFD, libc::SYS_userfaultfd),
+      (MOUNT, libc::SYS_mount),
+      (UMOUNT2, libc::SYS_umount2),
+      (PIVOT_ROOT, libc::SYS_pivot_root),
+      (OPEN_TREE, libc::SYS_open_tree),
+      (MOVE_MOUNT, libc::SYS_move_mount),
+      (FSOPEN, libc::SYS_fsopen),
+      (FSCONFIG, libc::SYS_fsconfig),
+      (FSMOUNT, libc::SYS_fsmount),
+      (FSPICK, libc::SYS_fspick),
+      (MOUNT_SETATTR, libc::SYS_mount_setattr),
+      (IOCTL, libc::SYS_ioctl),
+      (PERSONALITY, libc::SYS_personality),
+      (IO_URING_SETUP, libc::SYS_io_uring_setup),
+      (IO_URING_ENTER, libc::SYS_io_uring_enter),
+      (IO_URING_REGISTER, libc::SYS_io_uring_register),
+      (UNSHARE, libc::SYS_unshare),
+      (CLONE, libc::SYS_clone),
+      (CLONE3, libc::SYS_clone3),
+    ];
+    for (syscall, nr) in native {
+      assert_eq!(syscall.x86_64, Some(nr as u32), "syscall: {}", syscall.name);
+    }
+    assert_eq!(TIOCSTI, libc::TIOCSTI as u32);
+    assert_eq!(TIOCLINUX, libc::TIOCLINUX as u32);
+    assert_eq!(CLONE_NEWUSER, libc::CLONE_NEWUSER as u32);
+  }
+}
@@ -1,4 +1,6 @@
+use super::sandbox_config::deserialize_from_str;
 use rand::Rng;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
 use std::str::FromStr;
 
@@ -68,10 +70,6 @@ impl UserMapping {
       UserMapping::Custom(uid, gid) => (*uid, *gid),
     }
   }
-  pub fn get_uid_gid_string(&self) -> (String, String) {
-    let (uid, gid) = self.get_uid_gid();
-    (uid.to_string(), gid.to_string())
-  }
 }
 
 impl FromStr for UserMapping {
@@ -90,6 +88,27 @@ impl FromStr for UserMapping {
   }
 }
 
+impl fmt::Display for UserMapping {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      UserMapping::Random => write!(f, "random"),
+      UserMapping::Custom(uid, gid) => write!(f, "{}:{}", uid, gid),
+    }
+  }
+}
+
+impl Serialize for UserMapping {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for UserMapping {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserialize_from_str(deserializer)
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -0,0 +1,186 @@
+use super::bwrap_args::BwrapOp;
+use super::sandbox_config::deserialize_from_str;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// Runs the command, then waits for wineserver to exit i.e., until every Wine process in the prefix
+/// is gone, including the ones detached from the command.
+const WAIT_WINESERVER_SCRIPT: &str = r#""$@"
+status=$?
+if command -v wineserver > /dev/null; then
+  wineserver -w
+fi
+exit "$status""#;
+
+/// Runs the command, then waits up to `$RCAGE_WAIT_TIMEOUT` seconds for a process whose executable
+/// is named `$RCAGE_WAIT_PROCESS` to appear and waits for it to exit. The name is passed through the
+/// environment so this script never matches itself.
+const WAIT_PROCESS_SCRIPT: &str = r#""$@"
+status=$?
+is_running() {
+  for cmdline in /proc/[0-9]*/cmdline; do
+    case "$(tr '\000' '\n' < "$cmdline" 2> /dev/null | head -n 1)" in
+      "$RCAGE_WAIT_PROCESS" | */"$RCAGE_WAIT_PROCESS" | *\\"$RCAGE_WAIT_PROCESS") return 0 ;;
+    esac
+  done
+  return 1
+}
+elapsed=0
+until is_running; do
+  if [ "$elapsed" -ge "$RCAGE_WAIT_TIMEOUT" ]; then
+    exit "$status"
+  fi
+  sleep 1
+  elapsed=$((elapsed + 1))
+done
+while is_running; do
+  sleep 1
+done
+exit "$status""#;
+
+/// Controls when the sandbox is torn down. Since bwrap is started with `--die-with-parent` and its
+/// own PID namespace, every process left in the sandbox is killed as soon as the launched command
+/// exits, which breaks games that start a detached process (e.g. a launcher that spawns the actual
+/// game and exits right away).
+#[derive(Debug, Clone, PartialEq)]
+pub enum WaitMode {
+  /// Stop the sandbox when the launched command exits.
+  App,
+  /// Keep the sandbox alive until wineserver exits.
+  Wineserver,
+  /// Keep the sandbox alive until the given process exits, waiting up to `timeout` seconds (after
+  /// the launched command exits) for it to appear.
+  Process { name: String, timeout: u32 },
+}
+
+impl WaitMode {
+  /// Wrap `command` so it keeps the sandbox alive as configured, returns the environment variables
+  /// needed by the wrapper along with the new command.
+  pub fn wrap_command(&self, command: Vec<String>) -> (Vec<BwrapOp>, Vec<String>) {
+    let (ops, script) = match self {
+      WaitMode::App => return (vec![], command),
+      WaitMode::Wineserver => (vec![], WAIT_WINESERVER_SCRIPT),
+      WaitMode::Process { name, timeout } => {
+        let ops = vec![
+          BwrapOp::SetEnv {
+            key: "RCAGE_WAIT_PROCESS".into(),
+            value: name.clone(),
+          },
+          BwrapOp::SetEnv {
+            key: "RCAGE_WAIT_TIMEOUT".into(),
+            value: timeout.to_string(),
+          },
+        ];
+        (ops, WAIT_PROCESS_SCRIPT)
+      }
+    };
+    let mut wrapped: Vec<String> = vec!["sh".into(), "-c".into(), script.into(), "sh".into()];
+    wrapped.extend(command);
+    (ops, wrapped)
+  }
+}
+
+impl FromStr for WaitMode {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let lower = s.to_lowercase();
+    match lower.as_str() {
+      "app" | "a" => return Ok(WaitMode::App),
+      "wineserver" | "w" => return Ok(WaitMode::Wineserver),
+      _ => (),
+    }
+    if lower.starts_with("process:") || lower.starts_with("p:") {
+      let (_, params) = s.split_once(':').unwrap_or_default();
+      let process = params
+        .rsplit_once(':')
+        .filter(|(name, _)| !name.is_empty())
+        .and_then(|(name, timeout)| Some((name, timeout.parse::<u32>().ok()?)));
+      if let Some((name, timeout)) = process {
+        return Ok(WaitMode::Process {
+          name: name.to_string(),
+          timeout,
+        });
+      }
+      return Err(format!("Invalid wait mode: {}, expected process:NAME:TIMEOUT", s));
+    }
+    Err(format!("Invalid wait mode: {}", s))
+  }
+}
+
+impl fmt::Display for WaitMode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      WaitMode::App => write!(f, "app"),
+      WaitMode::Wineserver => write!(f, "wineserver"),
+      WaitMode::Process { name, timeout } => write!(f, "process:{}:{}", name, timeout),
+    }
+  }
+}
+
+impl Serialize for WaitMode {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for WaitMode {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserialize_from_str(deserializer)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_wait_mode_parsing() {
+    let test_cases = vec![
+      ("app", Ok(WaitMode::App)),
+      ("Wineserver", Ok(WaitMode::Wineserver)),
+      (
+        "process:HC2.exe:30",
+        Ok(WaitMode::Process {
+          name: "HC2.exe".into(),
+          timeout: 30,
+        }),
+      ),
+      (
+        "p:Some Game.exe:5",
+        Ok(WaitMode::Process {
+          name: "Some Game.exe".into(),
+          timeout: 5,
+        }),
+      ),
+      ("process:HC2.exe", Err(())),
+      ("process::30", Err(())),
+      ("process:HC2.exe:-1", Err(())),
+      ("forever", Err(())),
+    ];
+    for (input, expected) in test_cases {
+      let result = WaitMode::from_str(input).map_err(|_| ());
+      assert_eq!(result, expected, "input: {}", input);
+      if let Ok(mode) = result {
+        assert_eq!(WaitMode::from_str(&mode.to_string()), Ok(mode));
+      }
+    }
+  }
+
+  #[test]
+  fn test_wrap_command() {
+    let command = vec!["wine".to_string(), "/app/game.exe".to_string()];
+    let (ops, wrapped) = WaitMode::App.wrap_command(command.clone());
+    assert!(ops.is_empty());
+    assert_eq!(wrapped, command);
+    let mode = WaitMode::Process {
+      name: "game.exe".into(),
+      timeout: 10,
+    };
+    let (ops, wrapped) = mode.wrap_command(command);
+    assert_eq!(ops.len(), 2);
+    assert_eq!(wrapped[..2], ["sh", "-c"]);
+    assert_eq!(wrapped[3..], ["sh", "wine", "/app/game.exe"]);
+    assert!(!wrapped[2].contains("game.exe"));
+  }
+}
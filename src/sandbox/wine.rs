@@ -1,8 +1,13 @@
+use super::gamescope::{GamescopeFilter, MAX_GAMESCOPE_SHARPNESS, MIN_GAMESCOPE_SHARPNESS};
+use super::sandbox_config::deserialize_from_str;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
 use std::str::FromStr;
 
 const MIN_FSR_STRENGTH: u8 = 0;
 const MAX_FSR_STRENGTH: u8 = 5;
+/// Device of the ntsync kernel driver, used by `SyncMode::Ntsync`.
+pub const NTSYNC_DEVICE: &str = "/dev/ntsync";
 
 #[derive(Debug)]
 pub enum UpscaleModeError {
@@ -10,6 +15,9 @@ pub enum UpscaleModeError {
   InvalidFsrMode(String),
   InvalidFsrStrength(String),
   OutOfRangeFsrStrength(u8),
+  InvalidGamescopeFilter(String),
+  InvalidGamescopeSharpness(String),
+  OutOfRangeGamescopeSharpness(u8),
 }
 
 impl fmt::Display for UpscaleModeError {
@@ -23,6 +31,15 @@ impl fmt::Display for UpscaleModeError {
         "FSR strength must be between {} and {}, but got {}",
         MIN_FSR_STRENGTH, MAX_FSR_STRENGTH, strength
       ),
+      UpscaleModeError::InvalidGamescopeFilter(s) => write!(f, "Invalid gamescope filter: {}", s),
+      UpscaleModeError::InvalidGamescopeSharpness(s) => {
+        write!(f, "Invalid gamescope sharpness: {}", s)
+      }
+      UpscaleModeError::OutOfRangeGamescopeSharpness(sharpness) => write!(
+        f,
+        "Gamescope sharpness must be between {} and {}, but got {}",
+        MIN_GAMESCOPE_SHARPNESS, MAX_GAMESCOPE_SHARPNESS, sharpness
+      ),
     }
   }
 }
@@ -93,6 +110,12 @@ pub enum UpscaleMode {
   },
   /// NVIDIA Deep Learning Super Sampling (DLSS) upscaling.
   Dlss,
+  /// Upscaling done by gamescope, which is started even if `--gamescope` was not passed. The game
+  /// must render at a lower resolution than gamescope's output e.g., `--gamescope="-w 1280 -h 720"`.
+  Gamescope {
+    filter: GamescopeFilter,
+    sharpness: u8, // Sharpness value from 0 (sharpest) to 20.
+  },
 }
 
 impl FromStr for UpscaleMode {
@@ -118,12 +141,52 @@ impl FromStr for UpscaleMode {
             return Ok(UpscaleMode::Fsr { mode, strength });
           }
         }
+        // Gamescope upscaling looks like "gamescope:filter:sharpness".
+        if let Some((filter, sharpness)) =
+          s.strip_prefix("gamescope:").and_then(|v| v.split_once(':'))
+        {
+          let filter = GamescopeFilter::from_str(filter)
+            .map_err(|_| UpscaleModeError::InvalidGamescopeFilter(filter.to_string()))?;
+          let sharpness = sharpness
+            .parse::<u8>()
+            .map_err(|_| UpscaleModeError::InvalidGamescopeSharpness(sharpness.to_string()))?;
+          if sharpness > MAX_GAMESCOPE_SHARPNESS {
+            return Err(UpscaleModeError::OutOfRangeGamescopeSharpness(sharpness));
+          }
+          return Ok(UpscaleMode::Gamescope { filter, sharpness });
+        }
         return Err(UpscaleModeError::InvalidUpscaleMode(s.to_string()));
       }
     }
   }
 }
 
+impl fmt::Display for UpscaleMode {
+  /// Converts the UpscaleMode to the same syntax accepted by `from_str`.
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      UpscaleMode::None => write!(f, "none"),
+      UpscaleMode::Fsr { mode, strength } => write!(f, "fsr:{}:{}", mode, strength),
+      UpscaleMode::Dlss => write!(f, "dlss"),
+      UpscaleMode::Gamescope { filter, sharpness } => {
+        write!(f, "gamescope:{}:{}", filter, sharpness)
+      }
+    }
+  }
+}
+
+impl Serialize for UpscaleMode {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for UpscaleMode {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserialize_from_str(deserializer)
+  }
+}
+
 /// Configures how synchronization operations are done, it may affect CPU
 /// performance and game compatibility. Do **NOT** confuse with VSync.
 /// TL;DR: Fsync and Ntsync yield similar performance (according to Valve),
@@ -131,26 +194,79 @@ impl FromStr for UpscaleMode {
 /// does not need a newer kernel and is available in Proton and other variants.
 /// https://github.com/lutris/lutris/wiki/How-to:-Esync/be48f27a4112271d0eb7c42eb14b57cea022f8c6.
 /// https://github.com/Frogging-Family/wine-tkg-git/issues/936.
-/// TODO: as of 2024-08 the latest and greatest option seems to be NTsync. It seems to require an
-/// additional /dev/... mount, see https://www.phoronix.com/news/Linux-6.10-Merging-NTSYNC.
+/// https://www.phoronix.com/news/Linux-6.14-Char-Misc-NTSYNC.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum SyncMode {
   /// Will use the runner's default.
   None,
+  /// Picks the best mode supported by both the host and the runner: Ntsync, Fsync, then Esync.
+  Auto,
+  /// Kernel driver exposed as /dev/ntsync, requires Linux 6.14+.
+  Ntsync,
   /// Preferred over Esync, this is the default for the soda runner (as of 2024-08).
   Fsync,
   /// Old mode, used before Fsync was common.
   Esync,
 }
 
+impl SyncMode {
+  /// Oldest kernel, as `(major, minor)`, providing what the mode relies on: the ntsync driver, or
+  /// the futex_waitv syscall used by upstream Fsync.
+  pub fn min_kernel(self) -> Option<(u32, u32)> {
+    match self {
+      SyncMode::Ntsync => Some((6, 14)),
+      SyncMode::Fsync => Some((5, 16)),
+      SyncMode::None | SyncMode::Auto | SyncMode::Esync => None,
+    }
+  }
+
+  /// String found in the wineserver of runners supporting the mode, either the device it opens or
+  /// the variable that enables it.
+  pub fn runner_marker(self) -> Option<&'static str> {
+    match self {
+      SyncMode::Ntsync => Some(NTSYNC_DEVICE),
+      SyncMode::Fsync => Some("WINEFSYNC"),
+      SyncMode::Esync => Some("WINEESYNC"),
+      SyncMode::None | SyncMode::Auto => None,
+    }
+  }
+}
+
 impl FromStr for SyncMode {
   type Err = String;
   fn from_str(s: &str) -> Result<Self, Self::Err> {
     match s.to_lowercase().as_str() {
       "none" => Ok(SyncMode::None),
+      "auto" => Ok(SyncMode::Auto),
+      "ntsync" => Ok(SyncMode::Ntsync),
       "fsync" => Ok(SyncMode::Fsync),
       "esync" => Ok(SyncMode::Esync),
       _ => Err(format!("Invalid sync mode: {}", s)),
     }
   }
 }
+
+impl fmt::Display for SyncMode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let mode_str = match self {
+      SyncMode::None => "none",
+      SyncMode::Auto => "auto",
+      SyncMode::Ntsync => "ntsync",
+      SyncMode::Fsync => "fsync",
+      SyncMode::Esync => "esync",
+    };
+    write!(f, "{}", mode_str)
+  }
+}
+
+impl Serialize for SyncMode {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for SyncMode {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserialize_from_str(deserializer)
+  }
+}
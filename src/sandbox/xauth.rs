@@ -0,0 +1,154 @@
+use super::sandbox_config::deserialize_from_str;
+use anyhow::Context;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::fs;
+use std::process::Command;
+use std::str::FromStr;
+use tempfile::NamedTempFile;
+
+/// Xauthority entries with this family match any address, see `XauGetBestAuthByAddr`.
+const FAMILY_WILD: u16 = 0xffff;
+
+const COOKIE_NAME: &[u8] = b"MIT-MAGIC-COOKIE-1";
+
+/// Seconds an untrusted cookie stays valid without any client connected with it, it is revoked by
+/// the X server afterwards. A launcher that idles for longer before connecting again loses the
+/// display, but without a timeout the cookie would stay valid until the X server exits.
+pub const UNTRUSTED_COOKIE_TIMEOUT: u32 = 300;
+
+/// Controls the X11 cookie given to the sandbox when using the X server of the session.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum X11Cookie {
+  /// A temporary cookie generated through the X SECURITY extension, clients using it are untrusted
+  /// i.e., they cannot access the windows and input of trusted clients, nor use extensions such as
+  /// GLX or DRI3, which breaks OpenGL and Vulkan rendering through X11 (like `ssh -X`). Falls back
+  /// to the session cookie when the X server does not support it.
+  Untrusted,
+  /// The cookie of the session (`$XAUTHORITY`), which grants full access to the X server.
+  Session,
+}
+
+impl FromStr for X11Cookie {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "untrusted" | "u" => Ok(X11Cookie::Untrusted),
+      "session" | "s" => Ok(X11Cookie::Session),
+      _ => Err(format!("Invalid X11 cookie: {}", s)),
+    }
+  }
+}
+
+impl fmt::Display for X11Cookie {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let cookie_str = match self {
+      X11Cookie::Untrusted => "untrusted",
+      X11Cookie::Session => "session",
+    };
+    write!(f, "{}", cookie_str)
+  }
+}
+
+impl Serialize for X11Cookie {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for X11Cookie {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserialize_from_str(deserializer)
+  }
+}
+
+/// Encode a single Xauthority entry, every field is prefixed by its length (big endian).
+pub fn encode_xauthority(cookie: &[u8]) -> Vec<u8> {
+  let mut entry = FAMILY_WILD.to_be_bytes().to_vec();
+  // An empty address and display number match any address and display.
+  let fields: [&[u8]; 4] = [b"", b"", COOKIE_NAME, cookie];
+  for field in fields {
+    entry.extend((field.len() as u16).to_be_bytes());
+    entry.extend(field);
+  }
+  entry
+}
+
+/// Split the first `len` bytes off `rest`.
+fn take<'a>(rest: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+  let (head, tail) = rest.split_at_checked(len)?;
+  *rest = tail;
+  Some(head)
+}
+
+/// Get the first MIT-MAGIC-COOKIE-1 cookie of an Xauthority file.
+fn parse_cookie(content: &[u8]) -> Option<Vec<u8>> {
+  let mut rest = content;
+  loop {
+    take(&mut rest, 2)?; // Family
+    let mut fields = Vec::new();
+    for _ in 0..4 {
+      let len = take(&mut rest, 2)?;
+      fields.push(take(&mut rest, u16::from_be_bytes([len[0], len[1]]) as usize)?);
+    }
+    if fields[2] == COOKIE_NAME {
+      return Some(fields[3].to_vec());
+    }
+  }
+}
+
+/// Generate an untrusted cookie for the X server at `display_address` with `xauth`, which needs to
+/// be authorized with the session cookie. The entry written by `xauth` is bound to the hostname,
+/// which is different inside the sandbox, so it is replaced by one that matches any host.
+pub fn generate_untrusted(display_address: &str) -> anyhow::Result<NamedTempFile> {
+  let xauthority = NamedTempFile::new()?;
+  let output = Command::new("xauth")
+    .arg("-q")
+    .arg("-f")
+    .arg(xauthority.path())
+    .args([
+      "generate",
+      display_address,
+      "MIT-MAGIC-COOKIE-1",
+      "untrusted",
+    ])
+    .args(["timeout", &UNTRUSTED_COOKIE_TIMEOUT.to_string()])
+    .output()
+    .map_err(|e| anyhow::anyhow!("Could not run xauth: {}", e))?;
+  if !output.status.success() {
+    anyhow::bail!("xauth failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+  }
+  let content = fs::read(xauthority.path())?;
+  let cookie = parse_cookie(&content).context("xauth did not generate a cookie")?;
+  fs::write(xauthority.path(), encode_xauthority(&cookie))?;
+  Ok(xauthority)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_encode_xauthority() {
+    let entry = encode_xauthority(&[0xab; 16]);
+    let mut expected = vec![0xff, 0xff, 0, 0, 0, 0, 0, 18];
+    expected.extend(b"MIT-MAGIC-COOKIE-1");
+    expected.extend([0, 16]);
+    expected.extend([0xab; 16]);
+    assert_eq!(entry, expected);
+  }
+
+  #[test]
+  fn test_parse_cookie() {
+    // FamilyLocal entry for "host" display 0 with another auth method, then the cookie.
+    let mut content = vec![1, 0, 0, 4];
+    content.extend(b"host");
+    content.extend([0, 1, b'0', 0, 3]);
+    content.extend(b"XDM");
+    content.extend([0, 2, 1, 2]);
+    content.extend(encode_xauthority(&[0xcd; 16]));
+    assert_eq!(parse_cookie(&content), Some(vec![0xcd; 16]));
+    assert_eq!(parse_cookie(&content[..20]), None);
+    assert_eq!(parse_cookie(&[]), None);
+  }
+}
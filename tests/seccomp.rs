@@ -0,0 +1,251 @@
+//! Loads the filters generated for bwrap into a forked child, so the kernel itself checks that
+//! denied syscalls fail inside the sandbox while the rest keep working. When bwrap is installed, the
+//! probes also run inside a real sandbox, with the filter passed through `--seccomp`.
+#![cfg(target_arch = "x86_64")]
+
+use raptor_cage::sandbox::seccomp::{self, SeccompLevel};
+use std::arch::asm;
+use std::os::fd::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{self, Command, Stdio};
+use std::{env, io, ptr};
+
+const ADDR_NO_RANDOMIZE: libc::c_ulong = 0x0040000;
+
+/// Result of a native syscall, `Err(errno)` if it failed.
+fn check(ret: libc::c_long) -> Result<(), i32> {
+  match ret {
+    -1 => Err(std::io::Error::last_os_error().raw_os_error().unwrap_or(0)),
+    _ => Ok(()),
+  }
+}
+
+/// Syscall through the i386 ABI, like 32-bit Wine programs do, returns the raw value i.e.,
+/// `-errno` on failure.
+fn compat_syscall(nr: i32, arg1: i32) -> i32 {
+  let ret: i32;
+  // SAFETY: the probed syscalls only read their integer arguments.
+  unsafe {
+    // rbx is reserved by LLVM, so it is swapped in and out around the call.
+    asm!(
+      "xchg {arg1:r}, rbx",
+      "int 0x80",
+      "xchg {arg1:r}, rbx",
+      arg1 = inout(reg) arg1 as i64 => _,
+      inlateout("eax") nr => ret,
+      in("ecx") 0,
+      in("edx") 0,
+    );
+  }
+  ret
+}
+
+/// Whether the kernel can run i386 programs, which is required to test the compat table.
+fn has_ia32_emulation() -> bool {
+  // getpid
+  compat_syscall(20, 0) > 0
+}
+
+/// Run `probe` in a child process with the filter loaded, returns its exit code.
+fn run_filtered(level: SeccompLevel, deny_userns: bool, probe: fn() -> i32) -> i32 {
+  let program = seccomp::compile(level, deny_userns).unwrap().unwrap();
+  let bytes = seccomp::to_bytes(&program);
+  let filter = libc::sock_fprog {
+    len: program.len() as u16,
+    filter: bytes.as_ptr() as *mut libc::sock_filter,
+  };
+  // SAFETY: the child only makes syscalls before exiting, it does not allocate nor unwind.
+  unsafe {
+    let pid = libc::fork();
+    assert!(pid >= 0, "fork failed");
+    if pid == 0 {
+      if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+        || libc::syscall(libc::SYS_seccomp, libc::SECCOMP_SET_MODE_FILTER, 0, &filter) != 0
+      {
+        libc::_exit(100);
+      }
+      libc::_exit(probe());
+    }
+    let mut status = 0;
+    assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+    assert!(libc::WIFEXITED(status), "the probe was killed: {}", status);
+    libc::WEXITSTATUS(status)
+  }
+}
+
+/// Exit code of a probe, the number of the first failed check.
+fn first_failure(checks: &[bool]) -> i32 {
+  checks
+    .iter()
+    .position(|ok| !ok)
+    .map_or(0, |index| index as i32 + 1)
+}
+
+fn probe_default() -> i32 {
+  let mut byte = 0u8;
+  // SAFETY: the arguments are valid for each syscall, or rejected before being used.
+  unsafe {
+    let ia32 = has_ia32_emulation();
+    first_failure(&[
+      check(libc::syscall(libc::SYS_getpid)).is_ok(),
+      check(libc::ptrace(libc::PTRACE_TRACEME, 0, 0, 0)) == Err(libc::EPERM),
+      check(libc::syscall(libc::SYS_keyctl, 0, 0, 0, 0, 0)) == Err(libc::EPERM),
+      check(libc::syscall(libc::SYS_bpf, 0, 0, 0)) == Err(libc::EPERM),
+      check(libc::syscall(libc::SYS_userfaultfd, 0)) == Err(libc::EPERM),
+      check(libc::syscall(libc::SYS_fsopen, c"tmpfs".as_ptr(), 0)) == Err(libc::EPERM),
+      // Fails with EFAULT if allowed, before checking for privileges.
+      check(libc::mount(ptr::null(), ptr::null(), ptr::null(), 0, ptr::null()) as libc::c_long)
+        == Err(libc::EPERM),
+      check(libc::ioctl(0, libc::TIOCSTI, &mut byte) as libc::c_long) == Err(libc::EPERM),
+      // Other ioctls are allowed, and fail as usual.
+      check(libc::ioctl(-1, libc::TCGETS, &mut byte) as libc::c_long) == Err(libc::EBADF),
+      // The same rules apply to the compat table: ptrace, umount, and getpid.
+      !ia32 || compat_syscall(26, 0) == -libc::EPERM,
+      !ia32 || compat_syscall(22, 0) == -libc::EPERM,
+      !ia32 || compat_syscall(20, 0) > 0,
+      // Default does not restrict personality.
+      check(libc::syscall(libc::SYS_personality, ADDR_NO_RANDOMIZE)).is_ok(),
+    ])
+  }
+}
+
+fn probe_strict() -> i32 {
+  // SAFETY: the arguments are valid for each syscall, or rejected before being used.
+  unsafe {
+    let ia32 = has_ia32_emulation();
+    first_failure(&[
+      check(libc::ptrace(libc::PTRACE_TRACEME, 0, 0, 0)) == Err(libc::EPERM),
+      check(libc::syscall(libc::SYS_io_uring_setup, 1, 0)) == Err(libc::EPERM),
+      check(libc::syscall(libc::SYS_personality, ADDR_NO_RANDOMIZE)) == Err(libc::EPERM),
+      check(libc::syscall(libc::SYS_personality, 0xffff_ffff_u32)).is_ok(),
+      // personality
+      !ia32 || compat_syscall(136, ADDR_NO_RANDOMIZE as i32) == -libc::EPERM,
+    ])
+  }
+}
+
+fn probe_userns() -> i32 {
+  // SAFETY: the arguments are valid for each syscall, or rejected before being used.
+  unsafe {
+    let pid = libc::fork();
+    if pid == 0 {
+      libc::_exit(0);
+    }
+    let mut status = 0;
+    let forked = pid > 0 && libc::waitpid(pid, &mut status, 0) == pid;
+    first_failure(&[
+      check(libc::unshare(libc::CLONE_NEWUSER) as libc::c_long) == Err(libc::EPERM),
+      check(libc::syscall(libc::SYS_clone3, 0, 0)) == Err(libc::ENOSYS),
+      // Other namespaces and processes can still be created.
+      check(libc::unshare(0) as libc::c_long).is_ok(),
+      forked,
+      // The rest of the filter is only loaded with a level.
+      check(libc::ptrace(libc::PTRACE_TRACEME, 0, 0, 0)).is_ok(),
+    ])
+  }
+}
+
+#[test]
+fn test_default_filter() {
+  assert_eq!(run_filtered(SeccompLevel::Default, false, probe_default), 0);
+}
+
+#[test]
+fn test_strict_filter() {
+  assert_eq!(run_filtered(SeccompLevel::Strict, false, probe_strict), 0);
+}
+
+#[test]
+fn test_userns_filter() {
+  assert_eq!(run_filtered(SeccompLevel::Off, true, probe_userns), 0);
+}
+
+/// Set to the name of a probe to run it from `probe_in_bwrap`.
+const PROBE_VAR: &str = "RCAGE_SECCOMP_PROBE";
+
+/// Run `command` in a bwrap sandbox of the whole filesystem, with the filter compiled for `level`
+/// and `deny_userns` if any. Returns the exit code of the command.
+fn run_bwrap(
+  level: SeccompLevel,
+  deny_userns: bool,
+  command: &[&str],
+  probe: Option<&str>,
+) -> io::Result<Option<i32>> {
+  let mut bwrap = Command::new("bwrap");
+  bwrap
+    .args(["--ro-bind", "/", "/", "--dev", "/dev", "--proc", "/proc"])
+    .stdout(Stdio::null())
+    .stderr(Stdio::null());
+  if let Some(probe) = probe {
+    bwrap.env(PROBE_VAR, probe);
+  }
+  // bwrap reads the filter up to its end, so each run needs its own file.
+  let filter = seccomp::compile(level, deny_userns)
+    .unwrap()
+    .map(|program| seccomp::write_filter(&program).unwrap());
+  if let Some(fd) = filter.as_ref().map(|file| file.as_raw_fd()) {
+    bwrap.arg("--seccomp").arg(fd.to_string());
+    // SAFETY: fcntl is async-signal-safe, and the closure does not allocate.
+    unsafe {
+      bwrap.pre_exec(move || match libc::fcntl(fd, libc::F_SETFD, 0) {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+      });
+    }
+  }
+  Ok(bwrap.args(command).status()?.code())
+}
+
+/// Whether bwrap is installed and can create a sandbox on this host.
+fn has_bwrap() -> bool {
+  run_bwrap(SeccompLevel::Off, false, &["true"], None).is_ok_and(|code| code == Some(0))
+}
+
+/// Run a probe inside bwrap with the filter for `level`, by starting this test binary again with
+/// only `probe_in_bwrap` selected. Returns its exit code.
+fn run_probe_in_bwrap(level: SeccompLevel, probe: &str) -> Option<i32> {
+  let test_binary = env::current_exe().unwrap();
+  let command = [test_binary.to_str().unwrap(), "--exact", "probe_in_bwrap"];
+  run_bwrap(level, false, &command, Some(probe)).unwrap()
+}
+
+/// Entry point of the probes started by `run_probe_in_bwrap`, does nothing in a regular test run.
+#[test]
+fn probe_in_bwrap() {
+  let probe: fn() -> i32 = match env::var(PROBE_VAR).as_deref() {
+    Ok("default") => probe_default,
+    Ok("strict") => probe_strict,
+    _ => return,
+  };
+  process::exit(probe());
+}
+
+#[test]
+fn test_bwrap_default_filter() {
+  if !has_bwrap() {
+    eprintln!("bwrap cannot run on this host, skipping");
+    return;
+  }
+  assert_eq!(run_probe_in_bwrap(SeccompLevel::Default, "default"), Some(0));
+}
+
+#[test]
+fn test_bwrap_strict_filter() {
+  if !has_bwrap() {
+    eprintln!("bwrap cannot run on this host, skipping");
+    return;
+  }
+  assert_eq!(run_probe_in_bwrap(SeccompLevel::Strict, "strict"), Some(0));
+}
+
+#[test]
+fn test_bwrap_userns_filter() {
+  // Skipped without bwrap, or if user namespaces cannot be nested in its sandbox anyway.
+  let unshare = ["unshare", "-U", "true"];
+  if run_bwrap(SeccompLevel::Off, false, &unshare, None).ok() != Some(Some(0)) {
+    eprintln!("bwrap cannot create user namespaces on this host, skipping");
+    return;
+  }
+  assert_eq!(run_bwrap(SeccompLevel::Off, true, &["true"], None).unwrap(), Some(0));
+  assert_ne!(run_bwrap(SeccompLevel::Off, true, &unshare, None).unwrap(), Some(0));
+}